@@ -1,4 +1,9 @@
-use std::{net::Ipv4Addr, path::PathBuf};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
 
 use clap::{Args, Parser, ValueEnum, ValueHint};
 use clap_verbosity_flag::{InfoLevel, Verbosity};
@@ -9,15 +14,43 @@ use strum::EnumIter;
 #[command(name = "bandwhich", version)]
 pub struct Opt {
     #[arg(short, long)]
-    /// The network interface to listen on, eg. eth0
+    /// The network interface to listen on, eg. eth0, or any for all of them at once (Linux only)
     pub interface: Option<String>,
 
+    #[arg(long, value_hint = ValueHint::FilePath, conflicts_with = "interface")]
+    /// Read packets from a pcap or pcapng file instead of a live interface
+    pub pcap: Option<PathBuf>,
+
+    #[arg(long, conflicts_with = "pcap")]
+    /// Also listen in all other network namespaces, eg. those of containers (Linux only)
+    pub all_netns: bool,
+
+    #[arg(long, value_enum, default_value_t, requires = "pcap")]
+    /// How fast to replay packets read from a capture file
+    pub replay_speed: ReplaySpeed,
+
+    #[arg(long, requires = "pcap")]
+    /// An IP address of the machine the capture was recorded on (default: this machine's IPs)
+    pub local_ip: Vec<IpAddr>,
+
+    #[arg(long, value_name = "EXPRESSION")]
+    /// Only capture packets matching this tcpdump-style expression, eg. "tcp port 443"
+    pub filter: Option<String>,
+
+    #[arg(long, value_enum, default_value_t)]
+    /// Account VXLAN, GENEVE, GRE and IP-in-IP traffic to the tunnels or to the flows inside them
+    pub tunnels: TunnelFlows,
+
+    #[arg(long, value_enum, default_value_t)]
+    /// Which bytes of each packet to count
+    pub count_mode: CountMode,
+
     #[arg(short, long)]
     /// Machine friendlier output
     pub raw: bool,
 
     #[arg(short, long)]
-    /// Do not attempt to resolve IPs to their hostnames
+    /// Do not look up the hostnames of IPs; those seen in DNS responses are still shown
     pub no_resolve: bool,
 
     #[arg(short, long)]
@@ -28,6 +61,26 @@ pub struct Opt {
     /// A dns server ip to use instead of the system default
     pub dns_server: Option<Ipv4Addr>,
 
+    #[arg(long, value_hint = ValueHint::DirPath)]
+    /// Write CSV output to one file per table in this directory; implies --format csv
+    pub csv_dir: Option<PathBuf>,
+
+    #[arg(long, value_name = "ADDRESS")]
+    /// Serve Prometheus metrics on this address (eg. 127.0.0.1:9184) instead of showing the TUI
+    pub metrics: Option<SocketAddr>,
+
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    /// Exit after sampling for this long, eg. 30s, and print a summary
+    pub duration: Option<Duration>,
+
+    #[arg(
+        long,
+        value_name = "REFRESHES",
+        value_parser = clap::builder::RangedU64ValueParser::<u64>::new().range(1..)
+    )]
+    /// Exit after this many refreshes, and print a summary
+    pub count: Option<u64>,
+
     #[arg(long, value_hint = ValueHint::FilePath)]
     /// Enable debug logging to a file
     pub log_to: Option<PathBuf>,
@@ -37,9 +90,12 @@ pub struct Opt {
 
     #[command(flatten)]
     pub render_opts: RenderOpts,
+
+    #[command(flatten)]
+    pub filter_opts: FilterOpts,
 }
 
-#[derive(Copy, Clone, Debug, Default, Args)]
+#[derive(Copy, Clone, Debug, Args)]
 pub struct RenderOpts {
     #[arg(short, long)]
     /// Show processes table only
@@ -53,6 +109,10 @@ pub struct RenderOpts {
     /// Show remote addresses table only
     pub addresses: bool,
 
+    #[arg(long)]
+    /// Show containers table only
+    pub containers: bool,
+
     #[arg(short, long, value_enum, default_value_t)]
     /// Choose a specific family of units
     pub unit_family: UnitFamily,
@@ -60,6 +120,149 @@ pub struct RenderOpts {
     #[arg(short, long)]
     /// Show total (cumulative) usages
     pub total_utilization: bool,
+
+    #[arg(long)]
+    /// Show packet rates alongside the byte rates
+    pub packets: bool,
+
+    #[arg(long, value_enum, default_value_t)]
+    /// The format of machine friendlier output; implies --raw if not "text"
+    pub format: OutputFormat,
+
+    #[arg(long, value_name = "SECONDS", default_value_t = 300)]
+    /// How much bandwidth history to keep for the sparklines and charts; 0 disables them
+    pub history: usize,
+
+    #[arg(long, value_enum, default_value_t)]
+    /// How the displayed rates are smoothed over the averaging window
+    pub smoothing: Smoothing,
+
+    #[arg(
+        long,
+        value_name = "SNAPSHOTS",
+        default_value_t = 5,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    /// How many snapshots the displayed rates are averaged over
+    pub window: usize,
+
+    #[arg(long, value_name = "DURATION", default_value = "1s", value_parser = parse_duration)]
+    /// How often to take a snapshot and refresh, eg. 500ms or 2s
+    pub interval: Duration,
+}
+
+// the same defaults as on the command line
+impl Default for RenderOpts {
+    fn default() -> Self {
+        Self {
+            processes: false,
+            connections: false,
+            addresses: false,
+            containers: false,
+            unit_family: UnitFamily::default(),
+            total_utilization: false,
+            packets: false,
+            format: OutputFormat::default(),
+            history: 300,
+            smoothing: Smoothing::default(),
+            window: 5,
+            interval: Duration::from_secs(1),
+        }
+    }
+}
+
+// Which traffic is shown and counted in the totals; each option can be repeated.
+#[derive(Clone, Debug, Default, Args)]
+pub struct FilterOpts {
+    #[arg(long, value_name = "NAME")]
+    /// Only show traffic of processes with this name, eg. nginx
+    pub process: Vec<String>,
+
+    #[arg(long)]
+    /// Only show traffic of the process with this PID
+    pub pid: Vec<u32>,
+
+    #[arg(long)]
+    /// Only show traffic to or from this local or remote port
+    pub port: Vec<u16>,
+
+    #[arg(long, value_name = "ADDRESS")]
+    /// Only show traffic with this remote address or CIDR block, eg. 10.0.0.0/8
+    pub remote: Vec<IpBlock>,
+
+    #[arg(long, value_name = "NAME")]
+    /// Hide traffic of processes with this name
+    pub exclude_process: Vec<String>,
+
+    #[arg(long, value_name = "PID")]
+    /// Hide traffic of the process with this PID
+    pub exclude_pid: Vec<u32>,
+
+    #[arg(long, value_name = "PORT")]
+    /// Hide traffic to or from this local or remote port
+    pub exclude_port: Vec<u16>,
+
+    #[arg(long, value_name = "ADDRESS")]
+    /// Hide traffic with this remote address or CIDR block
+    pub exclude_remote: Vec<IpBlock>,
+}
+
+/// An IP address, or a block of them in CIDR notation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IpBlock {
+    pub address: IpAddr,
+    pub prefix_len: u8,
+}
+
+impl FromStr for IpBlock {
+    type Err = String;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = match arg.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (arg, None),
+        };
+        let address: IpAddr = address
+            .parse()
+            .map_err(|_| format!("invalid IP address {address:?}"))?;
+        let max_prefix_len = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .ok()
+                .filter(|&prefix_len| prefix_len <= max_prefix_len)
+                .ok_or_else(|| format!("invalid prefix length {prefix_len:?}"))?,
+            None => max_prefix_len,
+        };
+        Ok(Self {
+            address,
+            prefix_len,
+        })
+    }
+}
+
+/// Parses a duration such as `500ms`, `2s`, `1.5m` or `1h`; plain numbers are seconds.
+fn parse_duration(arg: &str) -> Result<Duration, String> {
+    let unit_start = arg
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(arg.len());
+    let (number, unit) = arg.split_at(unit_start);
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid duration {arg:?}"))?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return Err(format!("unknown unit {unit:?}, expected ms, s, m or h")),
+    };
+    let duration = Duration::try_from_secs_f64(seconds).map_err(|err| err.to_string())?;
+    if duration.is_zero() {
+        return Err("the duration must be longer than 0".into());
+    }
+    Ok(duration)
 }
 
 // IMPRV: it would be nice if we can `#[cfg_attr(not(build), derive(strum::EnumIter))]` this
@@ -76,3 +279,106 @@ pub enum UnitFamily {
     /// bits, in powers of 10^3
     SiBits,
 }
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    /// one line of text per row
+    Text,
+    /// one JSON object per refresh
+    Json,
+    /// one JSON object per row
+    Ndjson,
+    /// comma-separated values, one line per row
+    Csv,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum Smoothing {
+    /// the latest snapshot only
+    Instantaneous,
+    #[default]
+    /// the mean of the snapshots in the window
+    Sma,
+    /// a mean of the snapshots in the window, weighted towards the newest
+    Ewma,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum ReplaySpeed {
+    #[default]
+    /// at the pace the packets were originally captured
+    Realtime,
+    /// as fast as possible
+    Fast,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum TunnelFlows {
+    #[default]
+    /// to the tunnels, between their endpoints
+    Outer,
+    /// to the flows the tunnels carry, eg. between pods of an overlay network
+    Inner,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum CountMode {
+    /// whole frames, including the link-layer header, like the counters of `ip -s link`
+    Frame,
+    /// IP packets, including their headers
+    Ip,
+    #[default]
+    /// TCP and UDP segments, including their headers
+    Transport,
+    /// the data applications send, without TCP or UDP headers
+    Payload,
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("10.1.2.3", "10.1.2.3", 32)]
+    #[case("10.0.0.0/8", "10.0.0.0", 8)]
+    #[case("fd00::/64", "fd00::", 64)]
+    #[case("::1", "::1", 128)]
+    fn parses_ip_blocks(#[case] arg: &str, #[case] address: &str, #[case] prefix_len: u8) {
+        let expected = IpBlock {
+            address: address.parse().unwrap(),
+            prefix_len,
+        };
+        assert_eq!(arg.parse(), Ok(expected));
+    }
+
+    #[rstest]
+    #[case("example.com")]
+    #[case("10.0.0.0/33")]
+    #[case("fd00::/129")]
+    #[case("10.0.0.0/")]
+    fn rejects_invalid_ip_blocks(#[case] arg: &str) {
+        assert!(arg.parse::<IpBlock>().is_err());
+    }
+
+    #[rstest]
+    #[case("250ms", Duration::from_millis(250))]
+    #[case("2s", Duration::from_secs(2))]
+    #[case("1.5", Duration::from_millis(1500))]
+    #[case("2m", Duration::from_secs(120))]
+    #[case("1h", Duration::from_secs(3600))]
+    fn parses_durations(#[case] arg: &str, #[case] expected: Duration) {
+        assert_eq!(parse_duration(arg), Ok(expected));
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("0s")]
+    #[case("-1s")]
+    #[case("5d")]
+    fn rejects_invalid_durations(#[case] arg: &str) {
+        assert!(parse_duration(arg).is_err());
+    }
+}
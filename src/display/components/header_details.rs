@@ -88,8 +88,17 @@ impl HeaderDetails<'_> {
             bandwidth: self.state.total_bytes_downloaded as f64,
             unit_family,
         };
+        let filter = match &self.state.capture_filter {
+            Some(filter) => format!(" | Filter: {filter}"),
+            None => String::new(),
+        };
+        let averaging = if self.state.cumulative_mode {
+            String::new()
+        } else {
+            format!(" | Averaging: {}", self.state.averaging)
+        };
         let paused = if self.paused { " [PAUSED]" } else { "" };
-        format!("IF: {intrf} | Total {t} (Up / Down): {up} / {down}{paused}")
+        format!("IF: {intrf}{filter} | Total {t} (Up / Down): {up} / {down}{averaging}{paused}")
     }
 
     fn render_elapsed_time(&self, frame: &mut Frame, rect: Rect, elapsed_time: &str, color: Color) {
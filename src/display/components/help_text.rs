@@ -6,9 +6,14 @@ use ratatui::{
     Frame,
 };
 
-pub struct HelpText {
+use crate::display::Search;
+
+pub struct HelpText<'a> {
     pub paused: bool,
     pub show_dns: bool,
+    /// Whether a single process is shown, rather than the tables.
+    pub process_details: bool,
+    pub search: Option<&'a Search>,
 }
 
 const FIRST_WIDTH_BREAKPOINT: u16 = 76;
@@ -19,9 +24,24 @@ const TEXT_WHEN_NOT_PAUSED: &str = " Press <SPACE> to pause.";
 const TEXT_WHEN_DNS_NOT_SHOWN: &str = " (DNS queries hidden).";
 const TEXT_WHEN_DNS_SHOWN: &str = " (DNS queries shown).";
 const TEXT_TAB_TIP: &str = " Use <TAB> to rearrange tables.";
+const TEXT_BACK_TIP: &str = " Press <ESC> to go back.";
+const TEXT_SEARCH_TIP: &str = " <ENTER> to apply, <ESC> to cancel.";
 
-impl HelpText {
+impl HelpText<'_> {
     pub fn render(&self, frame: &mut Frame, rect: Rect) {
+        let search_content = match self.search {
+            Some(search) if search.editing => {
+                let text = format!(" Search: {}_ {TEXT_SEARCH_TIP}", search.query());
+                let paragraph = Paragraph::new(Span::styled(
+                    text,
+                    Style::default().add_modifier(Modifier::BOLD),
+                ));
+                frame.render_widget(paragraph, rect);
+                return;
+            }
+            Some(search) => format!(" Search: {} (<ESC> to clear).", search.query()),
+            None => String::new(),
+        };
         let pause_content = if self.paused {
             TEXT_WHEN_PAUSED
         } else {
@@ -36,14 +56,16 @@ impl HelpText {
             TEXT_WHEN_DNS_NOT_SHOWN
         };
 
-        let tab_text = if rect.width <= SECOND_WIDTH_BREAKPOINT {
+        let tab_text = if self.process_details {
+            TEXT_BACK_TIP
+        } else if rect.width <= SECOND_WIDTH_BREAKPOINT {
             ""
         } else {
             TEXT_TAB_TIP
         };
 
         let text = Span::styled(
-            [pause_content, tab_text, dns_content].concat(),
+            [&search_content, pause_content, tab_text, dns_content].concat(),
             Style::default().add_modifier(Modifier::BOLD),
         );
         let paragraph = Paragraph::new(text).alignment(Alignment::Left);
@@ -3,12 +3,12 @@ use ratatui::{
     Frame,
 };
 
-use crate::display::{HeaderDetails, HelpText, Table};
+use crate::display::{HeaderDetails, HelpText, RateChart, Table, TableSelection};
 
 const FIRST_HEIGHT_BREAKPOINT: u16 = 30;
 const FIRST_WIDTH_BREAKPOINT: u16 = 120;
 
-fn top_app_and_bottom_split(rect: Rect) -> (Rect, Rect, Rect) {
+pub(crate) fn top_app_and_bottom_split(rect: Rect) -> (Rect, Rect, Rect) {
     let parts = ratatui::layout::Layout::default()
         .direction(Direction::Vertical)
         .margin(0)
@@ -26,8 +26,10 @@ fn top_app_and_bottom_split(rect: Rect) -> (Rect, Rect, Rect) {
 
 pub struct Layout<'a> {
     pub header: HeaderDetails<'a>,
+    /// Shown above the tables, when toggled on.
+    pub chart: Option<RateChart<'a>>,
     pub children: Vec<Table>,
-    pub footer: HelpText,
+    pub footer: HelpText<'a>,
 }
 
 impl Layout<'_> {
@@ -99,8 +101,24 @@ impl Layout<'_> {
         }
     }
 
-    pub fn render(&self, frame: &mut Frame, rect: Rect, table_cycle_offset: usize) {
-        let (top, app, bottom) = top_app_and_bottom_split(rect);
+    /// The table in the first slot is the focused one, which `selection` belongs to.
+    pub fn render(
+        &self,
+        frame: &mut Frame,
+        rect: Rect,
+        table_cycle_offset: usize,
+        mut selection: Option<&mut TableSelection>,
+    ) {
+        let (top, mut app, bottom) = top_app_and_bottom_split(rect);
+        if let Some(chart) = &self.chart {
+            let [chart_rect, tables] = ratatui::layout::Layout::vertical([
+                Constraint::Percentage(30),
+                Constraint::Fill(1),
+            ])
+            .areas(app);
+            chart.render(frame, chart_rect);
+            app = tables;
+        }
         let layout_slots = self.build_layout(app);
         for i in 0..layout_slots.len() {
             if let Some(rect) = layout_slots.get(i) {
@@ -108,7 +126,8 @@ impl Layout<'_> {
                     .children
                     .get((i + table_cycle_offset) % self.children.len())
                 {
-                    child.render(frame, *rect);
+                    let selection = if i == 0 { selection.take() } else { None };
+                    child.render(frame, *rect, selection);
                 }
             }
         }
@@ -2,10 +2,16 @@ mod display_bandwidth;
 mod header_details;
 mod help_text;
 mod layout;
+mod process_details;
+mod rate_chart;
+mod search;
 mod table;
 
 pub use display_bandwidth::*;
 pub use header_details::*;
 pub use help_text::*;
 pub use layout::*;
+pub use process_details::*;
+pub use rate_chart::*;
+pub use search::*;
 pub use table::*;
@@ -0,0 +1,121 @@
+use std::{collections::HashMap, net::IpAddr};
+
+use ratatui::{
+    layout::{Constraint, Rect},
+    text::Line,
+    widgets::{Block, Borders, Paragraph},
+    Frame,
+};
+
+use crate::{
+    display::{
+        top_app_and_bottom_split, DisplayBandwidth, HeaderDetails, HelpText, NetworkData,
+        RateChart, Table,
+    },
+    os::ProcessInfo,
+};
+
+const FIRST_WIDTH_BREAKPOINT: u16 = 120;
+
+/// A full-screen view of a single process: its rates, totals and rate history, and what it
+/// talks to.
+pub struct ProcessDetails<'a> {
+    pub header: HeaderDetails<'a>,
+    pub proc_info: &'a ProcessInfo,
+    pub ip_to_host: &'a HashMap<IpAddr, String>,
+    pub footer: HelpText<'a>,
+}
+
+impl ProcessDetails<'_> {
+    pub fn render(&self, frame: &mut Frame, rect: Rect) {
+        let state = self.header.state;
+        let (top, app, bottom) = top_app_and_bottom_split(rect);
+        let [summary, chart, tables] = ratatui::layout::Layout::vertical([
+            Constraint::Length(4),
+            Constraint::Fill(1),
+            Constraint::Percentage(55),
+        ])
+        .areas(app);
+        let halves = [Constraint::Percentage(50), Constraint::Percentage(50)];
+        let [connections, remote_addresses] = if rect.width < FIRST_WIDTH_BREAKPOINT {
+            ratatui::layout::Layout::vertical(halves).areas(tables)
+        } else {
+            ratatui::layout::Layout::horizontal(halves).areas(tables)
+        };
+
+        self.render_summary(frame, summary);
+        RateChart {
+            title: "Rate history",
+            history: state.history.processes.get(self.proc_info),
+            interval: state.averaging.interval,
+            unit_family: state.unit_family,
+        }
+        .render(frame, chart);
+        Table::create_process_connections_table(state, self.ip_to_host, self.proc_info).render(
+            frame,
+            connections,
+            None,
+        );
+        Table::create_process_remote_addresses_table(state, self.ip_to_host, self.proc_info)
+            .render(frame, remote_addresses, None);
+        self.header.render(frame, top);
+        self.footer.render(frame, bottom);
+    }
+
+    fn render_summary(&self, frame: &mut Frame, rect: Rect) {
+        let state = self.header.state;
+        let proc_info = self.proc_info;
+        let bandwidth = |uploaded: u128, downloaded: u128| {
+            let up = DisplayBandwidth {
+                bandwidth: uploaded as f64,
+                unit_family: state.unit_family,
+            };
+            let down = DisplayBandwidth {
+                bandwidth: downloaded as f64,
+                unit_family: state.unit_family,
+            };
+            format!("{up} / {down}")
+        };
+
+        let mut title = format!("Process {} (PID {})", proc_info.name, proc_info.pid);
+        if let Some(container) = &proc_info.container {
+            title += &format!(" in container {container}");
+        }
+        let block = Block::default().title(title).borders(Borders::ALL);
+
+        let current = state
+            .processes
+            .iter()
+            .find(|(other, _)| other == proc_info)
+            .map(|(_, data)| data.clone())
+            .unwrap_or_default();
+        let totals = state
+            .totals
+            .processes
+            .get(proc_info)
+            .cloned()
+            .unwrap_or_default();
+        let NetworkData {
+            total_bytes_uploaded,
+            total_bytes_downloaded,
+            connection_count,
+            ..
+        } = current;
+        let t = if state.cumulative_mode {
+            "Data"
+        } else {
+            "Rate"
+        };
+        let lines = vec![
+            Line::from(format!(
+                "{t} (Up / Down): {}    Connections: {connection_count}",
+                bandwidth(total_bytes_uploaded, total_bytes_downloaded)
+            )),
+            Line::from(format!(
+                "Total since start (Up / Down): {}",
+                bandwidth(totals.total_bytes_uploaded, totals.total_bytes_downloaded)
+            )),
+        ];
+        frame.render_widget(Paragraph::new(lines).block(block), rect);
+    }
+}
@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use itertools::Itertools;
+use ratatui::{
+    layout::Rect,
+    style::{Color, Style},
+    symbols::Marker,
+    widgets::{Axis, Block, Borders, Chart, Dataset, GraphType},
+    Frame,
+};
+
+use crate::display::{display_seconds, BandwidthUnitFamily, DisplayBandwidth, RateHistory};
+
+/// A chart of the upload and download rates over a [`RateHistory`], newest on the right.
+pub struct RateChart<'a> {
+    pub title: &'a str,
+    pub history: Option<&'a RateHistory>,
+    /// How long apart the samples are.
+    pub interval: Duration,
+    pub unit_family: BandwidthUnitFamily,
+}
+
+impl RateChart<'_> {
+    pub fn render(&self, frame: &mut Frame, rect: Rect) {
+        let samples = self
+            .history
+            .map(|history| history.samples().collect_vec())
+            .unwrap_or_default();
+        // the newest sample is at 0, older ones to the left of it
+        let samples_apart = samples.len().max(2) - 1;
+        let span = samples_apart as f64;
+        let offset = samples.len() as f64 - 1.0;
+        let points = |pick: fn(&(u128, u128)) -> u128| {
+            samples
+                .iter()
+                .enumerate()
+                .map(|(i, sample)| (i as f64 - offset, pick(sample) as f64))
+                .collect_vec()
+        };
+        let uploaded = points(|&(up, _)| up);
+        let downloaded = points(|&(_, down)| down);
+        let max = samples
+            .iter()
+            .map(|&(up, down)| up.max(down))
+            .max()
+            .unwrap_or_default()
+            .max(1) as f64;
+        let display = |bandwidth| {
+            DisplayBandwidth {
+                bandwidth,
+                unit_family: self.unit_family,
+            }
+            .to_string()
+        };
+
+        let datasets = vec![
+            Dataset::default()
+                .name("Up")
+                .marker(Marker::Braille)
+                .graph_type(GraphType::Line)
+                .style(Style::default().fg(Color::Green))
+                .data(&uploaded),
+            Dataset::default()
+                .name("Down")
+                .marker(Marker::Braille)
+                .graph_type(GraphType::Line)
+                .style(Style::default().fg(Color::Cyan))
+                .data(&downloaded),
+        ];
+        let chart = Chart::new(datasets)
+            .block(Block::default().title(self.title).borders(Borders::ALL))
+            .x_axis(Axis::default().bounds([-span, 0.0]).labels([
+                format!("-{}", display_seconds(self.interval * samples_apart as u32)),
+                "now".into(),
+            ]))
+            .y_axis(Axis::default().bounds([0.0, max]).labels([
+                "0".into(),
+                display(max / 2.0),
+                display(max),
+            ]));
+        frame.render_widget(chart, rect);
+    }
+}
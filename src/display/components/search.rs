@@ -0,0 +1,197 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+};
+
+use regex::{Regex, RegexBuilder};
+
+use crate::{
+    display::{dns_query_row_id, process_row_id, TableKind, UIState},
+    network::display_ip_or_host,
+    os::ProcessInfo,
+};
+
+/// A search typed after `/`, which narrows every table to the rows matching it.
+///
+/// The query is a case-insensitive regex, or a plain substring if it is not a valid regex.
+#[derive(Clone, Debug, Default)]
+pub struct Search {
+    query: String,
+    pattern: Option<Regex>,
+    /// Whether keys are still typed into the query.
+    pub editing: bool,
+}
+
+impl Search {
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.query.push(c);
+        self.compile();
+    }
+
+    pub fn pop(&mut self) {
+        self.query.pop();
+        self.compile();
+    }
+
+    fn compile(&mut self) {
+        let build = |pattern: &str| RegexBuilder::new(pattern).case_insensitive(true).build();
+        self.pattern = build(&self.query)
+            .or_else(|_| build(&regex::escape(&self.query)))
+            .ok();
+    }
+
+    fn is_match(&self, text: &str) -> bool {
+        self.pattern
+            .as_ref()
+            .is_none_or(|pattern| pattern.is_match(text))
+    }
+
+    /// The ids of the rows to keep in each table, see [`Table::retain_rows`].
+    ///
+    /// A connection matches by its process name, its server name, its remote address or hostname,
+    /// or either of its ports; the other tables keep the rows which any matching connection adds
+    /// up to. A DNS query matches by its name or the name of its process.
+    ///
+    /// [`Table::retain_rows`]: crate::display::Table::retain_rows
+    pub fn row_ids(
+        &self,
+        state: &UIState,
+        ip_to_host: &HashMap<IpAddr, String>,
+    ) -> HashMap<TableKind, HashSet<String>> {
+        let mut row_ids: HashMap<TableKind, HashSet<String>> = HashMap::new();
+        let mut keep = |kind, row_id| {
+            row_ids.entry(kind).or_default().insert(row_id);
+        };
+
+        for (connection, data) in &state.connections {
+            let remote_ip = connection.remote_socket.ip;
+            let is_match = [
+                data.process_name.clone(),
+                data.server_name.clone().unwrap_or_default(),
+                display_ip_or_host(remote_ip, ip_to_host),
+                remote_ip.to_string(),
+                connection.local_socket.port.to_string(),
+                connection.remote_socket.port.to_string(),
+            ]
+            .iter()
+            .any(|text| self.is_match(text));
+            if is_match {
+                keep(TableKind::Connections, format!("{connection:?}"));
+                keep(TableKind::RemoteAddresses, remote_ip.to_string());
+                let proc_info = ProcessInfo::new(&data.process_name, data.pid);
+                keep(TableKind::Processes, process_row_id(&proc_info));
+            }
+        }
+        for (proc_info, _) in &state.processes {
+            if self.is_match(&proc_info.name) {
+                keep(TableKind::Processes, process_row_id(proc_info));
+            }
+        }
+        for (query, _) in &state.dns_queries {
+            if self.is_match(&query.name) || self.is_match(&query.proc_info.name) {
+                keep(TableKind::DnsQueries, dns_query_row_id(query));
+            }
+        }
+        for (proc_info, _) in &state.processes {
+            let Some(container) = &proc_info.container else {
+                continue;
+            };
+            let process_matches = row_ids
+                .get(&TableKind::Processes)
+                .is_some_and(|ids| ids.contains(&process_row_id(proc_info)));
+            if process_matches || self.is_match(&container.to_string()) {
+                row_ids
+                    .entry(TableKind::Containers)
+                    .or_default()
+                    .insert(container.id.clone());
+            }
+        }
+        row_ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    use crate::{
+        display::ConnectionData,
+        network::{Connection, Protocol},
+    };
+
+    fn search(query: &str) -> Search {
+        let mut search = Search::default();
+        query.chars().for_each(|c| search.push(c));
+        search
+    }
+
+    #[rstest]
+    #[case("curl", "curl", true)]
+    #[case("CURL", "curl", true)]
+    #[case("^cu.l$", "curl", true)]
+    #[case("^url", "curl", false)]
+    // not a valid regex, so a substring
+    #[case("a[", "a[b", true)]
+    #[case("", "anything", true)]
+    fn matches_regexes_and_substrings(
+        #[case] query: &str,
+        #[case] text: &str,
+        #[case] expected: bool,
+    ) {
+        assert_eq!(search(query).is_match(text), expected);
+    }
+
+    #[test]
+    fn narrows_every_table() {
+        let connection = |remote: &str, local_port| {
+            Connection::new(
+                remote.parse().unwrap(),
+                "10.0.0.2".parse().unwrap(),
+                local_port,
+                Protocol::Tcp,
+            )
+        };
+        let data = |process_name: &str, pid| ConnectionData {
+            process_name: process_name.into(),
+            pid,
+            ..Default::default()
+        };
+        let to_dns = connection("8.8.8.8:53", 1000);
+        let mut state = UIState::default();
+        state.connections = vec![
+            (connection("1.1.1.1:443", 1001), data("curl", 7)),
+            (to_dns, data("resolved", 8)),
+        ];
+        state.processes = vec![
+            (ProcessInfo::new("curl", 7), Default::default()),
+            (ProcessInfo::new("resolved", 8), Default::default()),
+        ];
+        let ip_to_host = HashMap::from([("8.8.8.8".parse().unwrap(), "dns.google".into())]);
+
+        let row_ids = search("google").row_ids(&state, &ip_to_host);
+        assert_eq!(
+            row_ids,
+            HashMap::from([
+                (
+                    TableKind::Connections,
+                    HashSet::from([format!("{to_dns:?}")])
+                ),
+                (
+                    TableKind::RemoteAddresses,
+                    HashSet::from(["8.8.8.8".into()])
+                ),
+                (TableKind::Processes, HashSet::from(["resolved:8".into()])),
+            ])
+        );
+        let row_ids = search("^443$").row_ids(&state, &ip_to_host);
+        assert_eq!(
+            row_ids[&TableKind::Processes],
+            HashSet::from(["curl:7".into()])
+        );
+    }
+}
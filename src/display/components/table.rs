@@ -1,18 +1,28 @@
-use std::{collections::HashMap, net::IpAddr, ops::Index, rc::Rc};
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    ops::Index,
+    rc::Rc,
+};
 
 use derive_more::Debug;
 use itertools::Itertools;
 use ratatui::{
     layout::{Constraint, Rect},
-    style::{Color, Style},
-    widgets::{Block, Borders, Row},
+    style::{Color, Modifier, Style},
+    symbols::bar,
+    widgets::{Block, Borders, Row, TableState},
     Frame,
 };
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 use crate::{
-    display::{Bandwidth, BandwidthUnitFamily, DisplayBandwidth, UIState},
-    network::{display_connection_string, display_ip_or_host},
+    display::{
+        Bandwidth, BandwidthUnitFamily, ConnectionData, DisplayBandwidth, DnsQuery, NetworkData,
+        RateHistory, SortOrder, UIState,
+    },
+    network::{display_connection_string, display_remote_address, Connection},
+    os::ProcessInfo,
 };
 
 /// The displayed layout choice of a table.
@@ -30,6 +40,10 @@ pub enum DisplayLayout {
     C3([u16; 3]),
     /// Show 4 columns.
     C4([u16; 4]),
+    /// Show 5 columns.
+    C5([u16; 5]),
+    /// Show 6 columns.
+    C6([u16; 6]),
 }
 
 impl Index<usize> for DisplayLayout {
@@ -40,6 +54,8 @@ impl Index<usize> for DisplayLayout {
             Self::C2(arr) => &arr[i],
             Self::C3(arr) => &arr[i],
             Self::C4(arr) => &arr[i],
+            Self::C5(arr) => &arr[i],
+            Self::C6(arr) => &arr[i],
         }
     }
 }
@@ -51,6 +67,8 @@ impl DisplayLayout {
             Self::C2(_) => 2,
             Self::C3(_) => 3,
             Self::C4(_) => 4,
+            Self::C5(_) => 5,
+            Self::C6(_) => 6,
         }
     }
 
@@ -60,6 +78,8 @@ impl DisplayLayout {
             Self::C2(ws) => ws.iter(),
             Self::C3(ws) => ws.iter(),
             Self::C4(ws) => ws.iter(),
+            Self::C5(ws) => ws.iter(),
+            Self::C6(ws) => ws.iter(),
         }
     }
 
@@ -108,6 +128,34 @@ impl DisplayLayout {
                     w3_new,
                 ])
             }
+            Self::C5([_w0, w1, w2, w3, w4]) => {
+                let w1_new = (w1 as f64 * m).trunc() as u16;
+                let w2_new = (w2 as f64 * m).trunc() as u16;
+                let w3_new = (w3 as f64 * m).trunc() as u16;
+                let w4_new = (w4 as f64 * m).trunc() as u16;
+                Self::C5([
+                    available_without_spacers - w1_new - w2_new - w3_new - w4_new,
+                    w1_new,
+                    w2_new,
+                    w3_new,
+                    w4_new,
+                ])
+            }
+            Self::C6([_w0, w1, w2, w3, w4, w5]) => {
+                let w1_new = (w1 as f64 * m).trunc() as u16;
+                let w2_new = (w2 as f64 * m).trunc() as u16;
+                let w3_new = (w3 as f64 * m).trunc() as u16;
+                let w4_new = (w4 as f64 * m).trunc() as u16;
+                let w5_new = (w5 as f64 * m).trunc() as u16;
+                Self::C6([
+                    available_without_spacers - w1_new - w2_new - w3_new - w4_new - w5_new,
+                    w1_new,
+                    w2_new,
+                    w3_new,
+                    w4_new,
+                    w5_new,
+                ])
+            }
         };
 
         (computed, spacer)
@@ -120,16 +168,12 @@ impl DisplayLayout {
 /// then new variants should be added.
 #[derive(Clone, Debug)]
 enum TableData {
-    /// A table with 3 columns.
-    C3(NColsTableData<3>),
     /// A table with 4 columns.
     C4(NColsTableData<4>),
-}
-
-impl From<NColsTableData<3>> for TableData {
-    fn from(data: NColsTableData<3>) -> Self {
-        Self::C3(data)
-    }
+    /// A table with 5 columns.
+    C5(NColsTableData<5>),
+    /// A table with 6 columns.
+    C6(NColsTableData<6>),
 }
 
 impl From<NColsTableData<4>> for TableData {
@@ -138,25 +182,50 @@ impl From<NColsTableData<4>> for TableData {
     }
 }
 
+impl From<NColsTableData<5>> for TableData {
+    fn from(data: NColsTableData<5>) -> Self {
+        Self::C5(data)
+    }
+}
+
+impl From<NColsTableData<6>> for TableData {
+    fn from(data: NColsTableData<6>) -> Self {
+        Self::C6(data)
+    }
+}
+
 impl TableData {
     fn column_names(&self) -> &[&str] {
         match self {
-            Self::C3(inner) => &inner.column_names,
             Self::C4(inner) => &inner.column_names,
+            Self::C5(inner) => &inner.column_names,
+            Self::C6(inner) => &inner.column_names,
         }
     }
 
     fn rows(&self) -> Vec<&[String]> {
         match self {
-            Self::C3(inner) => inner.rows.iter().map(|r| r.as_slice()).collect(),
             Self::C4(inner) => inner.rows.iter().map(|r| r.as_slice()).collect(),
+            Self::C5(inner) => inner.rows.iter().map(|r| r.as_slice()).collect(),
+            Self::C6(inner) => inner.rows.iter().map(|r| r.as_slice()).collect(),
+        }
+    }
+
+    /// Keeps the rows for which `keep` holds, given in the same order as the rows.
+    fn retain_rows(&mut self, keep: &[bool]) {
+        let mut keep = keep.iter();
+        match self {
+            Self::C4(inner) => inner.rows.retain(|_| *keep.next().unwrap()),
+            Self::C5(inner) => inner.rows.retain(|_| *keep.next().unwrap()),
+            Self::C6(inner) => inner.rows.retain(|_| *keep.next().unwrap()),
         }
     }
 
     fn column_selector(&self) -> &dyn Fn(&DisplayLayout) -> Vec<usize> {
         match self {
-            Self::C3(inner) => inner.column_selector.as_ref(),
             Self::C4(inner) => inner.column_selector.as_ref(),
+            Self::C5(inner) => inner.column_selector.as_ref(),
+            Self::C6(inner) => inner.column_selector.as_ref(),
         }
     }
 }
@@ -182,10 +251,65 @@ struct NColsTableData<const C: usize> {
 /// Clippy wanted me to write this. 💢
 type ColumnSelectorFn = dyn Fn(&DisplayLayout) -> Vec<usize>;
 
+/// Which data a table shows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TableKind {
+    Processes,
+    RemoteAddresses,
+    Connections,
+    Containers,
+    DnsQueries,
+}
+
+/// How the selected row of a table moves.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RowMovement {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    First,
+    Last,
+}
+
+/// The row the user selected in a table.
+///
+/// The selection follows its row as the table is reordered between refreshes.
+#[derive(Clone, Debug, Default)]
+pub struct TableSelection {
+    /// Identifies the selected row, see [`Table::row_ids`].
+    row_id: Option<String>,
+    state: TableState,
+    /// How many rows fit in the table when it was last drawn.
+    page_size: usize,
+}
+
+impl TableSelection {
+    pub fn row_id(&self) -> Option<&str> {
+        self.row_id.as_deref()
+    }
+}
+
+/// Identifies a row of the processes table.
+pub fn process_row_id(proc_info: &ProcessInfo) -> String {
+    format!("{}:{}", proc_info.name, proc_info.pid)
+}
+
+/// Identifies a row of the DNS queries table.
+pub fn dns_query_row_id(query: &DnsQuery) -> String {
+    format!(
+        "{} {} {}",
+        query.name,
+        query.record_type,
+        process_row_id(&query.proc_info)
+    )
+}
+
 /// A table displayed by bandwhich.
 #[derive(Clone, Debug)]
 pub struct Table {
-    title: &'static str,
+    kind: TableKind,
+    title: String,
     /// A layout mapping between minimum available width and the width of each column.
     ///
     /// Note that the width of each column here is the "desired minimum width".
@@ -198,19 +322,46 @@ pub struct Table {
     /// - If `Wt < Wd`, columns will proportionally shrink.
     width_cutoffs: Vec<(u16, DisplayLayout)>,
     data: TableData,
+    /// Identifies each row across refreshes, in the same order as the rows.
+    row_ids: Vec<String>,
+    /// The column of sparklines, if any, which are cut on the left to show the newest samples.
+    sparkline_column: Option<usize>,
 }
 
 impl Table {
     pub fn create_connections_table(state: &UIState, ip_to_host: &HashMap<IpAddr, String>) -> Self {
+        let title = sorted_title("Utilization by connection", state.sort_order);
+        Self::connections_table(title, &state.connections, state, ip_to_host)
+    }
+
+    /// Like [`Self::create_connections_table`], with only the connections of one process.
+    pub fn create_process_connections_table(
+        state: &UIState,
+        ip_to_host: &HashMap<IpAddr, String>,
+        proc_info: &ProcessInfo,
+    ) -> Self {
+        let connections = state.process_connections(proc_info).cloned().collect_vec();
+        Self::connections_table("Connections".into(), &connections, state, ip_to_host)
+    }
+
+    fn connections_table(
+        title: String,
+        connections: &[(Connection, ConnectionData)],
+        state: &UIState,
+        ip_to_host: &HashMap<IpAddr, String>,
+    ) -> Self {
         use DisplayLayout as D;
 
-        let title = "Utilization by connection";
-        let width_cutoffs = vec![
+        let kind = TableKind::Connections;
+        let mut width_cutoffs = vec![
             (0, D::C2([32, 18])),
             (80, D::C3([36, 12, 18])),
             (100, D::C3([54, 18, 22])),
             (120, D::C3([72, 24, 22])),
         ];
+        if state.show_packets {
+            widen(&mut width_cutoffs, 120, D::C4([56, 24, 22, 16]));
+        }
 
         let column_names = [
             "Connection",
@@ -220,9 +371,9 @@ impl Table {
             } else {
                 "Rate (Up / Down)"
             },
+            packets_column_name(state.cumulative_mode),
         ];
-        let rows = state
-            .connections
+        let rows = connections
             .iter()
             .map(|(connection, connection_data)| {
                 [
@@ -230,6 +381,7 @@ impl Table {
                         connection,
                         ip_to_host,
                         &connection_data.interface_name,
+                        connection_data.server_name.as_deref(),
                     ),
                     connection_data.process_name.to_string(),
                     display_upload_and_download(
@@ -237,16 +389,23 @@ impl Table {
                         state.unit_family,
                         state.cumulative_mode,
                     ),
+                    display_packets(connection_data),
                 ]
             })
             .collect();
+        let row_ids = connections
+            .iter()
+            .map(|(connection, _)| format!("{connection:?}"))
+            .collect();
         let column_selector = Rc::new(|layout: &D| match layout {
             D::C2(_) => vec![0, 2],
             D::C3(_) => vec![0, 1, 2],
-            D::C4(_) => unreachable!(),
+            D::C4(_) => vec![0, 1, 2, 3],
+            D::C5(_) | D::C6(_) => unreachable!(),
         });
 
         Table {
+            kind,
             title,
             width_cutoffs,
             data: NColsTableData {
@@ -255,19 +414,32 @@ impl Table {
                 column_selector,
             }
             .into(),
+            row_ids,
+            sparkline_column: None,
         }
     }
 
     pub fn create_processes_table(state: &UIState) -> Self {
         use DisplayLayout as D;
 
-        let title = "Utilization by process name";
-        let width_cutoffs = vec![
+        let kind = TableKind::Processes;
+        let title = sorted_title("Utilization by process name", state.sort_order);
+        let mut width_cutoffs = vec![
             (0, D::C2([16, 18])),
             (50, D::C3([16, 12, 20])),
             (60, D::C3([24, 12, 20])),
             (80, D::C4([28, 12, 12, 24])),
         ];
+        let show_history = state.history_length > 0;
+        match (show_history, state.show_packets) {
+            (true, true) => {
+                widen(&mut width_cutoffs, 90, D::C5([24, 8, 12, 24, 16]));
+                widen(&mut width_cutoffs, 120, D::C6([24, 8, 12, 24, 16, 16]));
+            }
+            (true, false) => widen(&mut width_cutoffs, 90, D::C5([24, 8, 12, 24, 16])),
+            (false, true) => widen(&mut width_cutoffs, 100, D::C5([24, 8, 12, 24, 16])),
+            (false, false) => {}
+        }
 
         let column_names = [
             "Process",
@@ -278,6 +450,8 @@ impl Table {
             } else {
                 "Rate (Up / Down)"
             },
+            packets_column_name(state.cumulative_mode),
+            "History",
         ];
         let rows = state
             .processes
@@ -292,16 +466,150 @@ impl Table {
                         state.unit_family,
                         state.cumulative_mode,
                     ),
+                    display_packets(data_for_process),
+                    sparkline(state.history.processes.get(proc_info)),
                 ]
             })
             .collect();
-        let column_selector = Rc::new(|layout: &D| match layout {
+        let row_ids = state
+            .processes
+            .iter()
+            .map(|(proc_info, _)| process_row_id(proc_info))
+            .collect();
+        // five columns are the history, unless the packets are shown without it
+        let column_selector = Rc::new(move |layout: &D| match layout {
             D::C2(_) => vec![0, 3],
             D::C3(_) => vec![0, 2, 3],
             D::C4(_) => vec![0, 1, 2, 3],
+            D::C5(_) if show_history => vec![0, 1, 2, 3, 5],
+            D::C5(_) => vec![0, 1, 2, 3, 4],
+            D::C6(_) => vec![0, 1, 2, 3, 4, 5],
+        });
+
+        Table {
+            kind,
+            title,
+            width_cutoffs,
+            data: NColsTableData {
+                column_names,
+                rows,
+                column_selector,
+            }
+            .into(),
+            row_ids,
+            sparkline_column: Some(5),
+        }
+    }
+
+    pub fn create_containers_table(state: &UIState) -> Self {
+        use DisplayLayout as D;
+
+        let kind = TableKind::Containers;
+        let title = sorted_title("Utilization by container", state.sort_order);
+        let mut width_cutoffs = vec![
+            (0, D::C2([16, 18])),
+            (60, D::C3([24, 12, 20])),
+            (100, D::C3([54, 16, 24])),
+        ];
+        if state.show_packets {
+            widen(&mut width_cutoffs, 100, D::C4([40, 12, 24, 16]));
+        }
+
+        let column_names = [
+            "Container",
+            "Connections",
+            if state.cumulative_mode {
+                "Data (Up / Down)"
+            } else {
+                "Rate (Up / Down)"
+            },
+            packets_column_name(state.cumulative_mode),
+        ];
+        let rows = state
+            .containers
+            .iter()
+            .map(|(container, data_for_container)| {
+                [
+                    container.to_string(),
+                    data_for_container.connection_count.to_string(),
+                    display_upload_and_download(
+                        data_for_container,
+                        state.unit_family,
+                        state.cumulative_mode,
+                    ),
+                    display_packets(data_for_container),
+                ]
+            })
+            .collect();
+        let row_ids = state
+            .containers
+            .iter()
+            .map(|(container, _)| container.id.clone())
+            .collect();
+        let column_selector = Rc::new(|layout: &D| match layout {
+            D::C2(_) => vec![0, 2],
+            D::C3(_) => vec![0, 1, 2],
+            D::C4(_) => vec![0, 1, 2, 3],
+            D::C5(_) | D::C6(_) => unreachable!(),
+        });
+
+        Table {
+            kind,
+            title,
+            width_cutoffs,
+            data: NColsTableData {
+                column_names,
+                rows,
+                column_selector,
+            }
+            .into(),
+            row_ids,
+            sparkline_column: None,
+        }
+    }
+
+    /// The DNS questions asked since start, with `--show-dns`, the most frequent first.
+    pub fn create_dns_queries_table(state: &UIState) -> Self {
+        use DisplayLayout as D;
+
+        let kind = TableKind::DnsQueries;
+        let title = "DNS queries".to_owned();
+        let width_cutoffs = vec![
+            (0, D::C2([24, 12])),
+            (60, D::C3([30, 16, 12])),
+            (80, D::C4([36, 16, 10, 12])),
+            (100, D::C5([44, 18, 10, 10, 12])),
+        ];
+
+        let column_names = ["Query", "Process", "Response", "Queries", "Rate"];
+        let rows = state
+            .dns_queries
+            .iter()
+            .map(|(query, data)| {
+                [
+                    format!("{} ({})", query.name, query.record_type),
+                    query.proc_info.name.clone(),
+                    data.response_code.clone().unwrap_or_default(),
+                    data.total_queries.to_string(),
+                    format!("{:.2}/s", data.rate),
+                ]
+            })
+            .collect();
+        let row_ids = state
+            .dns_queries
+            .iter()
+            .map(|(query, _)| dns_query_row_id(query))
+            .collect();
+        let column_selector = Rc::new(|layout: &D| match layout {
+            D::C2(_) => vec![0, 4],
+            D::C3(_) => vec![0, 1, 4],
+            D::C4(_) => vec![0, 1, 2, 4],
+            D::C5(_) => vec![0, 1, 2, 3, 4],
+            D::C6(_) => unreachable!(),
         });
 
         Table {
+            kind,
             title,
             width_cutoffs,
             data: NColsTableData {
@@ -310,22 +618,69 @@ impl Table {
                 column_selector,
             }
             .into(),
+            row_ids,
+            sparkline_column: None,
         }
     }
 
     pub fn create_remote_addresses_table(
         state: &UIState,
         ip_to_host: &HashMap<IpAddr, String>,
+    ) -> Self {
+        let title = sorted_title("Utilization by remote address", state.sort_order);
+        Self::remote_addresses_table(
+            title,
+            &state.remote_addresses,
+            Some(&state.history.remote_addresses),
+            state,
+            ip_to_host,
+        )
+    }
+
+    /// Like [`Self::create_remote_addresses_table`], with only the addresses one process talks to.
+    ///
+    /// The history of an address is not broken down by process, so it is left out.
+    pub fn create_process_remote_addresses_table(
+        state: &UIState,
+        ip_to_host: &HashMap<IpAddr, String>,
+        proc_info: &ProcessInfo,
+    ) -> Self {
+        let remote_addresses = state.process_remote_addresses(proc_info);
+        Self::remote_addresses_table(
+            "Remote addresses".into(),
+            &remote_addresses,
+            None,
+            state,
+            ip_to_host,
+        )
+    }
+
+    fn remote_addresses_table(
+        title: String,
+        remote_addresses: &[(IpAddr, NetworkData)],
+        history: Option<&HashMap<IpAddr, RateHistory>>,
+        state: &UIState,
+        ip_to_host: &HashMap<IpAddr, String>,
     ) -> Self {
         use DisplayLayout as D;
 
-        let title = "Utilization by remote address";
-        let width_cutoffs = vec![
+        let kind = TableKind::RemoteAddresses;
+        let mut width_cutoffs = vec![
             (0, D::C2([16, 16])),
             (40, D::C2([20, 16])),
             (60, D::C3([24, 10, 20])),
             (100, D::C3([54, 16, 24])),
         ];
+        let show_history = history.is_some() && state.history_length > 0;
+        match (show_history, state.show_packets) {
+            (true, true) => {
+                widen(&mut width_cutoffs, 96, D::C4([40, 12, 24, 16]));
+                widen(&mut width_cutoffs, 120, D::C5([40, 12, 24, 16, 16]));
+            }
+            (true, false) => widen(&mut width_cutoffs, 96, D::C4([40, 12, 24, 16])),
+            (false, true) => widen(&mut width_cutoffs, 100, D::C4([40, 12, 24, 16])),
+            (false, false) => {}
+        }
 
         let column_names = [
             "Remote Address",
@@ -335,12 +690,15 @@ impl Table {
             } else {
                 "Rate (Up / Down)"
             },
+            packets_column_name(state.cumulative_mode),
+            "History",
         ];
-        let rows = state
-            .remote_addresses
+        // an address may serve many sites, so all those asked for are listed
+        let server_names = state.remote_address_server_names();
+        let rows = remote_addresses
             .iter()
-            .map(|(remote_address, data_for_remote_address)| {
-                let remote_address = display_ip_or_host(*remote_address, ip_to_host);
+            .map(|(ip, data_for_remote_address)| {
+                let remote_address = display_remote_address(*ip, &server_names, ip_to_host);
                 [
                     remote_address,
                     data_for_remote_address.connection_count.to_string(),
@@ -349,16 +707,27 @@ impl Table {
                         state.unit_family,
                         state.cumulative_mode,
                     ),
+                    display_packets(data_for_remote_address),
+                    sparkline(history.and_then(|history| history.get(ip))),
                 ]
             })
             .collect();
-        let column_selector = Rc::new(|layout: &D| match layout {
+        let row_ids = remote_addresses
+            .iter()
+            .map(|(remote_address, _)| remote_address.to_string())
+            .collect();
+        // four columns are the history, unless the packets are shown without it
+        let column_selector = Rc::new(move |layout: &D| match layout {
             D::C2(_) => vec![0, 2],
             D::C3(_) => vec![0, 1, 2],
-            D::C4(_) => unreachable!(),
+            D::C4(_) if show_history => vec![0, 1, 2, 4],
+            D::C4(_) => vec![0, 1, 2, 3],
+            D::C5(_) => vec![0, 1, 2, 3, 4],
+            D::C6(_) => unreachable!(),
         });
 
         Table {
+            kind,
             title,
             width_cutoffs,
             data: NColsTableData {
@@ -367,28 +736,87 @@ impl Table {
                 column_selector,
             }
             .into(),
+            row_ids,
+            sparkline_column: Some(4),
         }
     }
 
-    /// See [`Table`] for layout rules.
-    pub fn render(&self, frame: &mut Frame, rect: Rect) {
-        let (computed_layout, spacer_width) = {
-            // pick the largest possible layout, constrained by the available width
-            let &(_, layout) = self
-                .width_cutoffs
-                .iter()
-                .rev()
-                .find(|(cutoff, _)| rect.width > *cutoff)
-                .unwrap(); // all cutoff tables have a 0-width entry
-            layout.compute_actual_widths(rect.width)
+    pub fn kind(&self) -> TableKind {
+        self.kind
+    }
+
+    /// Keeps only the rows whose id is in `row_ids`.
+    pub fn retain_rows(&mut self, row_ids: &HashSet<String>) {
+        let keep = self
+            .row_ids
+            .iter()
+            .map(|row_id| row_ids.contains(row_id))
+            .collect_vec();
+        self.data.retain_rows(&keep);
+        self.row_ids.retain(|row_id| row_ids.contains(row_id));
+    }
+
+    /// Moves the selection to another row. Without a selection, the first or last row is selected.
+    pub fn move_selection(&self, selection: &mut TableSelection, movement: RowMovement) {
+        use RowMovement as M;
+
+        let Some(last) = self.row_ids.len().checked_sub(1) else {
+            return;
         };
+        let page = selection.page_size.max(1);
+        let index = match (self.selected_index(selection), movement) {
+            (_, M::First) => 0,
+            (_, M::Last) => last,
+            (None, M::Up | M::PageUp) => last,
+            (None, M::Down | M::PageDown) => 0,
+            (Some(i), M::Up) => i.saturating_sub(1),
+            (Some(i), M::Down) => i + 1,
+            (Some(i), M::PageUp) => i.saturating_sub(page),
+            (Some(i), M::PageDown) => i + page,
+        }
+        .min(last);
+        selection.row_id = Some(self.row_ids[index].clone());
+        selection.state.select(Some(index));
+    }
 
-        let columns_to_show = self.data.column_selector()(&computed_layout);
-        let column_names: Vec<_> = columns_to_show
+    /// Finds the selected row, or the one now in its place if it is gone.
+    fn selected_index(&self, selection: &TableSelection) -> Option<usize> {
+        let last = self.row_ids.len().checked_sub(1)?;
+        let row_id = selection.row_id.as_ref()?;
+        self.row_ids
             .iter()
-            .copied()
+            .position(|id| id == row_id)
+            .or_else(|| Some(selection.state.selected()?.min(last)))
+    }
+
+    /// Picks the largest possible layout, constrained by the available width.
+    fn layout(&self, width: u16) -> DisplayLayout {
+        let &(_, layout) = self
+            .width_cutoffs
+            .iter()
+            .rev()
+            .find(|(cutoff, _)| width > *cutoff)
+            .unwrap(); // all cutoff tables have a 0-width entry
+        layout
+    }
+
+    /// The names of the columns shown in `layout`.
+    fn shown_column_names(&self, layout: &DisplayLayout) -> Vec<&str> {
+        self.data.column_selector()(layout)
+            .into_iter()
             .map(|i| self.data.column_names()[i])
-            .collect();
+            .collect()
+    }
+
+    /// See [`Table`] for layout rules.
+    ///
+    /// The selected row, if any, is highlighted and scrolled into view.
+    pub fn render(&self, frame: &mut Frame, rect: Rect, selection: Option<&mut TableSelection>) {
+        let (computed_layout, spacer_width) =
+            self.layout(rect.width).compute_actual_widths(rect.width);
+
+        let columns_to_show = self.data.column_selector()(&computed_layout);
+        let column_names = self.shown_column_names(&computed_layout);
 
         // text needs to react to column widths
         let tui_rows_iter = self
@@ -396,11 +824,20 @@ impl Table {
             .rows()
             .into_iter()
             .map(|row_data| {
-                let shown_columns_data = columns_to_show.iter().copied().map(|i| &row_data[i]);
+                let shown_columns_data = columns_to_show
+                    .iter()
+                    .copied()
+                    .map(|i| (Some(i) == self.sparkline_column, &row_data[i]));
                 let column_widths = computed_layout.iter().copied();
                 shown_columns_data
                     .zip_eq(column_widths)
-                    .map(|(text, width)| truncate_middle(text, width))
+                    .map(|((is_sparkline, text), width)| {
+                        if is_sparkline {
+                            truncate_start(text, width)
+                        } else {
+                            truncate_middle(text, width)
+                        }
+                    })
                     .collect::<Vec<_>>()
             })
             .map(Row::new);
@@ -412,12 +849,45 @@ impl Table {
             .collect();
 
         let table = ratatui::widgets::Table::new(tui_rows_iter, widths_constraints)
-            .block(Block::default().title(self.title).borders(Borders::ALL))
+            .block(
+                Block::default()
+                    .title(self.title.as_str())
+                    .borders(Borders::ALL),
+            )
             .header(Row::new(column_names).style(Style::default().fg(Color::Yellow)))
+            .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
             .flex(ratatui::layout::Flex::Legacy)
             .column_spacing(spacer_width);
-        frame.render_widget(table, rect);
+
+        match selection {
+            Some(selection) => {
+                // less the borders and the header
+                selection.page_size = rect.height.saturating_sub(3).into();
+                let index = self.selected_index(selection);
+                selection.row_id = index.map(|i| self.row_ids[i].clone());
+                selection.state.select(index);
+                frame.render_stateful_widget(table, rect, &mut selection.state);
+            }
+            None => frame.render_widget(table, rect),
+        }
+    }
+}
+
+/// Notes the order of the rows in the title, unless it is the default.
+/// Shows `layout` from `cutoff` up, in place of any narrower layout at or above that width.
+///
+/// Keeps the cutoffs increasing, so [`Table::layout`] picks each of them at some width.
+fn widen(width_cutoffs: &mut Vec<(u16, DisplayLayout)>, cutoff: u16, layout: DisplayLayout) {
+    width_cutoffs.retain(|&(existing, _)| existing < cutoff);
+    width_cutoffs.push((cutoff, layout));
+}
+
+fn sorted_title(title: &str, sort_order: SortOrder) -> String {
+    if sort_order == SortOrder::default() {
+        return title.to_owned();
     }
+    let direction = if sort_order.ascending { "▲" } else { "▼" };
+    format!("{title} (by {} {direction})", sort_order.column)
 }
 
 fn display_upload_and_download(
@@ -436,6 +906,48 @@ fn display_upload_and_download(
     format!("{up} / {down}")
 }
 
+/// The header of the packets column, shown with `--packets`.
+fn packets_column_name(cumulative: bool) -> &'static str {
+    if cumulative {
+        "Packets (Up / Down)"
+    } else {
+        "Packets/s (Up / Down)"
+    }
+}
+
+fn display_packets(bandwidth: &impl Bandwidth) -> String {
+    format!(
+        "{} / {}",
+        bandwidth.get_total_packets_uploaded(),
+        bandwidth.get_total_packets_downloaded()
+    )
+}
+
+/// Draws the total traffic of each sample with a bar of 1/8 steps, scaled to the peak.
+fn sparkline(history: Option<&RateHistory>) -> String {
+    let Some(history) = history else {
+        return String::new();
+    };
+    let levels = [
+        bar::NINE_LEVELS.empty,
+        bar::NINE_LEVELS.one_eighth,
+        bar::NINE_LEVELS.one_quarter,
+        bar::NINE_LEVELS.three_eighths,
+        bar::NINE_LEVELS.half,
+        bar::NINE_LEVELS.five_eighths,
+        bar::NINE_LEVELS.three_quarters,
+        bar::NINE_LEVELS.seven_eighths,
+        bar::NINE_LEVELS.full,
+    ];
+    let totals = history.samples().map(|(up, down)| up + down).collect_vec();
+    let peak = totals.iter().copied().max().unwrap_or_default().max(1);
+    totals
+        .into_iter()
+        // any traffic at all gets at least the lowest bar
+        .map(|total| levels[(total * 8).div_ceil(peak) as usize])
+        .collect()
+}
+
 fn collect_to_unicode_width<T>(iter: impl Iterator<Item = char>, width: usize) -> T
 where
     T: FromIterator<char>,
@@ -468,3 +980,148 @@ fn truncate_middle(row: &str, max_len: u16) -> String {
         row.to_string()
     }
 }
+
+/// Keeps the end of `row`, as wide as fits.
+fn truncate_start(row: &str, max_len: u16) -> String {
+    collect_to_unicode_width::<Vec<_>>(row.chars().rev(), max_len as usize)
+        .into_iter()
+        .rev()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn processes_table(names: &[&str]) -> Table {
+        let mut state = UIState::default();
+        state.processes = names
+            .iter()
+            .map(|name| {
+                let pid = name.as_bytes()[0].into();
+                (ProcessInfo::new(name, pid), NetworkData::default())
+            })
+            .collect();
+        Table::create_processes_table(&state)
+    }
+
+    #[test]
+    fn moves_selection_within_bounds() {
+        use RowMovement as M;
+
+        let table = processes_table(&["a", "b", "c", "d", "e"]);
+        let mut selection = TableSelection {
+            page_size: 2,
+            ..Default::default()
+        };
+        let mut moves = |movement| {
+            table.move_selection(&mut selection, movement);
+            table.selected_index(&selection).unwrap()
+        };
+        assert_eq!(moves(M::Down), 0);
+        assert_eq!(moves(M::Up), 0);
+        assert_eq!(moves(M::PageDown), 2);
+        assert_eq!(moves(M::PageDown), 4);
+        assert_eq!(moves(M::Down), 4);
+        assert_eq!(moves(M::PageUp), 2);
+        assert_eq!(moves(M::First), 0);
+        assert_eq!(moves(M::Last), 4);
+    }
+
+    #[test]
+    fn selection_follows_its_row() {
+        let mut selection = TableSelection::default();
+        let table = processes_table(&["a", "b", "c"]);
+        table.move_selection(&mut selection, RowMovement::Down);
+        table.move_selection(&mut selection, RowMovement::Down);
+
+        // "b" is still selected after it moved down
+        let reordered = processes_table(&["c", "a", "b"]);
+        assert_eq!(reordered.selected_index(&selection), Some(2));
+        // once it is gone, the row in its place is
+        let replaced = processes_table(&["x", "y"]);
+        assert_eq!(replaced.selected_index(&selection), Some(1));
+    }
+
+    #[test]
+    fn retains_rows_by_id() {
+        let mut table = processes_table(&["a", "b", "c"]);
+        table.retain_rows(&HashSet::from(["c:99".into(), "a:97".into()]));
+        assert_eq!(table.row_ids, ["a:97", "c:99"]);
+        let names = table
+            .data
+            .rows()
+            .iter()
+            .map(|row| row[0].clone())
+            .collect_vec();
+        assert_eq!(names, ["a", "c"]);
+    }
+
+    #[test]
+    fn shows_packets_in_wide_tables() {
+        let columns = |history_length, show_packets, width| {
+            let mut state = UIState::default();
+            state.history_length = history_length;
+            state.show_packets = show_packets;
+            let table = Table::create_processes_table(&state);
+            let layout = table.layout(width);
+            table.shown_column_names(&layout).join(", ")
+        };
+        let rate = "Process, PID, Connections, Rate (Up / Down)";
+        assert_eq!(columns(0, true, 90), rate);
+        assert_eq!(
+            columns(0, true, 110),
+            format!("{rate}, Packets/s (Up / Down)")
+        );
+        assert_eq!(columns(300, true, 110), format!("{rate}, History"));
+        assert_eq!(
+            columns(300, true, 130),
+            format!("{rate}, Packets/s (Up / Down), History")
+        );
+        assert_eq!(columns(300, false, 130), format!("{rate}, History"));
+    }
+
+    #[rstest]
+    fn cutoffs_are_increasing(
+        #[values(0, 300)] history_length: usize,
+        #[values(false, true)] show_packets: bool,
+    ) {
+        let mut state = UIState::default();
+        state.history_length = history_length;
+        state.show_packets = show_packets;
+        let ip_to_host = HashMap::new();
+        for table in [
+            Table::create_connections_table(&state, &ip_to_host),
+            Table::create_processes_table(&state),
+            Table::create_containers_table(&state),
+            Table::create_dns_queries_table(&state),
+            Table::create_remote_addresses_table(&state, &ip_to_host),
+        ] {
+            let cutoffs = table.width_cutoffs.iter().map(|&(cutoff, _)| cutoff);
+            assert!(
+                cutoffs.tuple_windows().all(|(a, b)| a < b),
+                "{:?}: {:?}",
+                table.kind,
+                table.width_cutoffs
+            );
+        }
+    }
+
+    #[test]
+    fn draws_sparklines_scaled_to_the_peak() {
+        let mut history = RateHistory::default();
+        for down in [0, 1, 40, 80, 20] {
+            let data = NetworkData {
+                total_bytes_downloaded: down,
+                ..Default::default()
+            };
+            history.push(&data, 4);
+        }
+        let line = sparkline(Some(&history));
+        assert_eq!(line, "▁▄█▂");
+        assert_eq!(truncate_start(&line, 2), "█▂");
+        assert_eq!(sparkline(None), "");
+    }
+}
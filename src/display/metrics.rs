@@ -0,0 +1,313 @@
+//! A minimal Prometheus exporter, serving the [`TotalCounters`] in the text exposition format.
+
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    thread::{self, park_timeout},
+    time::Duration,
+};
+
+use log::{debug, warn};
+
+use crate::display::{NetworkData, TotalCounters};
+
+/// How often the listener checks whether bandwhich is shutting down.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long a client may take to send its request, or to receive the response.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Renders the counters in the Prometheus text exposition format.
+pub fn render_metrics(totals: &TotalCounters) -> String {
+    let mut out = String::new();
+
+    write_family(
+        &mut out,
+        "bandwhich_process_bytes_total",
+        "Bytes transferred by a process since bandwhich started.",
+        totals
+            .processes
+            .iter()
+            .map(|(proc_info, data)| {
+                let labels = format!(
+                    r#"process="{}",pid="{}""#,
+                    escape_label_value(&proc_info.name),
+                    proc_info.pid
+                );
+                (labels, data)
+            })
+            .chain(pruned(r#"process="<OTHER>""#, &totals.pruned.processes)),
+    );
+    write_family(
+        &mut out,
+        "bandwhich_remote_address_bytes_total",
+        "Bytes exchanged with a remote address since bandwhich started.",
+        totals
+            .remote_addresses
+            .iter()
+            .map(|(ip, data)| (format!(r#"address="{ip}""#), data))
+            .chain(pruned(
+                r#"address="<OTHER>""#,
+                &totals.pruned.remote_addresses,
+            )),
+    );
+    write_family(
+        &mut out,
+        "bandwhich_interface_bytes_total",
+        "Bytes transferred on an interface since bandwhich started.",
+        totals
+            .interfaces
+            .iter()
+            .map(|(interface, data)| {
+                let labels = format!(r#"interface="{}""#, escape_label_value(interface));
+                (labels, data)
+            })
+            .chain(pruned(r#"interface="<OTHER>""#, &totals.pruned.interfaces)),
+    );
+
+    out
+}
+
+/// The sample of the entries pruned from a family, once there are any.
+fn pruned<'a>(
+    labels: &'static str,
+    data: &'a Option<NetworkData>,
+) -> impl Iterator<Item = (String, &'a NetworkData)> {
+    data.iter().map(move |data| (labels.to_string(), data))
+}
+
+fn write_family<'a>(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    samples: impl Iterator<Item = (String, &'a NetworkData)>,
+) {
+    // sort for a stable output between scrapes
+    let mut samples = samples.collect::<Vec<_>>();
+    samples.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} counter").unwrap();
+    for (labels, data) in samples {
+        writeln!(
+            out,
+            r#"{name}{{{labels},direction="up"}} {}"#,
+            data.total_bytes_uploaded
+        )
+        .unwrap();
+        writeln!(
+            out,
+            r#"{name}{{{labels},direction="down"}} {}"#,
+            data.total_bytes_downloaded
+        )
+        .unwrap();
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+/// Answers HTTP requests on `listener` with the latest rendered metrics, until `running` is cleared.
+pub fn serve_metrics(
+    listener: TcpListener,
+    metrics: Arc<RwLock<String>>,
+    running: Arc<AtomicBool>,
+) {
+    // poll instead of blocking, so that the thread can be joined on exit
+    if let Err(err) = listener.set_nonblocking(true) {
+        warn!("Failed to serve metrics: {err}");
+        return;
+    }
+    while running.load(Ordering::Acquire) {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                // a stalled client must not hold up the scrapes of others
+                let metrics = metrics.clone();
+                let spawned = thread::Builder::new()
+                    .name("metrics_client".to_string())
+                    .spawn(move || {
+                        if let Err(err) = respond(stream, &metrics) {
+                            debug!("Failed to answer metrics request from {peer}: {err}");
+                        }
+                    });
+                if let Err(err) = spawned {
+                    warn!("Failed to answer metrics request from {peer}: {err}");
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                park_timeout(ACCEPT_POLL_INTERVAL);
+            }
+            Err(err) => warn!("Failed to accept metrics connection: {err}"),
+        }
+    }
+}
+
+fn respond(mut stream: TcpStream, metrics: &RwLock<String>) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // the headers are of no interest, but are read so that the client sees a clean close
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    let path = path.split_once('?').map_or(path, |(path, _query)| path);
+
+    let (status, body) = match (method, path) {
+        ("GET", "/metrics") => ("200 OK", metrics.read().unwrap().clone()),
+        ("GET", _) => ("404 Not Found", "Metrics are served at /metrics\n".into()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {CONTENT_TYPE}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        io::Read,
+        net::{IpAddr, Ipv4Addr, TcpStream},
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{
+        display::{NetworkData, UIState},
+        network::{Connection, Direction, Protocol, Segment, Utilization},
+        os::ProcessInfo,
+    };
+
+    fn data(up: u128, down: u128) -> NetworkData {
+        NetworkData {
+            total_bytes_uploaded: up,
+            total_bytes_downloaded: down,
+            connection_count: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn exposition_format() {
+        let mut totals = TotalCounters::default();
+        totals
+            .processes
+            .insert(ProcessInfo::new(r#"a "quoted"\name"#, 42), data(1, 2));
+        totals
+            .remote_addresses
+            .insert(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), data(3, 4));
+        totals.interfaces.insert("eth0".into(), data(5, 6));
+        totals.pruned.interfaces = Some(data(7, 8));
+
+        let expected = r#"# HELP bandwhich_process_bytes_total Bytes transferred by a process since bandwhich started.
+# TYPE bandwhich_process_bytes_total counter
+bandwhich_process_bytes_total{process="a \"quoted\"\\name",pid="42",direction="up"} 1
+bandwhich_process_bytes_total{process="a \"quoted\"\\name",pid="42",direction="down"} 2
+# HELP bandwhich_remote_address_bytes_total Bytes exchanged with a remote address since bandwhich started.
+# TYPE bandwhich_remote_address_bytes_total counter
+bandwhich_remote_address_bytes_total{address="1.1.1.1",direction="up"} 3
+bandwhich_remote_address_bytes_total{address="1.1.1.1",direction="down"} 4
+# HELP bandwhich_interface_bytes_total Bytes transferred on an interface since bandwhich started.
+# TYPE bandwhich_interface_bytes_total counter
+bandwhich_interface_bytes_total{interface="<OTHER>",direction="up"} 7
+bandwhich_interface_bytes_total{interface="<OTHER>",direction="down"} 8
+bandwhich_interface_bytes_total{interface="eth0",direction="up"} 5
+bandwhich_interface_bytes_total{interface="eth0",direction="down"} 6
+"#;
+        assert_eq!(render_metrics(&totals), expected);
+    }
+
+    #[test]
+    fn totals_keep_growing_after_rates_settle() {
+        let connection = Connection::new(
+            "1.1.1.1:443".parse().unwrap(),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            4435,
+            Protocol::Tcp,
+        );
+        let procs = HashMap::from([(connection.local_socket, ProcessInfo::new("curl", 7))]);
+        let mut state = UIState::default();
+        for _ in 0..3 {
+            let mut utilization = Utilization::new();
+            utilization.ingest(Segment {
+                interface_name: "eth0".into(),
+                connection,
+                direction: Direction::Download,
+                data_length: 100,
+                packets: 1,
+                server_name: None,
+                dns_message: None,
+            });
+            state.update(procs.clone(), utilization, Duration::from_secs(1));
+        }
+
+        let totals = &state.totals;
+        let process = &totals.processes[&ProcessInfo::new("curl", 7)];
+        assert_eq!(process.total_bytes_downloaded, 300);
+        assert_eq!(process.total_bytes_uploaded, 0);
+        assert_eq!(
+            totals.remote_addresses[&connection.remote_socket.ip].total_bytes_downloaded,
+            300
+        );
+        assert_eq!(totals.interfaces["eth0"].total_bytes_downloaded, 300);
+        // the averaged rate does not grow
+        assert_eq!(state.processes[0].1.total_bytes_downloaded, 100);
+    }
+
+    #[test]
+    fn serves_metrics_over_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let metrics = Arc::new(RwLock::new("some_metric 1\n".to_string()));
+        let running = Arc::new(AtomicBool::new(true));
+        let server = thread::spawn({
+            let running = running.clone();
+            move || serve_metrics(listener, metrics, running)
+        });
+
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        // a client which never sends its request does not hold up the others
+        let _stalled = TcpStream::connect(address).unwrap();
+        thread::sleep(ACCEPT_POLL_INTERVAL * 2);
+        let start = std::time::Instant::now();
+        let found = get("/metrics");
+        let not_found = get("/");
+        let elapsed = start.elapsed();
+        running.store(false, Ordering::Release);
+        server.join().unwrap();
+
+        assert!(elapsed < REQUEST_TIMEOUT);
+        assert!(found.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(found.ends_with("\r\n\r\nsome_metric 1\n"));
+        assert!(not_found.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
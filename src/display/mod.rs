@@ -1,9 +1,16 @@
 mod components;
+mod metrics;
 mod raw_terminal_backend;
+mod structured_output;
+mod summary;
+mod traffic_filter;
 mod ui;
 mod ui_state;
 
 pub use components::*;
+pub use metrics::*;
 pub use raw_terminal_backend::*;
+pub use structured_output::*;
+pub use summary::*;
 pub use ui::*;
 pub use ui_state::*;
@@ -0,0 +1,599 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::IpAddr,
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::{
+    cli::RenderOpts,
+    display::{Bandwidth, UIState},
+    network::Protocol,
+};
+
+/// Version of the structured output schema.
+///
+/// Adding fields is not a breaking change; removing or changing the meaning of one is,
+/// and requires bumping this number.
+pub const STRUCTURED_OUTPUT_VERSION: u32 = 1;
+
+/// Fields shared by every structured output record.
+#[derive(Clone, Debug, Serialize)]
+pub struct RecordHeader {
+    pub version: u32,
+    /// Seconds since the Unix epoch.
+    pub timestamp: i64,
+    /// Whether `up` and `down`, and the packet counts, are totals since start instead of rates.
+    pub cumulative: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ProcessRecord<'a> {
+    pub name: &'a str,
+    pub pid: u32,
+    /// Set for processes running in a container.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    pub connections: u128,
+    pub up: u128,
+    pub down: u128,
+    /// Packets per second, or since start; only with `--packets`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packets_up: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packets_down: Option<u128>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ConnectionRecord<'a> {
+    pub interface: &'a str,
+    pub protocol: Protocol,
+    pub local_ip: IpAddr,
+    pub local_port: u16,
+    pub remote_ip: IpAddr,
+    pub remote_port: u16,
+    pub remote_host: Option<&'a str>,
+    pub process: &'a str,
+    pub pid: u32,
+    pub up: u128,
+    pub down: u128,
+    /// Packets per second, or since start; only with `--packets`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packets_up: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packets_down: Option<u128>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RemoteAddressRecord<'a> {
+    pub ip: IpAddr,
+    pub host: Option<&'a str>,
+    pub connections: u128,
+    pub up: u128,
+    pub down: u128,
+    /// Packets per second, or since start; only with `--packets`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packets_up: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packets_down: Option<u128>,
+}
+
+/// A single row of any table.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Record<'a> {
+    Process(ProcessRecord<'a>),
+    Connection(ConnectionRecord<'a>),
+    RemoteAddress(RemoteAddressRecord<'a>),
+}
+
+/// All tables of a single refresh, as emitted in JSON mode.
+#[derive(Clone, Debug, Serialize)]
+struct Snapshot<'a> {
+    #[serde(flatten)]
+    header: RecordHeader,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    processes: Option<Vec<ProcessRecord<'a>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connections: Option<Vec<ConnectionRecord<'a>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remote_addresses: Option<Vec<RemoteAddressRecord<'a>>>,
+}
+
+/// A single row, as emitted in NDJSON mode.
+#[derive(Clone, Debug, Serialize)]
+struct Line<'a> {
+    #[serde(flatten)]
+    header: &'a RecordHeader,
+    #[serde(flatten)]
+    record: Record<'a>,
+}
+
+/// Converts the tables of [`UIState`] into records.
+pub struct Records<'a> {
+    pub state: &'a UIState,
+    pub ip_to_host: &'a HashMap<IpAddr, String>,
+}
+
+impl<'a> Records<'a> {
+    pub fn header(&self, timestamp: i64) -> RecordHeader {
+        RecordHeader {
+            version: STRUCTURED_OUTPUT_VERSION,
+            timestamp,
+            cumulative: self.state.cumulative_mode,
+        }
+    }
+
+    fn host(&self, ip: &IpAddr) -> Option<&'a str> {
+        self.ip_to_host.get(ip).map(String::as_str)
+    }
+
+    pub fn processes(&self) -> impl Iterator<Item = ProcessRecord<'a>> {
+        let state = self.state;
+        state.processes.iter().map(move |(proc_info, data)| {
+            let (packets_up, packets_down) = packets(state, data);
+            ProcessRecord {
+                name: &proc_info.name,
+                pid: proc_info.pid,
+                container: proc_info.container.as_ref().map(ToString::to_string),
+                connections: data.connection_count,
+                up: data.total_bytes_uploaded,
+                down: data.total_bytes_downloaded,
+                packets_up,
+                packets_down,
+            }
+        })
+    }
+
+    pub fn connections(&self) -> impl Iterator<Item = ConnectionRecord<'a>> + '_ {
+        self.state.connections.iter().map(|(connection, data)| {
+            let (packets_up, packets_down) = packets(self.state, data);
+            ConnectionRecord {
+                interface: &data.interface_name,
+                protocol: connection.local_socket.protocol,
+                local_ip: connection.local_socket.ip,
+                local_port: connection.local_socket.port,
+                remote_ip: connection.remote_socket.ip,
+                remote_port: connection.remote_socket.port,
+                remote_host: self.host(&connection.remote_socket.ip),
+                process: &data.process_name,
+                pid: data.pid,
+                up: data.total_bytes_uploaded,
+                down: data.total_bytes_downloaded,
+                packets_up,
+                packets_down,
+            }
+        })
+    }
+
+    pub fn remote_addresses(&self) -> impl Iterator<Item = RemoteAddressRecord<'a>> + '_ {
+        self.state.remote_addresses.iter().map(|(ip, data)| {
+            let (packets_up, packets_down) = packets(self.state, data);
+            RemoteAddressRecord {
+                ip: *ip,
+                host: self.host(ip),
+                connections: data.connection_count,
+                up: data.total_bytes_uploaded,
+                down: data.total_bytes_downloaded,
+                packets_up,
+                packets_down,
+            }
+        })
+    }
+
+    /// Serializes all selected tables into a single JSON object.
+    pub fn to_json(&self, opts: &RenderOpts, timestamp: i64) -> String {
+        let (processes, connections, addresses) = selected_tables(opts);
+        let snapshot = Snapshot {
+            header: self.header(timestamp),
+            processes: processes.then(|| self.processes().collect()),
+            connections: connections.then(|| self.connections().collect()),
+            remote_addresses: addresses.then(|| self.remote_addresses().collect()),
+        };
+        serde_json::to_string(&snapshot).expect("records are always serializable")
+    }
+
+    /// Returns all rows of the selected tables.
+    pub fn selected(&self, opts: &RenderOpts) -> Vec<Record<'a>> {
+        let (processes, connections, addresses) = selected_tables(opts);
+        let mut records = vec![];
+        if processes {
+            records.extend(self.processes().map(Record::Process));
+        }
+        if connections {
+            records.extend(self.connections().map(Record::Connection));
+        }
+        if addresses {
+            records.extend(self.remote_addresses().map(Record::RemoteAddress));
+        }
+        records
+    }
+
+    /// Serializes all rows of the selected tables, one JSON object per row.
+    pub fn to_ndjson(&self, opts: &RenderOpts, timestamp: i64) -> Vec<String> {
+        let header = self.header(timestamp);
+        self.selected(opts)
+            .into_iter()
+            .map(|record| {
+                let line = Line {
+                    header: &header,
+                    record,
+                };
+                serde_json::to_string(&line).expect("records are always serializable")
+            })
+            .collect()
+    }
+}
+
+/// The uploaded and downloaded packets, if they are shown.
+fn packets(state: &UIState, data: &impl Bandwidth) -> (Option<u128>, Option<u128>) {
+    if state.show_packets {
+        (
+            Some(data.get_total_packets_uploaded()),
+            Some(data.get_total_packets_downloaded()),
+        )
+    } else {
+        (None, None)
+    }
+}
+
+/// Returns whether the processes, connections and remote addresses tables should be output.
+///
+/// If no table is explicitly selected, all of them are.
+pub fn selected_tables(opts: &RenderOpts) -> (bool, bool, bool) {
+    // containers are reported through the process records
+    if opts.processes || opts.connections || opts.addresses || opts.containers {
+        (
+            opts.processes || opts.containers,
+            opts.connections,
+            opts.addresses,
+        )
+    } else {
+        (true, true, true)
+    }
+}
+
+/// Header of the CSV written to stdout, which combines rows of all tables.
+pub const CSV_HEADER: &[&str] = &[
+    "timestamp",
+    "kind",
+    "process",
+    "pid",
+    "interface",
+    "protocol",
+    "local_ip",
+    "local_port",
+    "remote_ip",
+    "remote_port",
+    "remote_host",
+    "connections",
+    "up",
+    "down",
+];
+/// Columns appended to every CSV header with `--packets`.
+const PACKETS_CSV_HEADER: &[&str] = &["packets_up", "packets_down"];
+
+/// A column of the CSV output of a table: its header in the file of the table, its header in
+/// [`CSV_HEADER`], and how to format the field of a record.
+struct CsvColumn<F>(&'static str, &'static str, F);
+type CsvField<R> = fn(&R) -> String;
+type ProcessCsvField = fn(&ProcessRecord<'_>) -> String;
+type ConnectionCsvField = fn(&ConnectionRecord<'_>) -> String;
+type RemoteAddressCsvField = fn(&RemoteAddressRecord<'_>) -> String;
+
+// the columns of each table after the timestamp
+const PROCESS_CSV_COLUMNS: &[CsvColumn<ProcessCsvField>] = &[
+    CsvColumn("process", "process", |r| r.name.to_string()),
+    CsvColumn("pid", "pid", |r| r.pid.to_string()),
+    CsvColumn("connections", "connections", |r| r.connections.to_string()),
+    CsvColumn("up", "up", |r| r.up.to_string()),
+    CsvColumn("down", "down", |r| r.down.to_string()),
+];
+const CONNECTION_CSV_COLUMNS: &[CsvColumn<ConnectionCsvField>] = &[
+    CsvColumn("interface", "interface", |r| r.interface.to_string()),
+    CsvColumn("protocol", "protocol", |r| r.protocol.to_string()),
+    CsvColumn("local_ip", "local_ip", |r| r.local_ip.to_string()),
+    CsvColumn("local_port", "local_port", |r| r.local_port.to_string()),
+    CsvColumn("remote_ip", "remote_ip", |r| r.remote_ip.to_string()),
+    CsvColumn("remote_port", "remote_port", |r| r.remote_port.to_string()),
+    CsvColumn("remote_host", "remote_host", |r| {
+        r.remote_host.unwrap_or_default().to_string()
+    }),
+    CsvColumn("process", "process", |r| r.process.to_string()),
+    CsvColumn("pid", "pid", |r| r.pid.to_string()),
+    CsvColumn("up", "up", |r| r.up.to_string()),
+    CsvColumn("down", "down", |r| r.down.to_string()),
+];
+const REMOTE_ADDRESS_CSV_COLUMNS: &[CsvColumn<RemoteAddressCsvField>] = &[
+    CsvColumn("ip", "remote_ip", |r| r.ip.to_string()),
+    CsvColumn("host", "remote_host", |r| {
+        r.host.unwrap_or_default().to_string()
+    }),
+    CsvColumn("connections", "connections", |r| r.connections.to_string()),
+    CsvColumn("up", "up", |r| r.up.to_string()),
+    CsvColumn("down", "down", |r| r.down.to_string()),
+];
+
+/// Returns `header`, followed by the packet columns if they are shown.
+pub fn csv_header(header: &[&'static str], packets: bool) -> Vec<&'static str> {
+    let packets = if packets { PACKETS_CSV_HEADER } else { &[] };
+    [header, packets].concat()
+}
+
+/// The header of the CSV file of a table.
+fn table_csv_header<R>(columns: &[CsvColumn<CsvField<R>>], packets: bool) -> Vec<&'static str> {
+    let header = ["timestamp"]
+        .into_iter()
+        .chain(columns.iter().map(|CsvColumn(name, _, _)| *name))
+        .collect::<Vec<_>>();
+    csv_header(&header, packets)
+}
+
+/// The fields of a record in the CSV file of its table.
+fn table_csv_fields<R>(
+    columns: &[CsvColumn<CsvField<R>>],
+    record: &R,
+    timestamp: i64,
+    (packets_up, packets_down): (Option<u128>, Option<u128>),
+) -> Vec<String> {
+    [timestamp.to_string()]
+        .into_iter()
+        .chain(columns.iter().map(|CsvColumn(_, _, field)| field(record)))
+        .chain(packets_csv_fields(packets_up, packets_down))
+        .collect()
+}
+
+/// The fields of a record by their header in [`CSV_HEADER`].
+fn combined_csv_fields<R>(
+    columns: &[CsvColumn<CsvField<R>>],
+    record: &R,
+) -> Vec<(&'static str, String)> {
+    columns
+        .iter()
+        .map(|CsvColumn(_, combined_name, field)| (*combined_name, field(record)))
+        .collect()
+}
+
+/// The fields of the packet columns, which are only set with `--packets`.
+fn packets_csv_fields(up: Option<u128>, down: Option<u128>) -> impl Iterator<Item = String> {
+    [up, down]
+        .into_iter()
+        .flatten()
+        .map(|packets| packets.to_string())
+}
+
+impl Record<'_> {
+    /// Returns the fields of this record in the order of [`CSV_HEADER`].
+    pub fn csv_fields(&self, timestamp: i64) -> Vec<String> {
+        let (kind, fields, (packets_up, packets_down)) = match self {
+            Self::Process(r) => (
+                "process",
+                combined_csv_fields(PROCESS_CSV_COLUMNS, r),
+                (r.packets_up, r.packets_down),
+            ),
+            Self::Connection(r) => (
+                "connection",
+                combined_csv_fields(CONNECTION_CSV_COLUMNS, r),
+                (r.packets_up, r.packets_down),
+            ),
+            Self::RemoteAddress(r) => (
+                "remote_address",
+                combined_csv_fields(REMOTE_ADDRESS_CSV_COLUMNS, r),
+                (r.packets_up, r.packets_down),
+            ),
+        };
+        CSV_HEADER
+            .iter()
+            .map(|&column| match column {
+                "timestamp" => timestamp.to_string(),
+                "kind" => kind.to_owned(),
+                // columns of other tables are left empty
+                column => fields
+                    .iter()
+                    .find(|(name, _)| *name == column)
+                    .map(|(_, field)| field.clone())
+                    .unwrap_or_default(),
+            })
+            .chain(packets_csv_fields(packets_up, packets_down))
+            .collect()
+    }
+}
+
+/// Formats a single CSV line as per RFC 4180, without the line terminator.
+pub fn csv_line<S>(fields: &[S]) -> String
+where
+    S: AsRef<str>,
+{
+    fields
+        .iter()
+        .map(|field| {
+            let field = field.as_ref();
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// One CSV file per table, for when tables are written to separate files.
+pub struct CsvFiles {
+    processes: Option<BufWriter<File>>,
+    connections: Option<BufWriter<File>>,
+    remote_addresses: Option<BufWriter<File>>,
+}
+
+impl CsvFiles {
+    /// Creates a CSV file for each selected table in `dir`, and writes their headers.
+    pub fn create(dir: &Path, opts: &RenderOpts) -> io::Result<Self> {
+        let (processes, connections, addresses) = selected_tables(opts);
+        let create = |selected: bool, name: &str, header: Vec<&str>| {
+            selected
+                .then(|| {
+                    let mut file = BufWriter::new(File::create(dir.join(name))?);
+                    writeln!(file, "{}", csv_line(&header))?;
+                    file.flush()?;
+                    Ok::<_, io::Error>(file)
+                })
+                .transpose()
+        };
+        Ok(Self {
+            processes: create(
+                processes,
+                "processes.csv",
+                table_csv_header(PROCESS_CSV_COLUMNS, opts.packets),
+            )?,
+            connections: create(
+                connections,
+                "connections.csv",
+                table_csv_header(CONNECTION_CSV_COLUMNS, opts.packets),
+            )?,
+            remote_addresses: create(
+                addresses,
+                "remote_addresses.csv",
+                table_csv_header(REMOTE_ADDRESS_CSV_COLUMNS, opts.packets),
+            )?,
+        })
+    }
+
+    /// Appends the rows of a single refresh to each file.
+    pub fn write(&mut self, records: &Records, timestamp: i64) -> io::Result<()> {
+        if let Some(file) = self.processes.as_mut() {
+            for r in records.processes() {
+                let packets = (r.packets_up, r.packets_down);
+                let fields = table_csv_fields(PROCESS_CSV_COLUMNS, &r, timestamp, packets);
+                writeln!(file, "{}", csv_line(&fields))?;
+            }
+            file.flush()?;
+        }
+        if let Some(file) = self.connections.as_mut() {
+            for r in records.connections() {
+                let packets = (r.packets_up, r.packets_down);
+                let fields = table_csv_fields(CONNECTION_CSV_COLUMNS, &r, timestamp, packets);
+                writeln!(file, "{}", csv_line(&fields))?;
+            }
+            file.flush()?;
+        }
+        if let Some(file) = self.remote_addresses.as_mut() {
+            for r in records.remote_addresses() {
+                let packets = (r.packets_up, r.packets_down);
+                let fields = table_csv_fields(REMOTE_ADDRESS_CSV_COLUMNS, &r, timestamp, packets);
+                writeln!(file, "{}", csv_line(&fields))?;
+            }
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(packets: Option<u128>) -> Vec<Record<'static>> {
+        let ip = IpAddr::from([1, 1, 1, 1]);
+        vec![
+            Record::Process(ProcessRecord {
+                name: "curl",
+                pid: 1,
+                container: None,
+                connections: 1,
+                up: 2,
+                down: 3,
+                packets_up: packets,
+                packets_down: packets,
+            }),
+            Record::Connection(ConnectionRecord {
+                interface: "eth0",
+                protocol: Protocol::Tcp,
+                local_ip: IpAddr::from([10, 0, 0, 2]),
+                local_port: 443,
+                remote_ip: ip,
+                remote_port: 12345,
+                remote_host: None,
+                process: "curl",
+                pid: 1,
+                up: 2,
+                down: 3,
+                packets_up: packets,
+                packets_down: packets,
+            }),
+            Record::RemoteAddress(RemoteAddressRecord {
+                ip,
+                host: Some("one.one.one.one"),
+                connections: 1,
+                up: 2,
+                down: 3,
+                packets_up: packets,
+                packets_down: packets,
+            }),
+        ]
+    }
+
+    #[test]
+    fn csv_fields_match_the_headers() {
+        for (packets, packets_column) in [(None, false), (Some(4), true)] {
+            let header = csv_header(CSV_HEADER, packets_column);
+            for record in records(packets) {
+                let fields = record.csv_fields(0);
+                assert_eq!(fields.len(), header.len());
+                let (table_header, table_fields) = match &record {
+                    Record::Process(r) => (
+                        table_csv_header(PROCESS_CSV_COLUMNS, packets_column),
+                        table_csv_fields(PROCESS_CSV_COLUMNS, r, 0, (packets, packets)),
+                    ),
+                    Record::Connection(r) => (
+                        table_csv_header(CONNECTION_CSV_COLUMNS, packets_column),
+                        table_csv_fields(CONNECTION_CSV_COLUMNS, r, 0, (packets, packets)),
+                    ),
+                    Record::RemoteAddress(r) => (
+                        table_csv_header(REMOTE_ADDRESS_CSV_COLUMNS, packets_column),
+                        table_csv_fields(REMOTE_ADDRESS_CSV_COLUMNS, r, 0, (packets, packets)),
+                    ),
+                };
+                assert_eq!(table_fields.len(), table_header.len());
+                // every field of a table also goes into the combined CSV
+                for field in &table_fields {
+                    assert!(fields.contains(field), "{field} is missing in {fields:?}");
+                }
+                if packets_column {
+                    assert_eq!(fields[fields.len() - 2..], ["4", "4"]);
+                    assert_eq!(table_fields[table_fields.len() - 2..], ["4", "4"]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn table_columns_are_in_the_combined_csv() {
+        let combined_names = PROCESS_CSV_COLUMNS
+            .iter()
+            .map(|column| column.1)
+            .chain(CONNECTION_CSV_COLUMNS.iter().map(|column| column.1))
+            .chain(REMOTE_ADDRESS_CSV_COLUMNS.iter().map(|column| column.1));
+        for name in combined_names {
+            assert!(CSV_HEADER.contains(&name), "{name}");
+        }
+    }
+
+    #[test]
+    fn csv_quoting() {
+        let fields = [
+            "plain",
+            "with, comma",
+            r#"with "quotes""#,
+            "multi\nline",
+            "",
+        ];
+        assert_eq!(
+            csv_line(&fields),
+            r#"plain,"with, comma","with ""quotes""","multi
+line","#
+        );
+    }
+}
@@ -0,0 +1,308 @@
+//! The report printed at the end of a run bounded by `--duration` or `--count`.
+
+use std::{collections::HashMap, fmt::Write as _, hash::Hash, net::IpAddr, time::Duration};
+
+use itertools::Itertools;
+
+use crate::{
+    display::{
+        display_seconds, BandwidthUnitFamily, DisplayBandwidth, NameOrd, NetworkData, TotalCounters,
+    },
+    network::{display_connection_string, display_ip_or_host, Connection},
+    os::ProcessInfo,
+};
+
+/// How many of the busiest entries are listed per section.
+const SUMMARY_ROWS: usize = 10;
+
+/// What an entry transferred over the whole run, in bytes and in bytes per second.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct RunTraffic {
+    total_bytes_uploaded: u128,
+    total_bytes_downloaded: u128,
+    peak_upload_rate: u128,
+    peak_download_rate: u128,
+}
+
+impl RunTraffic {
+    /// Adds the bytes of a refresh which lasted `interval`.
+    fn add(&mut self, uploaded: u128, downloaded: u128, interval: Duration) {
+        let micros = interval.as_micros().max(1);
+        self.total_bytes_uploaded += uploaded;
+        self.total_bytes_downloaded += downloaded;
+        self.peak_upload_rate = self.peak_upload_rate.max(uploaded * 1_000_000 / micros);
+        self.peak_download_rate = self.peak_download_rate.max(downloaded * 1_000_000 / micros);
+    }
+
+    fn total(&self) -> u128 {
+        self.total_bytes_uploaded + self.total_bytes_downloaded
+    }
+}
+
+struct ConnectionTraffic {
+    interface_name: String,
+    process_name: String,
+    server_name: Option<String>,
+    traffic: RunTraffic,
+}
+
+/// Accumulates the traffic of every refresh, to report the busiest processes, remote addresses
+/// and connections once sampling stops.
+#[derive(Default)]
+pub struct Summary {
+    processes: HashMap<ProcessInfo, RunTraffic>,
+    remote_addresses: HashMap<IpAddr, RunTraffic>,
+    connections: HashMap<Connection, ConnectionTraffic>,
+    refreshes: u64,
+    elapsed: Duration,
+}
+
+impl Summary {
+    /// Adds the bytes transferred in the latest refresh, which lasted `duration`.
+    pub fn record(&mut self, latest: &TotalCounters, duration: Duration) {
+        record_each(&mut self.processes, &latest.processes, duration);
+        record_each(
+            &mut self.remote_addresses,
+            &latest.remote_addresses,
+            duration,
+        );
+        for (connection, data) in &latest.connections {
+            let connection_traffic =
+                self.connections
+                    .entry(*connection)
+                    .or_insert_with(|| ConnectionTraffic {
+                        interface_name: data.interface_name.clone(),
+                        process_name: data.process_name.clone(),
+                        server_name: None,
+                        traffic: RunTraffic::default(),
+                    });
+            if data.server_name.is_some() {
+                connection_traffic.server_name.clone_from(&data.server_name);
+            }
+            connection_traffic.traffic.add(
+                data.total_bytes_uploaded,
+                data.total_bytes_downloaded,
+                duration,
+            );
+        }
+        self.refreshes += 1;
+        self.elapsed += duration;
+    }
+
+    pub fn render(
+        &self,
+        unit_family: BandwidthUnitFamily,
+        ip_to_host: &HashMap<IpAddr, String>,
+    ) -> String {
+        let mut out = format!(
+            "Summary of {} refreshes over {}\n",
+            self.refreshes,
+            display_seconds(self.elapsed)
+        );
+        let elapsed_micros = self.elapsed.as_micros().max(1);
+        let bandwidth = |uploaded: u128, downloaded: u128| {
+            let up = DisplayBandwidth {
+                bandwidth: uploaded as f64,
+                unit_family,
+            };
+            let down = DisplayBandwidth {
+                bandwidth: downloaded as f64,
+                unit_family,
+            };
+            format!("{up} / {down}")
+        };
+        let columns = |traffic: &RunTraffic| {
+            vec![
+                bandwidth(traffic.total_bytes_uploaded, traffic.total_bytes_downloaded),
+                bandwidth(traffic.peak_upload_rate, traffic.peak_download_rate),
+                bandwidth(
+                    traffic.total_bytes_uploaded * 1_000_000 / elapsed_micros,
+                    traffic.total_bytes_downloaded * 1_000_000 / elapsed_micros,
+                ),
+            ]
+        };
+
+        let processes = busiest(&self.processes, |traffic| traffic)
+            .map(|(proc_info, traffic)| {
+                let mut row = vec![proc_info.name.clone(), proc_info.pid.to_string()];
+                row.extend(columns(traffic));
+                row
+            })
+            .collect_vec();
+        write_section(&mut out, &["Process", "PID"], processes);
+
+        let remote_addresses = busiest(&self.remote_addresses, |traffic| traffic)
+            .map(|(ip, traffic)| {
+                let mut row = vec![display_ip_or_host(*ip, ip_to_host)];
+                row.extend(columns(traffic));
+                row
+            })
+            .collect_vec();
+        write_section(&mut out, &["Remote address"], remote_addresses);
+
+        let connections = busiest(&self.connections, |connection| &connection.traffic)
+            .map(|(connection, data)| {
+                let mut row = vec![
+                    display_connection_string(
+                        connection,
+                        ip_to_host,
+                        &data.interface_name,
+                        data.server_name.as_deref(),
+                    ),
+                    data.process_name.clone(),
+                ];
+                row.extend(columns(&data.traffic));
+                row
+            })
+            .collect_vec();
+        write_section(&mut out, &["Connection", "Process"], connections);
+
+        out
+    }
+}
+
+fn record_each<K>(
+    summary: &mut HashMap<K, RunTraffic>,
+    latest: &HashMap<K, NetworkData>,
+    interval: Duration,
+) where
+    K: Eq + Hash + Clone,
+{
+    for (key, data) in latest {
+        summary.entry(key.clone()).or_default().add(
+            data.total_bytes_uploaded,
+            data.total_bytes_downloaded,
+            interval,
+        );
+    }
+}
+
+/// The [`SUMMARY_ROWS`] entries which transferred the most, busiest first.
+fn busiest<K, V>(
+    map: &HashMap<K, V>,
+    traffic: impl Fn(&V) -> &RunTraffic,
+) -> impl Iterator<Item = (&K, &V)>
+where
+    K: NameOrd,
+{
+    map.iter()
+        .sorted_by(|(a_key, a), (b_key, b)| {
+            (traffic(b).total().cmp(&traffic(a).total())).then_with(|| a_key.cmp_name(b_key))
+        })
+        .take(SUMMARY_ROWS)
+}
+
+/// Writes a table whose columns are padded to their widest cell.
+fn write_section(out: &mut String, labels: &[&str], rows: Vec<Vec<String>>) {
+    let header = labels
+        .iter()
+        .chain(&[
+            "Total (Up / Down)",
+            "Peak rate (Up / Down)",
+            "Average rate (Up / Down)",
+        ])
+        .map(|label| label.to_string())
+        .collect_vec();
+    writeln!(out).unwrap();
+    if rows.is_empty() {
+        writeln!(out, "{}: <NO TRAFFIC>", labels[0]).unwrap();
+        return;
+    }
+    let widths = (0..header.len())
+        .map(|i| {
+            rows.iter()
+                .chain([&header])
+                .map(|row| row[i].chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect_vec();
+    for row in [&header].into_iter().chain(&rows) {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{cell:<width$}"))
+            .join("  ");
+        writeln!(out, "{}", line.trim_end()).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::{
+        cli::UnitFamily,
+        display::{ConnectionData, NetworkData},
+        network::Protocol,
+    };
+
+    fn latest(up: u128, down: u128) -> TotalCounters {
+        let connection = Connection::new(
+            "1.1.1.1:443".parse().unwrap(),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            4435,
+            Protocol::Tcp,
+        );
+        let data = NetworkData {
+            total_bytes_uploaded: up,
+            total_bytes_downloaded: down,
+            connection_count: 1,
+            ..Default::default()
+        };
+        TotalCounters {
+            processes: HashMap::from([(ProcessInfo::new("curl", 7), data.clone())]),
+            remote_addresses: HashMap::from([(connection.remote_socket.ip, data)]),
+            connections: HashMap::from([(
+                connection,
+                ConnectionData {
+                    total_bytes_uploaded: up,
+                    total_bytes_downloaded: down,
+                    process_name: "curl".into(),
+                    pid: 7,
+                    interface_name: "eth0".into(),
+                    server_name: None,
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reports_totals_peaks_and_averages() {
+        let mut summary = Summary::default();
+        let second = Duration::from_secs(1);
+        summary.record(&latest(100, 1000), second);
+        summary.record(&latest(300, 0), second);
+        summary.record(&TotalCounters::default(), second);
+        summary.record(&latest(50, 2000), Duration::from_millis(500));
+
+        let expected = "Summary of 4 refreshes over 3.5s
+
+Process  PID  Total (Up / Down)  Peak rate (Up / Down)  Average rate (Up / Down)
+curl     7    450.00B / 2.93KiB  300.00B / 3.91KiB      128.00B / 857.00B
+
+Remote address  Total (Up / Down)  Peak rate (Up / Down)  Average rate (Up / Down)
+1.1.1.1         450.00B / 2.93KiB  300.00B / 3.91KiB      128.00B / 857.00B
+
+Connection                        Process  Total (Up / Down)  Peak rate (Up / Down)  Average rate (Up / Down)
+<eth0>:4435 => 1.1.1.1:443 (tcp)  curl     450.00B / 2.93KiB  300.00B / 3.91KiB      128.00B / 857.00B
+";
+        let unit_family = BandwidthUnitFamily::from(UnitFamily::BinBytes);
+        assert_eq!(summary.render(unit_family, &HashMap::new()), expected);
+    }
+
+    #[test]
+    fn reports_no_traffic() {
+        let mut summary = Summary::default();
+        summary.record(&TotalCounters::default(), Duration::from_secs(1));
+
+        let unit_family = BandwidthUnitFamily::from(UnitFamily::BinBytes);
+        assert_eq!(
+            summary.render(unit_family, &HashMap::new()),
+            "Summary of 1 refreshes over 1s\n\nProcess: <NO TRAFFIC>\n\nRemote address: <NO TRAFFIC>\n\nConnection: <NO TRAFFIC>\n"
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use std::net::IpAddr;
+
+use crate::{
+    cli::{FilterOpts, IpBlock},
+    network::Connection,
+    os::ProcessInfo,
+};
+
+impl FilterOpts {
+    /// Whether the traffic of a connection is shown and counted.
+    ///
+    /// An option only narrows the view when given, and repeating it widens what it matches.
+    /// `--process` and `--pid` both pick processes, so matching either of them is enough.
+    pub fn matches(&self, proc_info: &ProcessInfo, connection: &Connection) -> bool {
+        let is_process = |names: &[String], pids: &[u32]| {
+            names.contains(&proc_info.name) || pids.contains(&proc_info.pid)
+        };
+        let has_port = |ports: &[u16]| {
+            ports.contains(&connection.local_socket.port)
+                || ports.contains(&connection.remote_socket.port)
+        };
+        let is_remote = |blocks: &[IpBlock]| {
+            blocks
+                .iter()
+                .any(|block| block.contains(connection.remote_socket.ip))
+        };
+
+        let included = ((self.process.is_empty() && self.pid.is_empty())
+            || is_process(&self.process, &self.pid))
+            && (self.port.is_empty() || has_port(&self.port))
+            && (self.remote.is_empty() || is_remote(&self.remote));
+        let excluded = is_process(&self.exclude_process, &self.exclude_pid)
+            || has_port(&self.exclude_port)
+            || is_remote(&self.exclude_remote);
+        included && !excluded
+    }
+}
+
+impl IpBlock {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip) {
+            (IpAddr::V4(block), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or_default();
+                u32::from(block) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(block), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or_default();
+                u128::from(block) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use rstest::rstest;
+
+    use super::*;
+    use crate::network::Protocol;
+
+    #[rstest]
+    #[case("10.0.0.0/8", "10.20.30.40", true)]
+    #[case("10.0.0.0/8", "11.0.0.1", false)]
+    #[case("0.0.0.0/0", "1.1.1.1", true)]
+    #[case("1.1.1.1", "1.1.1.1", true)]
+    #[case("1.1.1.1", "1.1.1.2", false)]
+    #[case("fd00::/64", "fd00::1", true)]
+    #[case("fd00::/64", "fd01::1", false)]
+    #[case("10.0.0.0/8", "::ffff:10.0.0.1", false)]
+    fn ip_block_contains(#[case] block: &str, #[case] ip: &str, #[case] expected: bool) {
+        let block: IpBlock = block.parse().unwrap();
+        assert_eq!(block.contains(ip.parse().unwrap()), expected);
+    }
+
+    #[rstest]
+    #[case(FilterOpts::default(), true)]
+    #[case(FilterOpts { process: vec!["curl".into()], ..Default::default() }, true)]
+    #[case(FilterOpts { process: vec!["nginx".into()], ..Default::default() }, false)]
+    #[case(FilterOpts { process: vec!["nginx".into()], pid: vec![7], ..Default::default() }, true)]
+    #[case(FilterOpts { port: vec![443], ..Default::default() }, true)]
+    #[case(FilterOpts { port: vec![4435], ..Default::default() }, true)]
+    #[case(FilterOpts { port: vec![5432], ..Default::default() }, false)]
+    #[case(FilterOpts { remote: vec!["1.0.0.0/8".parse().unwrap()], ..Default::default() }, true)]
+    #[case(FilterOpts { process: vec!["curl".into()], port: vec![5432], ..Default::default() }, false)]
+    #[case(FilterOpts { exclude_process: vec!["curl".into()], ..Default::default() }, false)]
+    #[case(FilterOpts { exclude_pid: vec![8], ..Default::default() }, true)]
+    #[case(FilterOpts { port: vec![443], exclude_port: vec![4435], ..Default::default() }, false)]
+    #[case(FilterOpts { exclude_remote: vec!["1.1.1.1".parse().unwrap()], ..Default::default() }, false)]
+    fn filters_connections(#[case] filter: FilterOpts, #[case] expected: bool) {
+        let connection = Connection::new(
+            "1.1.1.1:443".parse().unwrap(),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            4435,
+            Protocol::Tcp,
+        );
+        let proc_info = ProcessInfo::new("curl", 7);
+        assert_eq!(filter.matches(&proc_info, &connection), expected);
+    }
+}
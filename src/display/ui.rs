@@ -1,15 +1,25 @@
-use std::{collections::HashMap, net::IpAddr, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    time::Duration,
+};
 
 use chrono::prelude::*;
+use crossterm::event::KeyCode;
+use log::warn;
 use ratatui::{backend::Backend, Terminal};
 
 use crate::{
-    cli::{Opt, RenderOpts},
+    cli::{Opt, OutputFormat, RenderOpts},
     display::{
-        components::{HeaderDetails, HelpText, Layout, Table},
-        UIState,
+        components::{
+            process_row_id, HeaderDetails, HelpText, Layout, ProcessDetails, RateChart,
+            RowMovement, Search, Table, TableKind, TableSelection,
+        },
+        csv_header, csv_line, render_metrics, Averaging, Bandwidth, CsvFiles, Records, Summary,
+        UIState, CSV_HEADER,
     },
-    network::{display_connection_string, display_ip_or_host, LocalSocket, Utilization},
+    network::{display_connection_string, display_remote_address, LocalSocket, Utilization},
     os::ProcessInfo,
 };
 
@@ -21,22 +31,51 @@ where
     state: UIState,
     ip_to_host: HashMap<IpAddr, String>,
     opts: RenderOpts,
+    /// Set when CSV rows are written to a file per table instead of stdout.
+    csv_files: Option<CsvFiles>,
+    csv_header_written: bool,
+    /// The row selected in each table, kept while the table is out of focus.
+    selections: HashMap<TableKind, TableSelection>,
+    /// The process shown on its own, in place of the tables.
+    process_details: Option<ProcessInfo>,
+    /// Whether the chart of the total rates is shown above the tables.
+    show_chart: bool,
+    /// Narrows the tables to the rows matching it, when typed after `/`.
+    search: Option<Search>,
+    /// The traffic of the whole run, kept when it is bounded by `--duration` or `--count`.
+    summary: Option<Summary>,
 }
 
 impl<B> Ui<B>
 where
     B: Backend,
 {
-    pub fn new(terminal_backend: B, opts: &Opt) -> Self {
+    pub fn new(terminal_backend: B, opts: &Opt, csv_files: Option<CsvFiles>) -> Self {
         let mut terminal = Terminal::new(terminal_backend).unwrap();
         terminal.clear().unwrap();
         terminal.hide_cursor().unwrap();
         let state = {
             let mut state = UIState::default();
-            state.interface_name.clone_from(&opts.interface);
+            // when replaying a capture file, show its name in place of the interface
+            state.interface_name = opts.interface.clone().or_else(|| {
+                let file_name = opts.pcap.as_deref()?.file_name()?;
+                Some(file_name.to_string_lossy().into_owned())
+            });
+            state.capture_filter = opts.filter.clone();
+            state.traffic_filter = opts.filter_opts.clone();
             state.unit_family = opts.render_opts.unit_family.into();
             state.cumulative_mode = opts.render_opts.total_utilization;
             state.show_dns = opts.show_dns;
+            state.show_packets = opts.render_opts.packets;
+            let interval = opts.render_opts.interval;
+            state.averaging = Averaging {
+                smoothing: opts.render_opts.smoothing,
+                window: opts.render_opts.window,
+                interval,
+            };
+            // --history is in seconds
+            state.history_length =
+                (opts.render_opts.history as f64 / interval.as_secs_f64()).ceil() as usize;
             state
         };
         Ui {
@@ -44,9 +83,64 @@ where
             state,
             ip_to_host: Default::default(),
             opts: opts.render_opts,
+            csv_files,
+            csv_header_written: false,
+            selections: HashMap::new(),
+            process_details: None,
+            show_chart: false,
+            search: None,
+            summary: (opts.duration.is_some() || opts.count.is_some()).then(Summary::default),
         }
     }
     pub fn output_text(&mut self, write_to_stdout: &mut (dyn FnMut(&str) + Send)) {
+        match self.opts.format {
+            OutputFormat::Text => self.output_plain_text(write_to_stdout),
+            OutputFormat::Json | OutputFormat::Ndjson => self.output_structured(write_to_stdout),
+            OutputFormat::Csv => self.output_csv(write_to_stdout),
+        }
+    }
+
+    fn output_csv(&mut self, write_to_stdout: &mut (dyn FnMut(&str) + Send)) {
+        let records = Records {
+            state: &self.state,
+            ip_to_host: &self.ip_to_host,
+        };
+        let timestamp = Local::now().timestamp();
+
+        if let Some(csv_files) = self.csv_files.as_mut() {
+            if let Err(err) = csv_files.write(&records, timestamp) {
+                warn!("Failed to write CSV files: {err}");
+            }
+            return;
+        }
+
+        if !self.csv_header_written {
+            write_to_stdout(&csv_line(&csv_header(CSV_HEADER, self.opts.packets)));
+            self.csv_header_written = true;
+        }
+        for record in records.selected(&self.opts) {
+            write_to_stdout(&csv_line(&record.csv_fields(timestamp)));
+        }
+    }
+
+    fn output_structured(&self, write_to_stdout: &mut (dyn FnMut(&str) + Send)) {
+        let records = Records {
+            state: &self.state,
+            ip_to_host: &self.ip_to_host,
+        };
+        let timestamp = Local::now().timestamp();
+        match self.opts.format {
+            OutputFormat::Json => write_to_stdout(&records.to_json(&self.opts, timestamp)),
+            OutputFormat::Ndjson => {
+                for line in records.to_ndjson(&self.opts, timestamp) {
+                    write_to_stdout(&line);
+                }
+            }
+            OutputFormat::Text | OutputFormat::Csv => unreachable!("not a JSON format"),
+        }
+    }
+
+    fn output_plain_text(&self, write_to_stdout: &mut (dyn FnMut(&str) + Send)) {
         let state = &self.state;
         let ip_to_host = &self.ip_to_host;
         let local_time: DateTime<Local> = Local::now();
@@ -57,42 +151,61 @@ where
                                    no_traffic: &mut bool| {
             for (proc_info, process_network_data) in &state.processes {
                 write_to_stdout(&format!(
-                    "process: <{timestamp}> \"{}\" up/down Bps: {}/{} connections: {}",
+                    "process: <{timestamp}> \"{}\" up/down Bps: {}/{}{} connections: {}",
                     proc_info.name,
                     process_network_data.total_bytes_uploaded,
                     process_network_data.total_bytes_downloaded,
+                    raw_packet_rates(state.show_packets, process_network_data),
                     process_network_data.connection_count
                 ));
                 *no_traffic = false;
             }
         };
 
+        let output_container_data = |write_to_stdout: &mut (dyn FnMut(&str) + Send),
+                                     no_traffic: &mut bool| {
+            for (container, container_network_data) in &state.containers {
+                write_to_stdout(&format!(
+                    "container: <{timestamp}> \"{container}\" up/down Bps: {}/{}{} connections: {}",
+                    container_network_data.total_bytes_uploaded,
+                    container_network_data.total_bytes_downloaded,
+                    raw_packet_rates(state.show_packets, container_network_data),
+                    container_network_data.connection_count
+                ));
+                *no_traffic = false;
+            }
+        };
+
         let output_connections_data =
             |write_to_stdout: &mut (dyn FnMut(&str) + Send), no_traffic: &mut bool| {
                 for (connection, connection_network_data) in &state.connections {
                     write_to_stdout(&format!(
-                        "connection: <{timestamp}> {} up/down Bps: {}/{} process: \"{}\"",
+                        "connection: <{timestamp}> {} up/down Bps: {}/{}{} process: \"{}\"",
                         display_connection_string(
                             connection,
                             ip_to_host,
                             &connection_network_data.interface_name,
+                            connection_network_data.server_name.as_deref(),
                         ),
                         connection_network_data.total_bytes_uploaded,
                         connection_network_data.total_bytes_downloaded,
+                        raw_packet_rates(state.show_packets, connection_network_data),
                         connection_network_data.process_name
                     ));
                     *no_traffic = false;
                 }
             };
 
+        let server_names = state.remote_address_server_names();
         let output_adressess_data = |write_to_stdout: &mut (dyn FnMut(&str) + Send),
                                      no_traffic: &mut bool| {
             for (remote_address, remote_address_network_data) in &state.remote_addresses {
                 write_to_stdout(&format!(
-                    "remote_address: <{timestamp}> {} up/down Bps: {}/{} connections: {}",
-                    display_ip_or_host(*remote_address, ip_to_host),
+                    "remote_address: <{timestamp}> {} up/down Bps: {}/{}{} connections: {}",
+                    display_remote_address(*remote_address, &server_names, ip_to_host),
                     remote_address_network_data.total_bytes_uploaded,
                     remote_address_network_data.total_bytes_downloaded,
+                    raw_packet_rates(state.show_packets, remote_address_network_data),
                     remote_address_network_data.connection_count
                 ));
                 *no_traffic = false;
@@ -112,10 +225,34 @@ where
         if self.opts.addresses {
             output_adressess_data(write_to_stdout, &mut no_traffic);
         }
-        if !(self.opts.processes || self.opts.connections || self.opts.addresses) {
+        if self.opts.containers {
+            output_container_data(write_to_stdout, &mut no_traffic);
+        }
+        if !(self.opts.processes
+            || self.opts.connections
+            || self.opts.addresses
+            || self.opts.containers)
+        {
             output_process_data(write_to_stdout, &mut no_traffic);
             output_connections_data(write_to_stdout, &mut no_traffic);
             output_adressess_data(write_to_stdout, &mut no_traffic);
+            output_container_data(write_to_stdout, &mut no_traffic);
+        }
+
+        if state.show_dns {
+            // only the queries asked lately, rather than all those since start
+            for (query, data) in state.dns_queries.iter().filter(|(_, data)| data.rate > 0.0) {
+                write_to_stdout(&format!(
+                    "dns_query: <{timestamp}> \"{}\" {} process: \"{}\" response: {} queries: {} rate: {:.2}/s",
+                    query.name,
+                    query.record_type,
+                    query.proc_info.name,
+                    data.response_code.as_deref().unwrap_or("<NONE>"),
+                    data.total_queries,
+                    data.rate
+                ));
+                no_traffic = false;
+            }
         }
 
         // body2: In case no traffic is detected
@@ -128,23 +265,154 @@ where
     }
 
     pub fn draw(&mut self, paused: bool, elapsed_time: Duration, table_cycle_offset: usize) {
+        let header = HeaderDetails {
+            state: &self.state,
+            elapsed_time,
+            paused,
+        };
+        let footer = HelpText {
+            paused,
+            show_dns: self.state.show_dns,
+            process_details: self.process_details.is_some(),
+            search: self
+                .search
+                .as_ref()
+                .filter(|_| self.process_details.is_none()),
+        };
+        if let Some(proc_info) = &self.process_details {
+            let details = ProcessDetails {
+                header,
+                proc_info,
+                ip_to_host: &self.ip_to_host,
+                footer,
+            };
+            self.terminal
+                .draw(|frame| details.render(frame, frame.area()))
+                .unwrap();
+            return;
+        }
+
+        let children = self.get_tables_to_display();
+        let focused = children[table_cycle_offset % children.len()].kind();
+        let selection = self.selections.get_mut(&focused);
+        let chart = (self.show_chart && self.state.history_length > 0).then_some(RateChart {
+            title: "Total rate history",
+            history: Some(&self.state.history.total),
+            interval: self.state.averaging.interval,
+            unit_family: self.state.unit_family,
+        });
         let layout = Layout {
-            header: HeaderDetails {
-                state: &self.state,
-                elapsed_time,
-                paused,
-            },
-            children: self.get_tables_to_display(),
-            footer: HelpText {
-                paused,
-                show_dns: self.state.show_dns,
-            },
+            header,
+            chart,
+            children,
+            footer,
         };
         self.terminal
-            .draw(|frame| layout.render(frame, frame.area(), table_cycle_offset))
+            .draw(|frame| layout.render(frame, frame.area(), table_cycle_offset, selection))
             .unwrap();
     }
 
+    /// Handles the keys which select rows, sort the tables and show a single process,
+    /// returning whether to redraw.
+    ///
+    /// Rows are selected in the focused table, which is the first one laid out.
+    pub fn handle_key(&mut self, code: KeyCode, table_cycle_offset: usize) -> bool {
+        if self.process_details.is_some() {
+            match code {
+                KeyCode::Esc => {
+                    self.process_details = None;
+                    return true;
+                }
+                KeyCode::Char('s' | 'r') => (),
+                _ => return false,
+            }
+        }
+        let movement = match code {
+            KeyCode::Up | KeyCode::Char('k') => RowMovement::Up,
+            KeyCode::Down | KeyCode::Char('j') => RowMovement::Down,
+            KeyCode::PageUp => RowMovement::PageUp,
+            KeyCode::PageDown => RowMovement::PageDown,
+            KeyCode::Home => RowMovement::First,
+            KeyCode::End => RowMovement::Last,
+            KeyCode::Esc => {
+                if self.search.take().is_some() {
+                    return true;
+                }
+                let tables = self.get_tables_to_display();
+                let focused = tables[table_cycle_offset % tables.len()].kind();
+                return self.selections.remove(&focused).is_some();
+            }
+            KeyCode::Enter => {
+                let tables = self.get_tables_to_display();
+                if tables[table_cycle_offset % tables.len()].kind() != TableKind::Processes {
+                    return false;
+                }
+                let Some(row_id) = self
+                    .selections
+                    .get(&TableKind::Processes)
+                    .and_then(TableSelection::row_id)
+                else {
+                    return false;
+                };
+                self.process_details = self
+                    .state
+                    .processes
+                    .iter()
+                    .find(|(proc_info, _)| process_row_id(proc_info) == row_id)
+                    .map(|(proc_info, _)| proc_info.clone());
+                return self.process_details.is_some();
+            }
+            KeyCode::Char('s') => {
+                self.state.sort_order = self.state.sort_order.next_column();
+                self.state.sort_tables();
+                return true;
+            }
+            KeyCode::Char('/') => {
+                self.search.get_or_insert_default().editing = true;
+                return true;
+            }
+            KeyCode::Char('g') => {
+                self.show_chart = !self.show_chart;
+                return true;
+            }
+            KeyCode::Char('r') => {
+                self.state.sort_order = self.state.sort_order.reversed();
+                self.state.sort_tables();
+                return true;
+            }
+            _ => return false,
+        };
+        let tables = self.get_tables_to_display();
+        let focused = &tables[table_cycle_offset % tables.len()];
+        let selection = self.selections.entry(focused.kind()).or_default();
+        focused.move_selection(selection, movement);
+        true
+    }
+
+    pub fn is_editing_search(&self) -> bool {
+        self.search.as_ref().is_some_and(|search| search.editing)
+    }
+
+    /// Types a key into the search while it is being edited, returning whether to redraw.
+    pub fn handle_search_key(&mut self, code: KeyCode) -> bool {
+        let Some(search) = self.search.as_mut() else {
+            return false;
+        };
+        match code {
+            KeyCode::Enter => {
+                search.editing = false;
+                if search.query().is_empty() {
+                    self.search = None;
+                }
+            }
+            KeyCode::Esc => self.search = None,
+            KeyCode::Backspace => search.pop(),
+            KeyCode::Char(c) => search.push(c),
+            _ => return false,
+        }
+        true
+    }
+
     fn get_tables_to_display(&self) -> Vec<Table> {
         let opts = &self.opts;
         let mut children: Vec<Table> = Vec::new();
@@ -163,12 +431,28 @@ where
                 &self.ip_to_host,
             ));
         }
-        if !(opts.processes || opts.addresses || opts.connections) {
+        if opts.containers {
+            children.push(Table::create_containers_table(&self.state));
+        }
+        if !(opts.processes || opts.addresses || opts.connections || opts.containers) {
             children = vec![
                 Table::create_processes_table(&self.state),
                 Table::create_remote_addresses_table(&self.state, &self.ip_to_host),
                 Table::create_connections_table(&self.state, &self.ip_to_host),
             ];
+            // only take up space on hosts which run containers
+            if !self.state.containers.is_empty() {
+                children.push(Table::create_containers_table(&self.state));
+            }
+        }
+        if self.state.show_dns {
+            children.push(Table::create_dns_queries_table(&self.state));
+        }
+        if let Some(search) = &self.search {
+            let row_ids = search.row_ids(&self.state, &self.ip_to_host);
+            for table in &mut children {
+                table.retain_rows(row_ids.get(&table.kind()).unwrap_or(&HashSet::new()));
+            }
         }
         children
     }
@@ -181,12 +465,54 @@ where
         &mut self,
         connections_to_procs: HashMap<LocalSocket, ProcessInfo>,
         utilization: Utilization,
+        duration: Duration,
         ip_to_host: HashMap<IpAddr, String>,
     ) {
-        self.state.update(connections_to_procs, utilization);
+        self.state
+            .update(connections_to_procs, utilization, duration);
         self.ip_to_host.extend(ip_to_host);
     }
+    /// Counts a refresh taken while paused, without touching what is displayed.
+    pub fn update_totals(
+        &mut self,
+        connections_to_procs: &HashMap<LocalSocket, ProcessInfo>,
+        utilization: &Utilization,
+    ) {
+        self.state.update_totals(connections_to_procs, utilization);
+    }
+    /// Adds the latest refresh to the summary of a bounded run.
+    pub fn record_summary(&mut self) {
+        if let Some(summary) = self.summary.as_mut() {
+            summary.record(&self.state.latest, self.state.latest_duration);
+        }
+    }
+    pub fn render_metrics(&self) -> String {
+        render_metrics(&self.state.totals)
+    }
+    /// Whether the run is bounded, so that its refreshes are summarised.
+    pub fn has_summary(&self) -> bool {
+        self.summary.is_some()
+    }
+
+    /// Renders the summary of a bounded run.
+    pub fn summary(&self) -> Option<String> {
+        let summary = self.summary.as_ref()?;
+        Some(summary.render(self.state.unit_family, &self.ip_to_host))
+    }
     pub fn end(&mut self) {
         self.terminal.show_cursor().unwrap();
     }
 }
+
+/// The packet rates, which follow the byte rates in raw mode with `--packets`.
+fn raw_packet_rates(show_packets: bool, data: &impl Bandwidth) -> String {
+    if show_packets {
+        format!(
+            " up/down pps: {}/{}",
+            data.get_total_packets_uploaded(),
+            data.get_total_packets_downloaded()
+        )
+    } else {
+        String::new()
+    }
+}
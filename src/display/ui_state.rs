@@ -1,32 +1,168 @@
 use std::{
-    cmp,
+    cmp::{self, Ordering},
     collections::{HashMap, HashSet, VecDeque},
+    fmt,
     hash::Hash,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
 };
 
 use log::warn;
 
 use crate::{
+    cli::{FilterOpts, Smoothing},
     display::BandwidthUnitFamily,
-    network::{Connection, LocalSocket, Utilization},
-    os::ProcessInfo,
+    network::{dns::DnsQuestion, Connection, ConnectionInfo, LocalSocket, Utilization},
+    os::{Container, ProcessInfo},
 };
 
 static RECALL_LENGTH: usize = 5;
 static MAX_BANDWIDTH_ITEMS: usize = 1000;
+/// The fixed-point scale of the weights of an exponentially weighted average.
+static EWMA_WEIGHT_SCALE: f64 = 65536.0;
 
 pub trait Bandwidth {
     fn get_total_bytes_downloaded(&self) -> u128;
     fn get_total_bytes_uploaded(&self) -> u128;
+    fn get_total_packets_downloaded(&self) -> u128;
+    fn get_total_packets_uploaded(&self) -> u128;
+    fn get_connection_count(&self) -> u128;
     fn combine_bandwidth(&mut self, other: &Self);
-    fn divide_by(&mut self, amount: u128);
+    /// Turns bytes and packets summed over snapshots, each multiplied by its weight, into a
+    /// weighted mean.
+    ///
+    /// See [`Averaging::weights`].
+    fn average(&mut self, total_weight: u128);
+}
+
+/// How the rows of a table compare when sorted by name.
+pub trait NameOrd {
+    fn cmp_name(&self, other: &Self) -> Ordering;
+}
+
+impl NameOrd for ProcessInfo {
+    fn cmp_name(&self, other: &Self) -> Ordering {
+        (&self.name, self.pid).cmp(&(&other.name, other.pid))
+    }
+}
+
+impl NameOrd for Container {
+    fn cmp_name(&self, other: &Self) -> Ordering {
+        self.to_string().cmp(&other.to_string())
+    }
+}
+
+impl NameOrd for IpAddr {
+    fn cmp_name(&self, other: &Self) -> Ordering {
+        self.cmp(other)
+    }
+}
+
+impl NameOrd for Connection {
+    fn cmp_name(&self, other: &Self) -> Ordering {
+        self.cmp(other)
+    }
+}
+
+impl NameOrd for String {
+    fn cmp_name(&self, other: &Self) -> Ordering {
+        self.cmp(other)
+    }
+}
+
+/// What the rows of the tables are sorted by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortColumn {
+    Upload,
+    Download,
+    #[default]
+    Total,
+    Name,
+    Connections,
+}
+
+impl SortColumn {
+    /// The column after this one, when cycling through them.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Upload => Self::Download,
+            Self::Download => Self::Total,
+            Self::Total => Self::Name,
+            Self::Name => Self::Connections,
+            Self::Connections => Self::Upload,
+        }
+    }
+}
+
+impl fmt::Display for SortColumn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Upload => "upload",
+            Self::Download => "download",
+            Self::Total => "total",
+            Self::Name => "name",
+            Self::Connections => "connections",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The order of the rows of the tables, by default the most active first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SortOrder {
+    pub column: SortColumn,
+    pub ascending: bool,
+}
+
+impl SortOrder {
+    /// Sorts by the next column, in the direction that suits it: names from A to Z,
+    /// and numbers from the largest.
+    pub fn next_column(self) -> Self {
+        let column = self.column.next();
+        Self {
+            column,
+            ascending: column == SortColumn::Name,
+        }
+    }
+
+    pub fn reversed(self) -> Self {
+        Self {
+            ascending: !self.ascending,
+            ..self
+        }
+    }
+
+    fn compare<K, V>(&self, (a_key, a): &(K, V), (b_key, b): &(K, V)) -> Ordering
+    where
+        K: NameOrd,
+        V: Bandwidth,
+    {
+        let total = |b: &V| b.get_total_bytes_downloaded() + b.get_total_bytes_uploaded();
+        let ordering = match self.column {
+            SortColumn::Upload => a
+                .get_total_bytes_uploaded()
+                .cmp(&b.get_total_bytes_uploaded()),
+            SortColumn::Download => a
+                .get_total_bytes_downloaded()
+                .cmp(&b.get_total_bytes_downloaded()),
+            SortColumn::Total => total(a).cmp(&total(b)),
+            SortColumn::Name => a_key.cmp_name(b_key),
+            SortColumn::Connections => a.get_connection_count().cmp(&b.get_connection_count()),
+        };
+        if self.ascending {
+            ordering
+        } else {
+            ordering.reverse()
+        }
+    }
 }
 
 #[derive(Clone, Default)]
 pub struct NetworkData {
     pub total_bytes_downloaded: u128,
     pub total_bytes_uploaded: u128,
+    pub total_packets_downloaded: u128,
+    pub total_packets_uploaded: u128,
     pub connection_count: u128,
 }
 
@@ -34,8 +170,41 @@ pub struct NetworkData {
 pub struct ConnectionData {
     pub total_bytes_downloaded: u128,
     pub total_bytes_uploaded: u128,
+    pub total_packets_downloaded: u128,
+    pub total_packets_uploaded: u128,
     pub process_name: String,
+    pub pid: u32,
     pub interface_name: String,
+    /// The server name the client asked for, from TLS SNI or the HTTP `Host` header.
+    pub server_name: Option<String>,
+}
+
+/// A question asked of DNS, and the process which asked it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DnsQuery {
+    pub name: String,
+    pub record_type: String,
+    pub proc_info: ProcessInfo,
+}
+
+impl DnsQuery {
+    fn new(question: &DnsQuestion, proc_info: &ProcessInfo) -> Self {
+        DnsQuery {
+            name: question.name.clone(),
+            record_type: question.record_type.clone(),
+            proc_info: proc_info.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DnsQueryData {
+    /// Queries per second, averaged like the other rates.
+    pub rate: f64,
+    /// Queries since start.
+    pub total_queries: u128,
+    /// The code of the latest response, eg. `NXDOMAIN`.
+    pub response_code: Option<String>,
 }
 
 impl Bandwidth for NetworkData {
@@ -45,14 +214,27 @@ impl Bandwidth for NetworkData {
     fn get_total_bytes_uploaded(&self) -> u128 {
         self.total_bytes_uploaded
     }
+    fn get_total_packets_downloaded(&self) -> u128 {
+        self.total_packets_downloaded
+    }
+    fn get_total_packets_uploaded(&self) -> u128 {
+        self.total_packets_uploaded
+    }
+    fn get_connection_count(&self) -> u128 {
+        self.connection_count
+    }
     fn combine_bandwidth(&mut self, other: &NetworkData) {
         self.total_bytes_downloaded += other.get_total_bytes_downloaded();
         self.total_bytes_uploaded += other.get_total_bytes_uploaded();
+        self.total_packets_downloaded += other.get_total_packets_downloaded();
+        self.total_packets_uploaded += other.get_total_packets_uploaded();
         self.connection_count = other.connection_count;
     }
-    fn divide_by(&mut self, amount: u128) {
-        self.total_bytes_downloaded /= amount;
-        self.total_bytes_uploaded /= amount;
+    fn average(&mut self, total_weight: u128) {
+        self.total_bytes_downloaded /= total_weight;
+        self.total_bytes_uploaded /= total_weight;
+        self.total_packets_downloaded /= total_weight;
+        self.total_packets_uploaded /= total_weight;
     }
 }
 
@@ -63,167 +245,643 @@ impl Bandwidth for ConnectionData {
     fn get_total_bytes_uploaded(&self) -> u128 {
         self.total_bytes_uploaded
     }
+    fn get_total_packets_downloaded(&self) -> u128 {
+        self.total_packets_downloaded
+    }
+    fn get_total_packets_uploaded(&self) -> u128 {
+        self.total_packets_uploaded
+    }
+    fn get_connection_count(&self) -> u128 {
+        1
+    }
     fn combine_bandwidth(&mut self, other: &ConnectionData) {
         self.total_bytes_downloaded += other.get_total_bytes_downloaded();
         self.total_bytes_uploaded += other.get_total_bytes_uploaded();
+        self.total_packets_downloaded += other.get_total_packets_downloaded();
+        self.total_packets_uploaded += other.get_total_packets_uploaded();
+        if self.server_name.is_none() {
+            self.server_name.clone_from(&other.server_name);
+        }
+    }
+    fn average(&mut self, total_weight: u128) {
+        self.total_bytes_downloaded /= total_weight;
+        self.total_bytes_uploaded /= total_weight;
+        self.total_packets_downloaded /= total_weight;
+        self.total_packets_uploaded /= total_weight;
+    }
+}
+
+/// How the displayed rates are averaged over the latest snapshots.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Averaging {
+    pub smoothing: Smoothing,
+    /// How many snapshots are averaged, unless only the latest one is shown.
+    pub window: usize,
+    /// How long each snapshot is meant to last; the rates are divided by how long they did.
+    pub interval: Duration,
+}
+
+impl Default for Averaging {
+    fn default() -> Self {
+        Self {
+            smoothing: Smoothing::Sma,
+            window: RECALL_LENGTH,
+            interval: Duration::from_secs(1),
+        }
+    }
+}
+
+impl fmt::Display for Averaging {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let span = display_seconds(self.interval * self.window as u32);
+        match self.smoothing {
+            Smoothing::Instantaneous => write!(f, "instantaneous"),
+            Smoothing::Sma => write!(f, "SMA over {span}"),
+            Smoothing::Ewma => write!(f, "EWMA over {span}"),
+        }
+    }
+}
+
+/// Formats a duration in seconds, to the millisecond, eg. `0.3s` or `300s`.
+pub fn display_seconds(duration: Duration) -> String {
+    let seconds = format!("{:.3}", duration.as_secs_f64());
+    format!("{}s", seconds.trim_end_matches('0').trim_end_matches('.'))
+}
+
+impl Averaging {
+    /// How many snapshots are kept.
+    fn snapshots(&self) -> usize {
+        match self.smoothing {
+            Smoothing::Instantaneous => 1,
+            Smoothing::Sma | Smoothing::Ewma => self.window.max(1),
+        }
+    }
+
+    /// The weight of each kept snapshot, newest first.
+    fn weights(&self) -> Vec<u128> {
+        // the usual smoothing factor of an EWMA spanning the window
+        let alpha = 2.0 / (self.window as f64 + 1.0);
+        (0..self.snapshots())
+            .map(|age| match self.smoothing {
+                Smoothing::Instantaneous | Smoothing::Sma => 1,
+                Smoothing::Ewma => {
+                    (EWMA_WEIGHT_SCALE * (1.0 - alpha).powi(age as i32)).round() as u128
+                }
+            })
+            .collect()
+    }
+}
+
+/// Monotonic byte and packet counters since start, as opposed to the averaged rates of [`UIState`].
+#[derive(Clone, Default)]
+pub struct TotalCounters {
+    pub processes: HashMap<ProcessInfo, NetworkData>,
+    pub remote_addresses: HashMap<IpAddr, NetworkData>,
+    pub connections: HashMap<Connection, ConnectionData>,
+    pub interfaces: HashMap<String, NetworkData>,
+    /// What the entries pruned from each table had counted.
+    pub pruned: PrunedCounters,
+}
+
+/// The counters of entries pruned from [`TotalCounters`], so that the counters of a table keep
+/// adding up to the traffic since start. They are `None` until an entry is pruned.
+#[derive(Clone, Default)]
+pub struct PrunedCounters {
+    pub processes: Option<NetworkData>,
+    pub remote_addresses: Option<NetworkData>,
+    pub interfaces: Option<NetworkData>,
+}
+
+impl TotalCounters {
+    fn add(
+        &mut self,
+        proc_info: &ProcessInfo,
+        connection: &Connection,
+        connection_info: &ConnectionInfo,
+    ) {
+        let entries = [
+            self.processes.entry(proc_info.clone()).or_default(),
+            self.remote_addresses
+                .entry(connection.remote_socket.ip)
+                .or_default(),
+            self.interfaces
+                .entry(connection_info.interface_name.clone())
+                .or_default(),
+        ];
+        for data in entries {
+            data.total_bytes_downloaded += connection_info.total_bytes_downloaded;
+            data.total_bytes_uploaded += connection_info.total_bytes_uploaded;
+            data.total_packets_downloaded += connection_info.total_packets_downloaded;
+            data.total_packets_uploaded += connection_info.total_packets_uploaded;
+        }
+        let data = self
+            .connections
+            .entry(*connection)
+            .or_insert_with(|| ConnectionData {
+                process_name: proc_info.name.clone(),
+                pid: proc_info.pid,
+                interface_name: connection_info.interface_name.clone(),
+                server_name: connection_info.server_name.clone(),
+                ..Default::default()
+            });
+        data.total_bytes_downloaded += connection_info.total_bytes_downloaded;
+        data.total_bytes_uploaded += connection_info.total_bytes_uploaded;
+        data.total_packets_downloaded += connection_info.total_packets_downloaded;
+        data.total_packets_uploaded += connection_info.total_packets_uploaded;
+    }
+
+    /// Adds the counters of the latest refresh.
+    fn merge(&mut self, latest: Self) {
+        merge_bandwidth(&mut self.processes, latest.processes);
+        merge_bandwidth(&mut self.remote_addresses, latest.remote_addresses);
+        merge_bandwidth(&mut self.connections, latest.connections);
+        merge_bandwidth(&mut self.interfaces, latest.interfaces);
+    }
+
+    /// Folds the least active entries into [`Self::pruned`], so that the number of counters stays
+    /// bounded. Entries active in `latest` are only pruned when there are too many of them, as a
+    /// counter that reappears starts over from 0.
+    fn prune(&mut self, latest: &Self) {
+        prune_counters(
+            &mut self.processes,
+            &latest.processes,
+            &mut self.pruned.processes,
+        );
+        prune_counters(
+            &mut self.remote_addresses,
+            &latest.remote_addresses,
+            &mut self.pruned.remote_addresses,
+        );
+        prune_counters(
+            &mut self.interfaces,
+            &latest.interfaces,
+            &mut self.pruned.interfaces,
+        );
+        // connections are not exported, so their counters need not add up
+        prune_counters(&mut self.connections, &latest.connections, &mut None);
+    }
+}
+
+fn prune_counters<K, V>(
+    counters: &mut HashMap<K, V>,
+    latest: &HashMap<K, V>,
+    pruned: &mut Option<NetworkData>,
+) where
+    K: Eq + Hash + Clone,
+    V: Bandwidth,
+{
+    if counters.len() <= MAX_BANDWIDTH_ITEMS {
+        return;
+    }
+    let mut keys = counters
+        .iter()
+        .map(|(key, data)| {
+            let total = data.get_total_bytes_downloaded() + data.get_total_bytes_uploaded();
+            (latest.contains_key(key), total, key.clone())
+        })
+        .collect::<Vec<_>>();
+    // idle entries first, then the least active
+    keys.sort_unstable_by_key(|&(active, total, _)| (active, total));
+    for (_, _, key) in keys.drain(..counters.len() - MAX_BANDWIDTH_ITEMS) {
+        if let Some(data) = counters.remove(&key) {
+            let pruned = pruned.get_or_insert_with(NetworkData::default);
+            pruned.total_bytes_downloaded += data.get_total_bytes_downloaded();
+            pruned.total_bytes_uploaded += data.get_total_bytes_uploaded();
+            pruned.total_packets_downloaded += data.get_total_packets_downloaded();
+            pruned.total_packets_uploaded += data.get_total_packets_uploaded();
+        }
+    }
+}
+
+/// The upload and download rates of each refresh, oldest first.
+#[derive(Clone, Debug, Default)]
+pub struct RateHistory {
+    samples: VecDeque<(u128, u128)>,
+}
+
+impl RateHistory {
+    pub(crate) fn push(&mut self, data: &NetworkData, length: usize) {
+        self.samples
+            .push_back((data.total_bytes_uploaded, data.total_bytes_downloaded));
+        while self.samples.len() > length {
+            self.samples.pop_front();
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.samples.iter().all(|&sample| sample == (0, 0))
+    }
+
+    /// The `(uploaded, downloaded)` rates, oldest first.
+    pub fn samples(&self) -> impl ExactSizeIterator<Item = (u128, u128)> + '_ {
+        self.samples.iter().copied()
+    }
+}
+
+/// Ring buffers of the rates of each refresh, unlike the rates of [`UIState`] which are averaged
+/// over the last few refreshes.
+#[derive(Default)]
+pub struct RateHistories {
+    /// The processes active within the history.
+    pub processes: HashMap<ProcessInfo, RateHistory>,
+    /// The remote addresses active within the history.
+    pub remote_addresses: HashMap<IpAddr, RateHistory>,
+    pub total: RateHistory,
+}
+
+impl RateHistories {
+    /// Appends the rates of the latest refresh, which lasted `interval`, keeping `length` samples
+    /// of each history.
+    fn record(&mut self, latest: &TotalCounters, length: usize, interval: Duration) {
+        record_each(&mut self.processes, &latest.processes, length, interval);
+        record_each(
+            &mut self.remote_addresses,
+            &latest.remote_addresses,
+            length,
+            interval,
+        );
+        let mut total = NetworkData::default();
+        for data in latest.interfaces.values() {
+            total.combine_bandwidth(data);
+        }
+        self.total.push(&per_second(&total, interval), length);
+    }
+}
+
+/// Appends to the history of each entry, and forgets entries which have been idle for the
+/// whole history.
+fn record_each<K>(
+    histories: &mut HashMap<K, RateHistory>,
+    latest: &HashMap<K, NetworkData>,
+    length: usize,
+    interval: Duration,
+) where
+    K: Eq + Hash + Clone,
+{
+    let idle = NetworkData::default();
+    for (key, history) in histories.iter_mut() {
+        if !latest.contains_key(key) {
+            history.push(&idle, length);
+        }
+    }
+    for (key, data) in latest {
+        histories
+            .entry(key.clone())
+            .or_default()
+            .push(&per_second(data, interval), length);
     }
-    fn divide_by(&mut self, amount: u128) {
-        self.total_bytes_downloaded /= amount;
-        self.total_bytes_uploaded /= amount;
+    histories.retain(|_, history| !history.is_idle());
+}
+
+/// The rate of the bytes and packets transferred in `interval`.
+fn per_second(data: &NetworkData, interval: Duration) -> NetworkData {
+    let interval = interval.as_micros().max(1);
+    NetworkData {
+        total_bytes_downloaded: data.total_bytes_downloaded * 1_000_000 / interval,
+        total_bytes_uploaded: data.total_bytes_uploaded * 1_000_000 / interval,
+        total_packets_downloaded: data.total_packets_downloaded * 1_000_000 / interval,
+        total_packets_uploaded: data.total_packets_uploaded * 1_000_000 / interval,
+        connection_count: data.connection_count,
     }
 }
 
 pub struct UtilizationData {
     connections_to_procs: HashMap<LocalSocket, ProcessInfo>,
     network_utilization: Utilization,
+    /// How long the snapshot actually lasted, which is shorter than the interval when refreshed
+    /// early, eg. by a keypress.
+    duration: Duration,
 }
 
 #[derive(Default)]
 pub struct UIState {
     /// The interface name in single-interface mode. `None` means all interfaces.
     pub interface_name: Option<String>,
+    /// The `--filter` expression, if any.
+    pub capture_filter: Option<String>,
     pub processes: Vec<(ProcessInfo, NetworkData)>,
+    pub containers: Vec<(Container, NetworkData)>,
     pub remote_addresses: Vec<(IpAddr, NetworkData)>,
     pub connections: Vec<(Connection, ConnectionData)>,
     pub total_bytes_downloaded: u128,
     pub total_bytes_uploaded: u128,
     pub cumulative_mode: bool,
     pub show_dns: bool,
+    /// Whether packet rates are shown alongside the byte rates, with `--packets`.
+    pub show_packets: bool,
     pub unit_family: BandwidthUnitFamily,
+    pub sort_order: SortOrder,
+    pub averaging: Averaging,
     pub utilization_data: VecDeque<UtilizationData>,
     pub processes_map: HashMap<ProcessInfo, NetworkData>,
+    pub containers_map: HashMap<Container, NetworkData>,
     pub remote_addresses_map: HashMap<IpAddr, NetworkData>,
     pub connections_map: HashMap<Connection, ConnectionData>,
+    pub totals: TotalCounters,
+    /// The bytes transferred in the latest refresh.
+    pub latest: TotalCounters,
+    /// How long the latest refresh lasted.
+    pub latest_duration: Duration,
+    /// How many refreshes [`Self::history`] keeps.
+    pub history_length: usize,
+    pub history: RateHistories,
+    /// Which connections are counted, from the `--process`, `--port`, `--remote` etc. options.
+    pub traffic_filter: FilterOpts,
+    /// The DNS questions asked since start, with `--show-dns`, the most frequent first.
+    pub dns_queries: Vec<(DnsQuery, DnsQueryData)>,
+    pub dns_queries_map: HashMap<DnsQuery, DnsQueryData>,
     /// Used for reducing logging noise.
     known_orphan_sockets: VecDeque<LocalSocket>,
+    /// The server names of connections, which are only sent when they start, so they are kept
+    /// for as long as the connections are shown.
+    server_names: HashMap<Connection, String>,
 }
 
 impl UIState {
+    /// Adds a snapshot of the traffic, which lasted `duration`.
     pub fn update(
         &mut self,
         connections_to_procs: HashMap<LocalSocket, ProcessInfo>,
         network_utilization: Utilization,
+        duration: Duration,
     ) {
         self.utilization_data.push_back(UtilizationData {
             connections_to_procs,
             network_utilization,
+            duration,
         });
-        if self.utilization_data.len() > RECALL_LENGTH {
+        while self.utilization_data.len() > self.averaging.snapshots() {
             self.utilization_data.pop_front();
         }
+        let weights = self.averaging.weights();
+        // the time the snapshots cover, each multiplied by its weight
+        let weighted_micros = self
+            .utilization_data
+            .iter()
+            .rev()
+            .zip(&weights)
+            .map(|(state, weight)| state.duration.as_micros().max(1) * weight)
+            .sum::<u128>()
+            .max(1);
+        // rates are per second, while data in cumulative mode adds up the bytes of each refresh
+        let (period, total_weight) = if self.cumulative_mode {
+            let total_weight = weights.iter().take(self.utilization_data.len()).sum();
+            (1, total_weight)
+        } else {
+            (1_000_000, weighted_micros)
+        };
         let mut processes: HashMap<ProcessInfo, NetworkData> = HashMap::new();
+        let mut containers: HashMap<Container, NetworkData> = HashMap::new();
         let mut remote_addresses: HashMap<IpAddr, NetworkData> = HashMap::new();
         let mut connections: HashMap<Connection, ConnectionData> = HashMap::new();
         let mut total_bytes_downloaded: u128 = 0;
         let mut total_bytes_uploaded: u128 = 0;
 
+        let mut latest = TotalCounters::default();
+        // queries weighted like bytes, to average their rates
+        let mut dns_query_weights: HashMap<DnsQuery, u128> = HashMap::new();
+
         let mut seen_connections = HashSet::new();
-        for state in self.utilization_data.iter().rev() {
+        for ((i, state), weight) in self.utilization_data.iter().rev().enumerate().zip(&weights) {
+            // only the newest snapshot has not been counted yet
+            let is_newest = i == 0;
             let connections_to_procs = &state.connections_to_procs;
             let network_utilization = &state.network_utilization;
 
             for (connection, connection_info) in &network_utilization.connections {
+                let local_socket = connection.local_socket;
+                let proc_info = get_proc_info(connections_to_procs, &local_socket);
+
+                // only log each orphan connection once; no socket owns ICMP and the like
+                if proc_info.is_none()
+                    && local_socket.protocol.has_ports()
+                    && !self.known_orphan_sockets.contains(&local_socket)
+                {
+                    // newer connections go in the front so that searches are faster
+                    // basically recency bias
+                    self.known_orphan_sockets.push_front(local_socket);
+                    self.known_orphan_sockets.truncate(10_000); // arbitrary maximum backlog
+
+                    match connections_to_procs
+                        .iter()
+                        .find(|(&LocalSocket { port, protocol, .. }, _)| {
+                            port == local_socket.port && protocol == local_socket.protocol
+                        })
+                        .and_then(|(local_conn_lookalike, info)| {
+                            network_utilization
+                                .connections
+                                .keys()
+                                .find(|conn| &conn.local_socket == local_conn_lookalike)
+                                .map(|conn| (conn, info))
+                        }) {
+                        Some((lookalike, proc_info)) => {
+                            warn!(
+                                r#""{0}" owns a similar looking connection, but its local ip doesn't match."#,
+                                proc_info.name
+                            );
+                            warn!("Looking for: {connection:?}; found: {lookalike:?}");
+                        }
+                        None => {
+                            warn!("Cannot determine which process owns {connection:?}");
+                        }
+                    };
+                }
+
+                let proc_info = proc_info
+                    .cloned()
+                    .unwrap_or_else(|| ProcessInfo::new("<UNKNOWN>", 0));
+                if !self.traffic_filter.matches(&proc_info, connection) {
+                    continue;
+                }
+
                 let connection_previously_seen = !seen_connections.insert(connection);
+                let downloaded = connection_info.total_bytes_downloaded * weight * period;
+                let uploaded = connection_info.total_bytes_uploaded * weight * period;
+                let packets_downloaded = connection_info.total_packets_downloaded * weight * period;
+                let packets_uploaded = connection_info.total_packets_uploaded * weight * period;
                 let connection_data = connections.entry(*connection).or_default();
                 let data_for_remote_address = remote_addresses
                     .entry(connection.remote_socket.ip)
                     .or_default();
-                connection_data.total_bytes_downloaded += connection_info.total_bytes_downloaded;
-                connection_data.total_bytes_uploaded += connection_info.total_bytes_uploaded;
+                connection_data.total_bytes_downloaded += downloaded;
+                connection_data.total_bytes_uploaded += uploaded;
+                connection_data.total_packets_downloaded += packets_downloaded;
+                connection_data.total_packets_uploaded += packets_uploaded;
                 connection_data
                     .interface_name
                     .clone_from(&connection_info.interface_name);
-                data_for_remote_address.total_bytes_downloaded +=
-                    connection_info.total_bytes_downloaded;
-                data_for_remote_address.total_bytes_uploaded +=
-                    connection_info.total_bytes_uploaded;
+                connection_data.process_name.clone_from(&proc_info.name);
+                connection_data.pid = proc_info.pid;
+                if let Some(server_name) = &connection_info.server_name {
+                    self.server_names.insert(*connection, server_name.clone());
+                }
+                connection_data.server_name = self.server_names.get(connection).cloned();
+                data_for_remote_address.total_bytes_downloaded += downloaded;
+                data_for_remote_address.total_bytes_uploaded += uploaded;
+                data_for_remote_address.total_packets_downloaded += packets_downloaded;
+                data_for_remote_address.total_packets_uploaded += packets_uploaded;
                 if !connection_previously_seen {
                     data_for_remote_address.connection_count += 1;
                 }
-                total_bytes_downloaded += connection_info.total_bytes_downloaded;
-                total_bytes_uploaded += connection_info.total_bytes_uploaded;
-
-                let data_for_process = {
-                    let local_socket = connection.local_socket;
-                    let proc_info = get_proc_info(connections_to_procs, &local_socket);
-
-                    // only log each orphan connection once
-                    if proc_info.is_none() && !self.known_orphan_sockets.contains(&local_socket) {
-                        // newer connections go in the front so that searches are faster
-                        // basically recency bias
-                        self.known_orphan_sockets.push_front(local_socket);
-                        self.known_orphan_sockets.truncate(10_000); // arbitrary maximum backlog
-
-                        match connections_to_procs
-                            .iter()
-                            .find(|(&LocalSocket { port, protocol, .. }, _)| {
-                                port == local_socket.port && protocol == local_socket.protocol
-                            })
-                            .and_then(|(local_conn_lookalike, info)| {
-                                network_utilization
-                                    .connections
-                                    .keys()
-                                    .find(|conn| &conn.local_socket == local_conn_lookalike)
-                                    .map(|conn| (conn, info))
-                            }) {
-                            Some((lookalike, proc_info)) => {
-                                warn!(
-                                    r#""{0}" owns a similar looking connection, but its local ip doesn't match."#,
-                                    proc_info.name
-                                );
-                                warn!("Looking for: {connection:?}; found: {lookalike:?}");
-                            }
-                            None => {
-                                warn!("Cannot determine which process owns {connection:?}");
-                            }
-                        };
-                    }
+                total_bytes_downloaded += downloaded;
+                total_bytes_uploaded += uploaded;
 
-                    let proc_info = proc_info
-                        .cloned()
-                        .unwrap_or_else(|| ProcessInfo::new("<UNKNOWN>", 0));
-                    connection_data.process_name.clone_from(&proc_info.name);
-                    processes.entry(proc_info).or_default()
-                };
-
-                data_for_process.total_bytes_downloaded += connection_info.total_bytes_downloaded;
-                data_for_process.total_bytes_uploaded += connection_info.total_bytes_uploaded;
+                if is_newest {
+                    latest.add(&proc_info, connection, connection_info);
+                }
+                for (question, exchange) in &connection_info.dns_questions {
+                    let query = DnsQuery::new(question, &proc_info);
+                    if exchange.queries > 0 {
+                        *dns_query_weights.entry(query.clone()).or_default() +=
+                            weight * exchange.queries;
+                    }
+                    if is_newest {
+                        let data = self.dns_queries_map.entry(query).or_default();
+                        data.total_queries += exchange.queries;
+                        if exchange.response_code.is_some() {
+                            data.response_code.clone_from(&exchange.response_code);
+                        }
+                    }
+                }
+                if let Some(container) = &proc_info.container {
+                    let data_for_container = containers.entry(container.clone()).or_default();
+                    data_for_container.total_bytes_downloaded += downloaded;
+                    data_for_container.total_bytes_uploaded += uploaded;
+                    data_for_container.total_packets_downloaded += packets_downloaded;
+                    data_for_container.total_packets_uploaded += packets_uploaded;
+                    if !connection_previously_seen {
+                        data_for_container.connection_count += 1;
+                    }
+                }
+                let data_for_process = processes.entry(proc_info).or_default();
+                data_for_process.total_bytes_downloaded += downloaded;
+                data_for_process.total_bytes_uploaded += uploaded;
+                data_for_process.total_packets_downloaded += packets_downloaded;
+                data_for_process.total_packets_uploaded += packets_uploaded;
                 if !connection_previously_seen {
                     data_for_process.connection_count += 1;
                 }
             }
         }
-        let divide_by = if self.utilization_data.is_empty() {
-            1_u128
-        } else {
-            self.utilization_data.len() as u128
-        };
         for network_data in processes.values_mut() {
-            network_data.divide_by(divide_by)
+            network_data.average(total_weight)
+        }
+        for network_data in containers.values_mut() {
+            network_data.average(total_weight)
         }
         for network_data in remote_addresses.values_mut() {
-            network_data.divide_by(divide_by)
+            network_data.average(total_weight)
         }
         for connection_data in connections.values_mut() {
-            connection_data.divide_by(divide_by)
+            connection_data.average(total_weight)
+        }
+        for data in self.dns_queries_map.values_mut() {
+            data.rate = 0.0;
+        }
+        for (query, weight) in dns_query_weights {
+            self.dns_queries_map.entry(query).or_default().rate =
+                (weight * 1_000_000) as f64 / weighted_micros as f64;
         }
 
+        self.history.record(&latest, self.history_length, duration);
+        self.totals.merge(latest.clone());
+        self.latest = latest;
+        self.latest_duration = duration;
+
         if self.cumulative_mode {
             merge_bandwidth(&mut self.processes_map, processes);
+            merge_bandwidth(&mut self.containers_map, containers);
             merge_bandwidth(&mut self.remote_addresses_map, remote_addresses);
             merge_bandwidth(&mut self.connections_map, connections);
-            self.total_bytes_downloaded += total_bytes_downloaded / divide_by;
-            self.total_bytes_uploaded += total_bytes_uploaded / divide_by;
+            self.total_bytes_downloaded += total_bytes_downloaded / total_weight;
+            self.total_bytes_uploaded += total_bytes_uploaded / total_weight;
         } else {
             self.processes_map = processes;
+            self.containers_map = containers;
             self.remote_addresses_map = remote_addresses;
             self.connections_map = connections;
-            self.total_bytes_downloaded = total_bytes_downloaded / divide_by;
-            self.total_bytes_uploaded = total_bytes_uploaded / divide_by;
+            self.total_bytes_downloaded = total_bytes_downloaded / total_weight;
+            self.total_bytes_uploaded = total_bytes_uploaded / total_weight;
+        }
+        self.totals.prune(&self.latest);
+        let (shown, totals) = (&self.connections_map, &self.totals.connections);
+        self.server_names.retain(|connection, _| {
+            shown.contains_key(connection) || totals.contains_key(connection)
+        });
+        self.sort_tables();
+        self.dns_queries = sort_and_prune_dns_queries(&mut self.dns_queries_map);
+    }
+
+    /// Adds a snapshot taken while paused to the monotonic [`Self::totals`] only, so that the
+    /// exported counters keep up with the traffic while the displayed rates are frozen.
+    pub fn update_totals(
+        &mut self,
+        connections_to_procs: &HashMap<LocalSocket, ProcessInfo>,
+        network_utilization: &Utilization,
+    ) {
+        let mut latest = TotalCounters::default();
+        for (connection, connection_info) in &network_utilization.connections {
+            let proc_info = get_proc_info(connections_to_procs, &connection.local_socket)
+                .cloned()
+                .unwrap_or_else(|| ProcessInfo::new("<UNKNOWN>", 0));
+            if self.traffic_filter.matches(&proc_info, connection) {
+                latest.add(&proc_info, connection, connection_info);
+            }
+        }
+        self.totals.merge(latest.clone());
+        self.totals.prune(&latest);
+    }
+
+    /// The server names of the connections to each remote address, sorted.
+    pub fn remote_address_server_names(&self) -> HashMap<IpAddr, Vec<&str>> {
+        let mut server_names: HashMap<IpAddr, Vec<&str>> = HashMap::new();
+        for (connection, data) in &self.connections {
+            if let Some(server_name) = &data.server_name {
+                server_names
+                    .entry(connection.remote_socket.ip)
+                    .or_default()
+                    .push(server_name);
+            }
         }
-        self.processes = sort_and_prune(&mut self.processes_map);
-        self.remote_addresses = sort_and_prune(&mut self.remote_addresses_map);
-        self.connections = sort_and_prune(&mut self.connections_map);
+        for names in server_names.values_mut() {
+            names.sort_unstable();
+            names.dedup();
+        }
+        server_names
+    }
+
+    /// The connections of a process, in the order of [`Self::connections`].
+    pub fn process_connections<'a>(
+        &'a self,
+        proc_info: &'a ProcessInfo,
+    ) -> impl Iterator<Item = &'a (Connection, ConnectionData)> {
+        self.connections
+            .iter()
+            .filter(|(_, data)| data.pid == proc_info.pid && data.process_name == proc_info.name)
+    }
+
+    /// The remote addresses a process talks to, aggregated from its connections.
+    pub fn process_remote_addresses(&self, proc_info: &ProcessInfo) -> Vec<(IpAddr, NetworkData)> {
+        let mut remote_addresses: HashMap<IpAddr, NetworkData> = HashMap::new();
+        for (connection, connection_data) in self.process_connections(proc_info) {
+            let data = remote_addresses
+                .entry(connection.remote_socket.ip)
+                .or_default();
+            data.total_bytes_downloaded += connection_data.total_bytes_downloaded;
+            data.total_bytes_uploaded += connection_data.total_bytes_uploaded;
+            data.total_packets_downloaded += connection_data.total_packets_downloaded;
+            data.total_packets_uploaded += connection_data.total_packets_uploaded;
+            data.connection_count += 1;
+        }
+        sort_and_prune(&mut remote_addresses, self.sort_order)
+    }
+
+    /// Orders the rows of the tables by [`Self::sort_order`], dropping the least active ones.
+    pub fn sort_tables(&mut self) {
+        self.processes = sort_and_prune(&mut self.processes_map, self.sort_order);
+        self.containers = sort_and_prune(&mut self.containers_map, self.sort_order);
+        self.remote_addresses = sort_and_prune(&mut self.remote_addresses_map, self.sort_order);
+        self.connections = sort_and_prune(&mut self.connections_map, self.sort_order);
     }
 }
 
@@ -260,6 +918,27 @@ fn get_proc_info<'a>(
         })
 }
 
+/// Sorts the most frequent queries first, dropping the least frequent ones.
+fn sort_and_prune_dns_queries(
+    map: &mut HashMap<DnsQuery, DnsQueryData>,
+) -> Vec<(DnsQuery, DnsQueryData)> {
+    let mut dns_queries = Vec::from_iter(map.clone());
+    dns_queries.sort_by(|(a_query, a), (b_query, b)| {
+        (b.rate.total_cmp(&a.rate))
+            .then_with(|| b.total_queries.cmp(&a.total_queries))
+            .then_with(|| {
+                (&a_query.name, &a_query.record_type).cmp(&(&b_query.name, &b_query.record_type))
+            })
+            .then_with(|| a_query.proc_info.cmp_name(&b_query.proc_info))
+    });
+    if dns_queries.len() > MAX_BANDWIDTH_ITEMS {
+        for (query, _) in dns_queries.drain(MAX_BANDWIDTH_ITEMS..) {
+            map.remove(&query);
+        }
+    }
+    dns_queries
+}
+
 fn merge_bandwidth<K, V>(self_map: &mut HashMap<K, V>, other_map: HashMap<K, V>)
 where
     K: Eq + Hash,
@@ -273,12 +952,13 @@ where
     }
 }
 
-fn sort_and_prune<K, V>(map: &mut HashMap<K, V>) -> Vec<(K, V)>
+fn sort_and_prune<K, V>(map: &mut HashMap<K, V>, order: SortOrder) -> Vec<(K, V)>
 where
-    K: Eq + Hash + Clone,
+    K: Eq + Hash + Clone + NameOrd,
     V: Bandwidth + Clone,
 {
     let mut bandwidth_list = Vec::from_iter(map.clone());
+    // the least active entries are pruned, whichever order they are shown in
     bandwidth_list.sort_by_key(|(_, b)| {
         cmp::Reverse(b.get_total_bytes_downloaded() + b.get_total_bytes_uploaded())
     });
@@ -289,5 +969,428 @@ where
         }
     }
 
+    // the sort is stable, so ties stay ordered by activity
+    bandwidth_list.sort_by(|a, b| order.compare(a, b));
     bandwidth_list
 }
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    use crate::network::Protocol;
+
+    fn order(column: SortColumn, ascending: bool) -> SortOrder {
+        SortOrder { column, ascending }
+    }
+
+    #[rstest]
+    #[case(SortOrder::default(), ["b", "c", "a"])]
+    #[case(order(SortColumn::Total, true), ["a", "c", "b"])]
+    #[case(order(SortColumn::Upload, false), ["b", "a", "c"])]
+    #[case(order(SortColumn::Download, false), ["c", "a", "b"])]
+    #[case(order(SortColumn::Name, true), ["a", "b", "c"])]
+    #[case(order(SortColumn::Connections, false), ["a", "c", "b"])]
+    fn sorts_rows(#[case] order: SortOrder, #[case] expected: [&str; 3]) {
+        let data = |up, down, connection_count| NetworkData {
+            total_bytes_uploaded: up,
+            total_bytes_downloaded: down,
+            connection_count,
+            ..Default::default()
+        };
+        let mut map = HashMap::from([
+            (ProcessInfo::new("a", 1), data(1, 1, 3)),
+            (ProcessInfo::new("b", 2), data(10, 0, 1)),
+            (ProcessInfo::new("c", 3), data(0, 5, 2)),
+        ]);
+
+        let names = sort_and_prune(&mut map, order)
+            .into_iter()
+            .map(|(proc_info, _)| proc_info.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, expected);
+    }
+
+    #[test]
+    fn prunes_least_active_whatever_the_order() {
+        let mut map = (0..=MAX_BANDWIDTH_ITEMS as u128)
+            .map(|i| {
+                let data = NetworkData {
+                    total_bytes_downloaded: i,
+                    ..Default::default()
+                };
+                (format!("{i:04}"), data)
+            })
+            .collect::<HashMap<_, _>>();
+
+        let sorted = sort_and_prune(&mut map, order(SortColumn::Name, true));
+        assert_eq!(sorted[0].0, "0000");
+        assert_eq!(map.len(), MAX_BANDWIDTH_ITEMS);
+        assert!(!map.contains_key("0000"));
+    }
+
+    #[test]
+    fn folds_idle_counters_into_the_pruned_ones() {
+        let data = |downloaded| NetworkData {
+            total_bytes_downloaded: downloaded,
+            total_packets_downloaded: 1,
+            ..Default::default()
+        };
+        let mut counters = (0..=MAX_BANDWIDTH_ITEMS as u128)
+            .map(|i| (format!("{i:04}"), data(i)))
+            .collect::<HashMap<_, _>>();
+        // the least active counter is still counting, so the next one goes instead
+        let latest = HashMap::from([("0000".to_string(), data(0))]);
+        let mut pruned = None;
+
+        prune_counters(&mut counters, &latest, &mut pruned);
+        assert_eq!(counters.len(), MAX_BANDWIDTH_ITEMS);
+        assert!(counters.contains_key("0000"));
+        assert!(!counters.contains_key("0001"));
+        let pruned =
+            pruned.map(|data| (data.total_bytes_downloaded, data.total_packets_downloaded));
+        assert_eq!(pruned, Some((1, 1)));
+    }
+
+    #[test]
+    fn cycles_columns_in_their_natural_direction() {
+        let by_name = SortOrder::default().next_column();
+        assert_eq!(by_name, order(SortColumn::Name, true));
+        assert_eq!(by_name.next_column(), order(SortColumn::Connections, false));
+        assert_eq!(by_name.reversed(), order(SortColumn::Name, false));
+    }
+
+    #[test]
+    fn aggregates_remote_addresses_of_a_process() {
+        let connection = |remote: &str, local_port| {
+            Connection::new(
+                remote.parse().unwrap(),
+                "10.0.0.2".parse().unwrap(),
+                local_port,
+                Protocol::Tcp,
+            )
+        };
+        let data = |process_name: &str, pid, down| ConnectionData {
+            total_bytes_downloaded: down,
+            process_name: process_name.into(),
+            pid,
+            ..Default::default()
+        };
+        let state = UIState {
+            connections: vec![
+                (connection("1.1.1.1:443", 1000), data("curl", 7, 10)),
+                (connection("1.1.1.1:443", 1001), data("curl", 7, 5)),
+                (connection("8.8.8.8:53", 1002), data("curl", 7, 1)),
+                // the same name, but another process
+                (connection("1.1.1.1:443", 1003), data("curl", 8, 100)),
+            ],
+            ..Default::default()
+        };
+
+        let curl = ProcessInfo::new("curl", 7);
+        assert_eq!(state.process_connections(&curl).count(), 3);
+        let remote_addresses = state.process_remote_addresses(&curl);
+        let summary = remote_addresses
+            .iter()
+            .map(|(ip, data)| {
+                (
+                    ip.to_string(),
+                    data.total_bytes_downloaded,
+                    data.connection_count,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [("1.1.1.1".into(), 15, 2), ("8.8.8.8".into(), 1, 1)]
+        );
+    }
+
+    #[test]
+    fn records_history_until_idle() {
+        let curl = ProcessInfo::new("curl", 7);
+        let latest = |down| {
+            let data = NetworkData {
+                total_bytes_downloaded: down,
+                ..Default::default()
+            };
+            TotalCounters {
+                processes: HashMap::from([(curl.clone(), data.clone())]),
+                interfaces: HashMap::from([("eth0".into(), data)]),
+                ..Default::default()
+            }
+        };
+        let mut history = RateHistories::default();
+        let second = Duration::from_secs(1);
+        history.record(&latest(10), 3, second);
+        history.record(&latest(20), 3, second);
+        history.record(&TotalCounters::default(), 3, second);
+        let samples = history.processes[&curl].samples().collect::<Vec<_>>();
+        assert_eq!(samples, [(0, 10), (0, 20), (0, 0)]);
+
+        // in bytes per second
+        history.record(&latest(30), 3, Duration::from_millis(500));
+        let samples = history.total.samples().collect::<Vec<_>>();
+        assert_eq!(samples, [(0, 20), (0, 0), (0, 60)]);
+
+        for _ in 0..3 {
+            history.record(&TotalCounters::default(), 3, second);
+        }
+        assert!(history.processes.is_empty());
+    }
+
+    #[rstest]
+    #[case(Smoothing::Instantaneous, 1000, 600)]
+    #[case(Smoothing::Sma, 1000, 300)]
+    #[case(Smoothing::Ewma, 1000, 414)]
+    #[case(Smoothing::Sma, 250, 1200)]
+    #[case(Smoothing::Sma, 2000, 150)]
+    fn averages_rates_over_the_window(
+        #[case] smoothing: Smoothing,
+        #[case] interval_ms: u64,
+        #[case] expected: u128,
+    ) {
+        let connection = Connection::new(
+            "1.1.1.1:443".parse().unwrap(),
+            "10.0.0.2".parse().unwrap(),
+            1000,
+            Protocol::Tcp,
+        );
+        let mut state = UIState {
+            averaging: Averaging {
+                smoothing,
+                window: 3,
+                interval: Duration::from_millis(interval_ms),
+            },
+            ..Default::default()
+        };
+        // the first one falls out of the window
+        for downloaded in [1000, 100, 200, 600] {
+            let info = ConnectionInfo {
+                interface_name: "eth0".into(),
+                total_bytes_downloaded: downloaded,
+                total_bytes_uploaded: 0,
+                total_packets_downloaded: downloaded / 100,
+                total_packets_uploaded: 0,
+                server_name: None,
+                dns_questions: HashMap::new(),
+            };
+            let utilization = Utilization {
+                connections: HashMap::from([(connection, info)]),
+            };
+            state.update(
+                HashMap::new(),
+                utilization,
+                Duration::from_millis(interval_ms),
+            );
+        }
+        assert_eq!(state.total_bytes_downloaded, expected);
+        assert_eq!(state.connections[0].1.total_bytes_downloaded, expected);
+        // packets are averaged like bytes
+        assert_eq!(
+            state.connections[0].1.total_packets_downloaded,
+            expected / 100
+        );
+    }
+    #[test]
+    fn divides_rates_by_the_measured_snapshot_durations() {
+        let connection = Connection::new(
+            "1.1.1.1:443".parse().unwrap(),
+            "10.0.0.2".parse().unwrap(),
+            1000,
+            Protocol::Tcp,
+        );
+        let mut state = UIState {
+            averaging: Averaging {
+                smoothing: Smoothing::Sma,
+                window: 2,
+                interval: Duration::from_secs(1),
+            },
+            ..Default::default()
+        };
+        // the second snapshot was cut short by a keypress
+        for (downloaded, duration) in [(1000, 1000), (250, 250)] {
+            let info = ConnectionInfo {
+                interface_name: "eth0".into(),
+                total_bytes_downloaded: downloaded,
+                total_bytes_uploaded: 0,
+                total_packets_downloaded: 1,
+                total_packets_uploaded: 0,
+                server_name: None,
+                dns_questions: HashMap::new(),
+            };
+            let utilization = Utilization {
+                connections: HashMap::from([(connection, info)]),
+            };
+            state.update(HashMap::new(), utilization, Duration::from_millis(duration));
+        }
+        assert_eq!(state.total_bytes_downloaded, 1000);
+    }
+    #[rstest]
+    #[case(Smoothing::Sma, 5, 1000, "SMA over 5s")]
+    #[case(Smoothing::Sma, 3, 100, "SMA over 0.3s")]
+    #[case(Smoothing::Ewma, 299, 100, "EWMA over 29.9s")]
+    #[case(Smoothing::Instantaneous, 1, 1000, "instantaneous")]
+    fn displays_averaging_spans_exactly(
+        #[case] smoothing: Smoothing,
+        #[case] window: usize,
+        #[case] interval_ms: u64,
+        #[case] expected: &str,
+    ) {
+        let averaging = Averaging {
+            smoothing,
+            window,
+            interval: Duration::from_millis(interval_ms),
+        };
+        assert_eq!(averaging.to_string(), expected);
+    }
+    #[test]
+    fn counts_totals_while_paused() {
+        let connection = Connection::new(
+            "1.1.1.1:443".parse().unwrap(),
+            "10.0.0.2".parse().unwrap(),
+            1000,
+            Protocol::Tcp,
+        );
+        let procs = HashMap::from([(connection.local_socket, ProcessInfo::new("curl", 7))]);
+        let utilization = |downloaded| {
+            let mut utilization = Utilization::new();
+            utilization.connections.insert(
+                connection,
+                ConnectionInfo {
+                    interface_name: "eth0".into(),
+                    total_bytes_downloaded: downloaded,
+                    total_bytes_uploaded: 0,
+                    total_packets_downloaded: 1,
+                    total_packets_uploaded: 0,
+                    server_name: None,
+                    dns_questions: HashMap::new(),
+                },
+            );
+            utilization
+        };
+        let mut state = UIState::default();
+        state.update(procs.clone(), utilization(100), Duration::from_secs(1));
+        state.update_totals(&procs, &utilization(50));
+
+        let curl = &state.totals.processes[&ProcessInfo::new("curl", 7)];
+        assert_eq!(curl.total_bytes_downloaded, 150);
+        assert_eq!(state.totals.interfaces["eth0"].total_packets_downloaded, 2);
+        // the displayed rates stay as they were
+        assert_eq!(
+            state.latest.processes[&ProcessInfo::new("curl", 7)].total_bytes_downloaded,
+            100
+        );
+    }
+
+    #[test]
+    fn filters_traffic_before_aggregating() {
+        let connection = |remote: &str, local_port| {
+            Connection::new(
+                remote.parse().unwrap(),
+                "10.0.0.2".parse().unwrap(),
+                local_port,
+                Protocol::Tcp,
+            )
+        };
+        let info = |downloaded| ConnectionInfo {
+            interface_name: "eth0".into(),
+            total_bytes_downloaded: downloaded,
+            total_bytes_uploaded: 0,
+            total_packets_downloaded: 1,
+            total_packets_uploaded: 0,
+            server_name: None,
+            dns_questions: HashMap::new(),
+        };
+        let nginx = connection("1.1.1.1:50000", 443);
+        let postgres = connection("10.0.0.3:50001", 5432);
+        let procs = HashMap::from([
+            (nginx.local_socket, ProcessInfo::new("nginx", 7)),
+            (postgres.local_socket, ProcessInfo::new("postgres", 8)),
+        ]);
+        let mut state = UIState {
+            averaging: Averaging {
+                smoothing: Smoothing::Instantaneous,
+                ..Default::default()
+            },
+            traffic_filter: FilterOpts {
+                exclude_remote: vec!["10.0.0.0/8".parse().unwrap()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let utilization = Utilization {
+            connections: HashMap::from([(nginx, info(100)), (postgres, info(1000))]),
+        };
+        state.update(procs, utilization, Duration::from_secs(1));
+
+        assert_eq!(state.total_bytes_downloaded, 100);
+        let processes = state
+            .processes
+            .iter()
+            .map(|(proc_info, _)| proc_info.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(processes, ["nginx"]);
+        assert_eq!(state.remote_addresses.len(), 1);
+        assert_eq!(state.connections.len(), 1);
+        assert!(!state
+            .totals
+            .processes
+            .contains_key(&ProcessInfo::new("postgres", 8)));
+    }
+    #[test]
+    fn keeps_server_names_after_the_connections_start() {
+        let connection = |local_port| {
+            Connection::new(
+                "1.1.1.1:443".parse().unwrap(),
+                "10.0.0.2".parse().unwrap(),
+                local_port,
+                Protocol::Tcp,
+            )
+        };
+        let info = |server_name: Option<&str>| ConnectionInfo {
+            interface_name: "eth0".into(),
+            total_bytes_downloaded: 100,
+            total_bytes_uploaded: 0,
+            total_packets_downloaded: 1,
+            total_packets_uploaded: 0,
+            server_name: server_name.map(Into::into),
+            dns_questions: HashMap::new(),
+        };
+        let mut state = UIState {
+            averaging: Averaging {
+                smoothing: Smoothing::Instantaneous,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let utilization = Utilization {
+            connections: HashMap::from([
+                (connection(1000), info(Some("b.example.com"))),
+                (connection(1001), info(Some("a.example.com"))),
+                (connection(1002), info(Some("a.example.com"))),
+            ]),
+        };
+        state.update(HashMap::new(), utilization, Duration::from_secs(1));
+        let utilization = Utilization {
+            connections: HashMap::from([(connection(1000), info(None))]),
+        };
+        state.update(HashMap::new(), utilization, Duration::from_secs(1));
+
+        assert_eq!(
+            state.connections[0].1.server_name.as_deref(),
+            Some("b.example.com")
+        );
+        assert_eq!(
+            state.remote_address_server_names(),
+            HashMap::from([("1.1.1.1".parse().unwrap(), vec!["b.example.com"])])
+        );
+        assert_eq!(
+            state.totals.connections[&connection(1001)]
+                .server_name
+                .as_deref(),
+            Some("a.example.com")
+        );
+    }
+}
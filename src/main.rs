@@ -10,6 +10,7 @@ mod tests;
 use std::{
     collections::HashMap,
     fs::File,
+    net::TcpListener,
     sync::{
         atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc, Mutex, RwLock,
@@ -18,28 +19,39 @@ use std::{
     time::{Duration, Instant},
 };
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use crossterm::{
     event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
     terminal,
 };
-use display::{elapsed_time, RawTerminalBackend, Ui};
+use display::{elapsed_time, serve_metrics, CsvFiles, RawTerminalBackend, Ui};
 use eyre::bail;
+use log::info;
 use network::{
-    dns::{self, IpTable},
-    LocalSocket, Sniffer, Utilization,
+    dns::{self, SnoopedHostnames},
+    CaptureFilter, LocalSocket, Sniffer, Utilization,
 };
-use pnet::datalink::{DataLinkReceiver, NetworkInterface};
 use ratatui::backend::{Backend, CrosstermBackend};
 use simplelog::WriteLogger;
 
-use crate::cli::Opt;
-use crate::os::ProcessInfo;
-
-const DISPLAY_DELTA: Duration = Duration::from_millis(1000);
+use crate::cli::{Opt, OutputFormat};
+use crate::os::{InterfaceWithFrames, InterfacesWithFrames, NetnsScan, NetnsScanner, ProcessInfo};
 
 fn main() -> eyre::Result<()> {
-    let opts = Opt::parse();
+    let mut opts = Opt::parse();
+    if opts.csv_dir.is_some() {
+        match opts.render_opts.format {
+            OutputFormat::Text | OutputFormat::Csv => opts.render_opts.format = OutputFormat::Csv,
+            other => bail!(
+                "--csv-dir cannot be used with --format {}",
+                other.to_possible_value().unwrap().get_name()
+            ),
+        }
+    }
+    // structured output formats only make sense without the TUI
+    if opts.render_opts.format != OutputFormat::Text {
+        opts.raw = true;
+    }
 
     // init logging
     if let Some(ref log_path) = opts.log_to {
@@ -54,8 +66,8 @@ fn main() -> eyre::Result<()> {
         )?;
     }
 
-    let os_input = os::get_input(opts.interface.as_deref(), !opts.no_resolve, opts.dns_server)?;
-    if opts.raw {
+    let os_input = os::get_input(&opts)?;
+    if opts.raw || opts.metrics.is_some() {
         let terminal_backend = RawTerminalBackend {};
         start(terminal_backend, os_input, opts);
     } else {
@@ -84,13 +96,23 @@ pub struct OpenSockets {
 }
 
 pub struct OsInputOutput {
-    pub interfaces_with_frames: Vec<(NetworkInterface, Box<dyn DataLinkReceiver>)>,
+    pub interfaces_with_frames: InterfacesWithFrames,
     pub get_open_sockets: fn() -> OpenSockets,
     pub terminal_events: Box<dyn Iterator<Item = Event> + Send>,
     pub dns_client: Option<dns::Client>,
     pub write_to_stdout: Box<dyn FnMut(&str) + Send>,
+    pub csv_files: Option<CsvFiles>,
+    pub metrics_listener: Option<TcpListener>,
+    pub capture_filter: Option<CaptureFilter>,
+    /// Looks for new network namespaces to sniff in, and for those which are gone.
+    pub netns_scanner: Option<NetnsScanner>,
+    /// Tells the time snapshots are taken at, which the rates are divided by.
+    pub clock: Box<dyn FnMut() -> Instant + Send>,
 }
 
+/// How often the thread scanning for network namespaces checks whether bandwhich is stopping.
+const NETNS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 pub fn start<B>(terminal_backend: B, os_input: OsInputOutput, opts: Opt)
 where
     B: Backend + Send + 'static,
@@ -118,11 +140,26 @@ where
     let get_open_sockets = os_input.get_open_sockets;
     let mut write_to_stdout = os_input.write_to_stdout;
     let mut dns_client = os_input.dns_client;
+    let mut clock = os_input.clock;
 
     let raw_mode = opts.raw;
+    let structured_output = opts.render_opts.format != OutputFormat::Text;
+    let display_delta = opts.render_opts.interval;
+    let deadline = opts.duration.map(|duration| Instant::now() + duration);
+    let mut refreshes_left = opts.count;
+    // the first refresh comes as soon as bandwhich starts, before any traffic was sampled
+    let mut first_refresh = true;
+    // serving metrics without --raw runs headless, with neither the TUI nor text output
+    let tui_mode = !raw_mode && os_input.metrics_listener.is_none();
+    let metrics = Arc::new(RwLock::new(String::new()));
 
     let network_utilization = Arc::new(Mutex::new(Utilization::new()));
-    let ui = Arc::new(Mutex::new(Ui::new(terminal_backend, &opts)));
+    let snooped_hostnames = SnoopedHostnames::default();
+    let ui = Arc::new(Mutex::new(Ui::new(
+        terminal_backend,
+        &opts,
+        os_input.csv_files,
+    )));
 
     let display_handler = thread::Builder::new()
         .name("display_handler".to_string())
@@ -135,19 +172,36 @@ where
             let last_start_time = last_start_time.clone();
             let cumulative_time = cumulative_time.clone();
             let ui = ui.clone();
+            let metrics = os_input.metrics_listener.is_some().then(|| metrics.clone());
+            let snooped_hostnames = snooped_hostnames.clone();
 
             move || {
+                let mut last_snapshot_time = clock();
+                // whether the run was stopped from outside, eg. by ctrl-c, between two refreshes
+                let mut cut_short = true;
                 while running.load(Ordering::Acquire) {
                     let render_start_time = Instant::now();
                     let utilization = network_utilization.lock().unwrap().clone_and_reset();
+                    // refreshing early, eg. on a keypress, cuts a snapshot short
+                    let snapshot_time = clock();
+                    let snapshot_duration = snapshot_time - last_snapshot_time;
+                    last_snapshot_time = snapshot_time;
                     let OpenSockets { sockets_to_procs } = get_open_sockets();
-                    let mut ip_to_host = IpTable::new();
+                    let mut ip_to_host = dns_client
+                        .as_mut()
+                        .map(dns::Client::cache)
+                        .unwrap_or_default();
+                    // names snooped from DNS responses are the ones actually looked up, so they
+                    // take priority over reverse lookups, which are then not needed
+                    snooped_hostnames.merge_into(&mut ip_to_host);
                     if let Some(dns_client) = dns_client.as_mut() {
-                        ip_to_host = dns_client.cache();
                         let unresolved_ips = utilization
                             .connections
                             .keys()
-                            .filter(|conn| !ip_to_host.contains_key(&conn.remote_socket.ip))
+                            .filter(|conn| {
+                                let ip = &conn.remote_socket.ip;
+                                !ip_to_host.contains_key(ip) && !snooped_hostnames.knows(ip)
+                            })
                             .map(|conn| conn.remote_socket.ip)
                             .collect::<Vec<_>>();
                         dns_client.resolve(unresolved_ips);
@@ -157,7 +211,22 @@ where
                         let paused = paused.load(Ordering::SeqCst);
                         let table_cycle_offset = table_cycle_offset.load(Ordering::SeqCst);
                         if !paused {
-                            ui.update_state(sockets_to_procs, utilization, ip_to_host);
+                            ui.update_state(
+                                sockets_to_procs,
+                                utilization,
+                                snapshot_duration,
+                                ip_to_host,
+                            );
+                            if !first_refresh {
+                                ui.record_summary();
+                                if let Some(refreshes) = refreshes_left.as_mut() {
+                                    *refreshes -= 1;
+                                }
+                            }
+                            first_refresh = false;
+                        } else {
+                            // the exported counters are monotonic, so they cannot skip traffic
+                            ui.update_totals(&sockets_to_procs, &utilization);
                         }
                         let elapsed_time = elapsed_time(
                             *last_start_time.read().unwrap(),
@@ -165,21 +234,64 @@ where
                             paused,
                         );
 
+                        if let Some(metrics) = metrics.as_ref() {
+                            *metrics.write().unwrap() = ui.render_metrics();
+                        }
                         if raw_mode {
                             ui.output_text(&mut write_to_stdout);
-                        } else {
+                        } else if tui_mode {
                             ui.draw(paused, elapsed_time, table_cycle_offset);
                         }
                     }
-                    let render_duration = render_start_time.elapsed();
-                    if render_duration < DISPLAY_DELTA {
-                        park_timeout(DISPLAY_DELTA - render_duration);
+                    let now = Instant::now();
+                    if refreshes_left == Some(0) || deadline.is_some_and(|deadline| now >= deadline)
+                    {
+                        running.store(false, Ordering::Release);
+                        cut_short = false;
+                        break;
+                    }
+                    let render_duration = now - render_start_time;
+                    if render_duration < display_delta {
+                        let mut timeout = display_delta - render_duration;
+                        if let Some(deadline) = deadline {
+                            timeout = timeout.min(deadline - now);
+                        }
+                        park_timeout(timeout);
                     }
                 }
-                if !raw_mode {
-                    let mut ui = ui.lock().unwrap();
+                let mut ui = ui.lock().unwrap();
+                // the traffic since the last refresh belongs in the summary of a run cut short
+                if ui.has_summary() && cut_short && !first_refresh && !paused.load(Ordering::SeqCst)
+                {
+                    let utilization = network_utilization.lock().unwrap().clone_and_reset();
+                    let OpenSockets { sockets_to_procs } = get_open_sockets();
+                    let snapshot_duration = clock() - last_snapshot_time;
+                    ui.update_state(
+                        sockets_to_procs,
+                        utilization,
+                        snapshot_duration,
+                        HashMap::new(),
+                    );
+                    ui.record_summary();
+                }
+                if tui_mode {
                     ui.end();
                 }
+                if let Some(summary) = ui.summary() {
+                    // print below the shell prompt rather than on the alternate screen
+                    #[cfg(not(test))]
+                    if tui_mode {
+                        let _ = terminal::disable_raw_mode();
+                        let _ =
+                            crossterm::execute!(std::io::stdout(), terminal::LeaveAlternateScreen);
+                    }
+                    if structured_output {
+                        // keep stdout parseable as the selected format
+                        eprint!("{summary}");
+                    } else {
+                        write_to_stdout(&summary);
+                    }
+                }
             }
         })
         .unwrap();
@@ -199,7 +311,25 @@ where
                     let mut ui = ui.lock().unwrap();
 
                     match evt {
-                        Event::Resize(_x, _y) if !raw_mode => {
+                        // while searching, keys are typed into the search instead
+                        Event::Key(KeyEvent {
+                            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                            code,
+                            kind: KeyEventKind::Press,
+                            ..
+                        }) if tui_mode && ui.is_editing_search() => {
+                            let table_cycle_offset = table_cycle_offset.load(Ordering::SeqCst);
+                            if ui.handle_search_key(code) {
+                                let paused = paused.load(Ordering::SeqCst);
+                                let elapsed_time = elapsed_time(
+                                    *last_start_time.read().unwrap(),
+                                    *cumulative_time.read().unwrap(),
+                                    paused,
+                                );
+                                ui.draw(paused, elapsed_time, table_cycle_offset);
+                            }
+                        }
+                        Event::Resize(_x, _y) if tui_mode => {
                             let paused = paused.load(Ordering::SeqCst);
                             ui.draw(
                                 paused,
@@ -255,7 +385,7 @@ where
                             code: KeyCode::Tab,
                             kind: KeyEventKind::Press,
                             ..
-                        }) => {
+                        }) if tui_mode => {
                             let paused = paused.load(Ordering::SeqCst);
                             let elapsed_time = elapsed_time(
                                 *last_start_time.read().unwrap(),
@@ -267,6 +397,23 @@ where
                             table_cycle_offset.store(new, Ordering::SeqCst);
                             ui.draw(paused, elapsed_time, new);
                         }
+                        Event::Key(KeyEvent {
+                            modifiers: KeyModifiers::NONE,
+                            code,
+                            kind: KeyEventKind::Press,
+                            ..
+                        }) if tui_mode => {
+                            let table_cycle_offset = table_cycle_offset.load(Ordering::SeqCst);
+                            if ui.handle_key(code, table_cycle_offset) {
+                                let paused = paused.load(Ordering::SeqCst);
+                                let elapsed_time = elapsed_time(
+                                    *last_start_time.read().unwrap(),
+                                    *cumulative_time.read().unwrap(),
+                                    paused,
+                                );
+                                ui.draw(paused, elapsed_time, table_cycle_offset);
+                            }
+                        }
                         _ => (),
                     };
                 }
@@ -277,30 +424,117 @@ where
     active_threads.push(display_handler);
     active_threads.push(terminal_event_handler);
 
-    let sniffer_threads = os_input
-        .interfaces_with_frames
-        .into_iter()
-        .map(|(iface, frames)| {
+    if let Some(listener) = os_input.metrics_listener {
+        let metrics_handler = thread::Builder::new()
+            .name("metrics_handler".to_string())
+            .spawn({
+                let running = running.clone();
+                move || serve_metrics(listener, metrics, running)
+            })
+            .unwrap();
+        active_threads.push(metrics_handler);
+    }
+
+    // sniffers in another network namespace stop once it is gone
+    let spawn_sniffer = {
+        let running = running.clone();
+        let show_dns = opts.show_dns;
+        let tunnel_flows = opts.tunnels;
+        let count_mode = opts.count_mode;
+        let filter = os_input.capture_filter;
+        let network_utilization = network_utilization.clone();
+        let snooped_hostnames = snooped_hostnames.clone();
+
+        move |(iface, frames, link_layer, netns): InterfaceWithFrames, gone: Arc<AtomicBool>| {
             let name = format!("sniffing_handler_{}", iface.name);
             let running = running.clone();
-            let show_dns = opts.show_dns;
+            let filter = filter.clone();
             let network_utilization = network_utilization.clone();
+            let snooped_hostnames = snooped_hostnames.clone();
 
             thread::Builder::new()
                 .name(name)
                 .spawn(move || {
-                    let mut sniffer = Sniffer::new(iface, frames, show_dns);
+                    let mut sniffer = Sniffer::new(
+                        iface,
+                        frames,
+                        link_layer,
+                        show_dns,
+                        tunnel_flows,
+                        count_mode,
+                        filter,
+                        netns,
+                        snooped_hostnames,
+                    );
 
-                    while running.load(Ordering::Acquire) {
+                    while running.load(Ordering::Acquire) && !gone.load(Ordering::Acquire) {
                         if let Some(segment) = sniffer.next() {
                             network_utilization.lock().unwrap().ingest(segment);
                         }
                     }
                 })
                 .unwrap()
-        })
-        .collect::<Vec<_>>();
-    active_threads.extend(sniffer_threads);
+        }
+    };
+
+    let mut netns_gone: HashMap<u64, Arc<AtomicBool>> = HashMap::new();
+    for interface_with_frames in os_input.interfaces_with_frames {
+        let gone = match &interface_with_frames.3 {
+            Some(netns) => netns_gone.entry(netns.id).or_default().clone(),
+            None => Arc::default(),
+        };
+        active_threads.push(spawn_sniffer(interface_with_frames, gone));
+    }
+
+    if let Some(mut scan_netns) = os_input.netns_scanner {
+        let netns_handler = thread::Builder::new()
+            .name("netns_handler".to_string())
+            .spawn({
+                let running = running.clone();
+
+                move || {
+                    let mut sniffer_threads = vec![];
+                    let mut last_scan = Instant::now();
+                    while running.load(Ordering::Acquire) {
+                        park_timeout(NETNS_POLL_INTERVAL);
+                        if last_scan.elapsed() < display_delta {
+                            continue;
+                        }
+                        last_scan = Instant::now();
+
+                        let NetnsScan {
+                            namespaces,
+                            interfaces_with_frames,
+                        } = scan_netns();
+                        netns_gone.retain(|id, gone| {
+                            let exists = namespaces.contains(id);
+                            if !exists {
+                                info!("Network namespace {id} is gone, no longer sniffing in it");
+                                gone.store(true, Ordering::Release);
+                            }
+                            exists
+                        });
+                        for interface_with_frames in interfaces_with_frames {
+                            let Some(netns) = &interface_with_frames.3 else {
+                                continue;
+                            };
+                            info!(
+                                "Sniffing on {} in new network namespace {}",
+                                interface_with_frames.0.name, netns.label
+                            );
+                            let gone = netns_gone.entry(netns.id).or_default().clone();
+                            sniffer_threads.push(spawn_sniffer(interface_with_frames, gone));
+                        }
+                        sniffer_threads.retain(|sniffer| !sniffer.is_finished());
+                    }
+                    for sniffer in sniffer_threads {
+                        sniffer.join().unwrap();
+                    }
+                }
+            })
+            .unwrap();
+        active_threads.push(netns_handler);
+    }
 
     for thread_handler in active_threads {
         thread_handler.join().unwrap()
@@ -0,0 +1,693 @@
+//! Compiles a [`CaptureFilter`] to a classic BPF program, so that the kernel can drop
+//! unwanted frames before they are copied to userspace.
+//!
+//! The program only needs to accept a superset of what the filter matches: the sniffer
+//! still checks every segment in userspace. Frames the program cannot reason about, ie.
+//! VLAN-tagged frames, IPv6 packets with extension headers and IPv4 fragments after the
+//! first, are all let through for userspace to decide. So are tunnels when the flows inside
+//! them are accounted, as the filter is about their inner packets.
+
+use std::net::IpAddr;
+
+use pnet::ipnetwork::IpNetwork;
+
+use crate::{
+    cli::TunnelFlows,
+    network::{
+        CaptureFilter, Endpoint, FilterExpr, IpFamily, LinkLayer, Primitive, GENEVE_PORT,
+        VXLAN_PORTS,
+    },
+};
+
+// opcodes, see linux/bpf_common.h
+const BPF_LD: u16 = 0x00;
+const BPF_LDX: u16 = 0x01;
+const BPF_ALU: u16 = 0x04;
+const BPF_JMP: u16 = 0x05;
+const BPF_RET: u16 = 0x06;
+const BPF_W: u16 = 0x00;
+const BPF_H: u16 = 0x08;
+const BPF_B: u16 = 0x10;
+const BPF_ABS: u16 = 0x20;
+const BPF_IND: u16 = 0x40;
+const BPF_MSH: u16 = 0xa0;
+const BPF_AND: u16 = 0x50;
+const BPF_JEQ: u16 = 0x10;
+const BPF_JGT: u16 = 0x20;
+const BPF_JGE: u16 = 0x30;
+const BPF_JSET: u16 = 0x40;
+const BPF_K: u16 = 0x00;
+
+/// How many bytes of an accepted frame are passed on, ie. all of them.
+const ACCEPT_LENGTH: u32 = 0x40000;
+
+const ETHERTYPE_IPV4: u32 = 0x0800;
+const ETHERTYPE_IPV6: u32 = 0x86dd;
+/// 802.1Q, 802.1ad and pre-standard QinQ tags.
+const ETHERTYPES_VLAN: [u32; 3] = [0x8100, 0x88a8, 0x9100];
+const IPPROTO_TCP: u32 = 6;
+const IPPROTO_UDP: u32 = 17;
+/// IP-in-IP, IPv6-in-IP and GRE.
+const TUNNEL_PROTOCOLS: [u32; 3] = [4, 41, 47];
+/// Hop-by-hop options, routing, fragment, ESP, AH and destination options headers, which move
+/// the transport header of IPv6 packets by a varying length.
+const IPV6_EXTENSION_HEADERS: [u32; 6] = [0, 43, 44, 50, 51, 60];
+
+/// The layout of `struct sock_filter`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BpfInstruction {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+/// Compiles the filter, or returns `None` if it does not fit in a classic BPF program.
+pub fn compile_bpf(
+    filter: &CaptureFilter,
+    link_layer: LinkLayer,
+    tunnels: TunnelFlows,
+) -> Option<Vec<BpfInstruction>> {
+    let mut compiler = Compiler {
+        link_layer,
+        code: vec![],
+        labels: vec![],
+    };
+    let accept = compiler.label();
+    let reject = compiler.label();
+    // userspace decides on the packets whose transport header cannot be found
+    let mut test = Test::Or(vec![
+        compiler.is_undecidable(),
+        compiler.lower(filter.root()),
+    ]);
+    if tunnels == TunnelFlows::Inner {
+        test = Test::Or(vec![compiler.is_tunnel(), test]);
+    }
+    if link_layer.ethertype_offset().is_some() {
+        // tags move the IP header by a varying length, so tagged frames are all let through
+        test = Test::Or(vec![compiler.is_vlan_tagged(), test]);
+    }
+    compiler.emit(&test, accept, reject);
+
+    compiler.place(accept);
+    compiler.code.push(Op::Ret(ACCEPT_LENGTH));
+    compiler.place(reject);
+    compiler.code.push(Op::Ret(0));
+    compiler.assemble()
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Size {
+    Word,
+    Half,
+    Byte,
+}
+
+impl Size {
+    fn code(self) -> u16 {
+        match self {
+            Size::Word => BPF_W,
+            Size::Half => BPF_H,
+            Size::Byte => BPF_B,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Load {
+    /// At an offset from the start of the frame.
+    Absolute(Size, u32),
+    /// At an offset from the end of the IPv4 header.
+    Transport(Size, u32),
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Comparison {
+    Equal,
+    Greater,
+    GreaterOrEqual,
+    AnyBitSet,
+}
+
+/// Filter expressions, broken down to what a single conditional jump can test.
+#[derive(Clone, Debug)]
+enum Test {
+    And(Vec<Test>),
+    Or(Vec<Test>),
+    Not(Box<Test>),
+    Compare {
+        load: Load,
+        mask: Option<u32>,
+        comparison: Comparison,
+        value: u32,
+    },
+}
+
+impl Test {
+    fn equal(load: Load, value: u32) -> Self {
+        Test::Compare {
+            load,
+            mask: None,
+            comparison: Comparison::Equal,
+            value,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Label(usize);
+
+#[derive(Copy, Clone, Debug)]
+enum Op {
+    Statement(u16, u32),
+    Jump(u16, u32, Label, Label),
+    Ret(u32),
+}
+
+struct Compiler {
+    link_layer: LinkLayer,
+    code: Vec<Op>,
+    /// The index of the instruction each label points to, once placed.
+    labels: Vec<Option<usize>>,
+}
+
+impl Compiler {
+    fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    fn place(&mut self, label: Label) {
+        self.labels[label.0] = Some(self.code.len());
+    }
+
+    fn emit(&mut self, test: &Test, on_true: Label, on_false: Label) {
+        match test {
+            Test::And(tests) | Test::Or(tests) => {
+                let is_and = matches!(test, Test::And(_));
+                let Some((last, rest)) = tests.split_last() else {
+                    // an empty conjunction is always true, an empty disjunction never is
+                    let target = if is_and { on_true } else { on_false };
+                    self.code
+                        .push(Op::Jump(BPF_JMP | BPF_JSET | BPF_K, 0, target, target));
+                    return;
+                };
+                for test in rest {
+                    let next = self.label();
+                    if is_and {
+                        self.emit(test, next, on_false);
+                    } else {
+                        self.emit(test, on_true, next);
+                    }
+                    self.place(next);
+                }
+                self.emit(last, on_true, on_false);
+            }
+            Test::Not(test) => self.emit(test, on_false, on_true),
+            Test::Compare {
+                load,
+                mask,
+                comparison,
+                value,
+            } => {
+                let l2_len = self.link_layer.header_len() as u32;
+                match *load {
+                    Load::Absolute(size, offset) => self
+                        .code
+                        .push(Op::Statement(BPF_LD | size.code() | BPF_ABS, offset)),
+                    Load::Transport(size, offset) => {
+                        // X = 4 * (IHL), the length of the IPv4 header
+                        self.code
+                            .push(Op::Statement(BPF_LDX | BPF_B | BPF_MSH, l2_len));
+                        self.code.push(Op::Statement(
+                            BPF_LD | size.code() | BPF_IND,
+                            l2_len + offset,
+                        ));
+                    }
+                }
+                if let Some(mask) = mask {
+                    self.code
+                        .push(Op::Statement(BPF_ALU | BPF_AND | BPF_K, *mask));
+                }
+                let code = match comparison {
+                    Comparison::Equal => BPF_JEQ,
+                    Comparison::Greater => BPF_JGT,
+                    Comparison::GreaterOrEqual => BPF_JGE,
+                    Comparison::AnyBitSet => BPF_JSET,
+                };
+                self.code
+                    .push(Op::Jump(BPF_JMP | code | BPF_K, *value, on_true, on_false));
+            }
+        }
+    }
+
+    fn assemble(&self) -> Option<Vec<BpfInstruction>> {
+        let offset = |from: usize, label: Label| -> Option<u8> {
+            let target = self.labels[label.0]?;
+            // jumps are relative to the next instruction, and can only go forward
+            u8::try_from(target.checked_sub(from + 1)?).ok()
+        };
+        self.code
+            .iter()
+            .enumerate()
+            .map(|(i, op)| {
+                Some(match *op {
+                    Op::Statement(code, k) => BpfInstruction {
+                        code,
+                        jt: 0,
+                        jf: 0,
+                        k,
+                    },
+                    Op::Jump(code, k, on_true, on_false) => BpfInstruction {
+                        code,
+                        jt: offset(i, on_true)?,
+                        jf: offset(i, on_false)?,
+                        k,
+                    },
+                    Op::Ret(k) => BpfInstruction {
+                        code: BPF_RET | BPF_K,
+                        jt: 0,
+                        jf: 0,
+                        k,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    fn lower(&self, expr: &FilterExpr) -> Test {
+        match expr {
+            FilterExpr::And(a, b) => Test::And(vec![self.lower(a), self.lower(b)]),
+            FilterExpr::Or(a, b) => Test::Or(vec![self.lower(a), self.lower(b)]),
+            FilterExpr::Not(a) => Test::Not(Box::new(self.lower(a))),
+            FilterExpr::Primitive(primitive) => self.lower_primitive(primitive),
+        }
+    }
+
+    fn lower_primitive(&self, primitive: &Primitive) -> Test {
+        match primitive {
+            Primitive::Family(family) => self.is_family(*family),
+            Primitive::Transport(protocol) => {
+                let protocol = u32::from(protocol.number());
+                Test::Or(vec![
+                    self.has_protocol(IpFamily::V4, protocol),
+                    self.has_protocol(IpFamily::V6, protocol),
+                ])
+            }
+            Primitive::Host(endpoint, ip) => {
+                let prefix = if ip.is_ipv4() { 32 } else { 128 };
+                let net = IpNetwork::new(*ip, prefix).unwrap();
+                self.in_network(*endpoint, net)
+            }
+            Primitive::Net(endpoint, net) => self.in_network(*endpoint, *net),
+            Primitive::Port(endpoint, ports) => {
+                let ports = (u32::from(*ports.start()), u32::from(*ports.end()));
+                Test::Or(vec![
+                    self.has_port(IpFamily::V4, *endpoint, ports),
+                    self.has_port(IpFamily::V6, *endpoint, ports),
+                ])
+            }
+        }
+    }
+
+    fn ip_offset(&self, offset: u32) -> u32 {
+        self.link_layer.header_len() as u32 + offset
+    }
+
+    fn is_family(&self, family: IpFamily) -> Test {
+        match self.link_layer.ethertype_offset() {
+            Some(offset) => {
+                let ethertype = match family {
+                    IpFamily::V4 => ETHERTYPE_IPV4,
+                    IpFamily::V6 => ETHERTYPE_IPV6,
+                };
+                Test::equal(Load::Absolute(Size::Half, offset as u32), ethertype)
+            }
+            None => {
+                let version = match family {
+                    IpFamily::V4 => 0x40,
+                    IpFamily::V6 => 0x60,
+                };
+                Test::Compare {
+                    load: Load::Absolute(Size::Byte, 0),
+                    mask: Some(0xf0),
+                    comparison: Comparison::Equal,
+                    value: version,
+                }
+            }
+        }
+    }
+
+    fn is_vlan_tagged(&self) -> Test {
+        let offset = self.link_layer.ethertype_offset().unwrap_or_default() as u32;
+        Test::Or(
+            ETHERTYPES_VLAN
+                .iter()
+                .map(|&ethertype| Test::equal(Load::Absolute(Size::Half, offset), ethertype))
+                .collect(),
+        )
+    }
+
+    /// Whether the transport header is elsewhere than right after the fixed IP header, or not in
+    /// the packet at all.
+    fn is_undecidable(&self) -> Test {
+        let next_header = Load::Absolute(Size::Byte, self.ip_offset(6));
+        Test::Or(vec![
+            Test::And(vec![
+                self.is_family(IpFamily::V4),
+                // only the first fragment carries the transport header
+                Test::Compare {
+                    load: Load::Absolute(Size::Half, self.ip_offset(6)),
+                    mask: None,
+                    comparison: Comparison::AnyBitSet,
+                    value: 0x1fff,
+                },
+            ]),
+            Test::And(vec![
+                self.is_family(IpFamily::V6),
+                Test::Or(
+                    IPV6_EXTENSION_HEADERS
+                        .iter()
+                        .map(|&header| Test::equal(next_header, header))
+                        .collect(),
+                ),
+            ]),
+        ])
+    }
+
+    /// Whether the packet is one of the tunnels decapsulated with `--tunnels inner`.
+    fn is_tunnel(&self) -> Test {
+        let mut tests = vec![];
+        for family in [IpFamily::V4, IpFamily::V6] {
+            for protocol in TUNNEL_PROTOCOLS {
+                tests.push(self.has_protocol(family, protocol));
+            }
+            for port in VXLAN_PORTS.into_iter().chain([GENEVE_PORT]) {
+                let port = u32::from(port);
+                tests.push(Test::And(vec![
+                    self.has_protocol(family, IPPROTO_UDP),
+                    self.has_port(family, Some(Endpoint::Destination), (port, port)),
+                ]));
+            }
+        }
+        Test::Or(tests)
+    }
+
+    fn has_protocol(&self, family: IpFamily, protocol: u32) -> Test {
+        let offset = match family {
+            IpFamily::V4 => 9,
+            IpFamily::V6 => 6,
+        };
+        Test::And(vec![
+            self.is_family(family),
+            Test::equal(Load::Absolute(Size::Byte, self.ip_offset(offset)), protocol),
+        ])
+    }
+
+    fn in_network(&self, endpoint: Option<Endpoint>, net: IpNetwork) -> Test {
+        let (family, source_offset, destination_offset) = match net {
+            IpNetwork::V4(_) => (IpFamily::V4, 12, 16),
+            IpNetwork::V6(_) => (IpFamily::V6, 8, 24),
+        };
+        let words = match net.network() {
+            IpAddr::V4(ip) => vec![u32::from(ip)],
+            IpAddr::V6(ip) => ip
+                .segments()
+                .chunks(2)
+                .map(|chunk| (u32::from(chunk[0]) << 16) | u32::from(chunk[1]))
+                .collect(),
+        };
+        let address_matches = |offset: u32| {
+            let mut prefix = u32::from(net.prefix());
+            let mut tests = vec![];
+            for (i, word) in (0u32..).zip(words.iter()) {
+                if prefix == 0 {
+                    break;
+                }
+                let load = Load::Absolute(Size::Word, self.ip_offset(offset + 4 * i));
+                let mask = (!0u32).checked_shl(32 - prefix.min(32)).unwrap_or(0);
+                tests.push(Test::Compare {
+                    load,
+                    mask: (mask != !0).then_some(mask),
+                    comparison: Comparison::Equal,
+                    value: word & mask,
+                });
+                prefix = prefix.saturating_sub(32);
+            }
+            Test::And(tests)
+        };
+        let addresses = match endpoint {
+            Some(Endpoint::Source) => address_matches(source_offset),
+            Some(Endpoint::Destination) => address_matches(destination_offset),
+            None => Test::Or(vec![
+                address_matches(source_offset),
+                address_matches(destination_offset),
+            ]),
+        };
+        Test::And(vec![self.is_family(family), addresses])
+    }
+
+    fn has_port(&self, family: IpFamily, endpoint: Option<Endpoint>, ports: (u32, u32)) -> Test {
+        let load_port = |offset: u32| match family {
+            IpFamily::V4 => Load::Transport(Size::Half, offset),
+            // packets with extension headers are left to userspace, see `is_undecidable`
+            IpFamily::V6 => Load::Absolute(Size::Half, self.ip_offset(40 + offset)),
+        };
+        let port_matches = |offset: u32| {
+            let load = load_port(offset);
+            if ports.0 == ports.1 {
+                return Test::equal(load, ports.0);
+            }
+            Test::And(vec![
+                Test::Compare {
+                    load,
+                    mask: None,
+                    comparison: Comparison::GreaterOrEqual,
+                    value: ports.0,
+                },
+                Test::Not(Box::new(Test::Compare {
+                    load,
+                    mask: None,
+                    comparison: Comparison::Greater,
+                    value: ports.1,
+                })),
+            ])
+        };
+        let ports_match = match endpoint {
+            Some(Endpoint::Source) => port_matches(0),
+            Some(Endpoint::Destination) => port_matches(2),
+            None => Test::Or(vec![port_matches(0), port_matches(2)]),
+        };
+
+        Test::And(vec![
+            Test::Or(vec![
+                self.has_protocol(family, IPPROTO_TCP),
+                self.has_protocol(family, IPPROTO_UDP),
+            ]),
+            ports_match,
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pnet::packet::{
+        ethernet::MutableEthernetPacket,
+        ipv4::{checksum, MutableIpv4Packet},
+    };
+    use rstest::rstest;
+
+    use super::*;
+
+    /// Runs a classic BPF program on a frame, returning whether it was accepted.
+    fn run(program: &[BpfInstruction], frame: &[u8]) -> bool {
+        let load = |size: u16, offset: u32| -> u32 {
+            let offset = offset as usize;
+            match size {
+                BPF_W => u32::from_be_bytes(frame[offset..offset + 4].try_into().unwrap()),
+                BPF_H => u16::from_be_bytes(frame[offset..offset + 2].try_into().unwrap()).into(),
+                _ => frame[offset].into(),
+            }
+        };
+        let (mut a, mut x, mut pc) = (0u32, 0u32, 0usize);
+        loop {
+            let BpfInstruction { code, jt, jf, k } = program[pc];
+            pc += 1;
+            match code & 0x07 {
+                BPF_LD if code & 0xe0 == BPF_ABS => a = load(code & 0x18, k),
+                BPF_LD => a = load(code & 0x18, x + k),
+                BPF_LDX => x = 4 * (u32::from(frame[k as usize]) & 0xf),
+                BPF_ALU => a &= k,
+                BPF_JMP => {
+                    let taken = match code & 0xf0 {
+                        BPF_JEQ => a == k,
+                        BPF_JGT => a > k,
+                        BPF_JGE => a >= k,
+                        _ => a & k != 0,
+                    };
+                    pc += usize::from(if taken { jt } else { jf });
+                }
+                _ => return k != 0,
+            }
+        }
+    }
+
+    /// An Ethernet frame carrying TCP from 10.0.0.2:50000 to 1.1.1.1:443.
+    fn frame() -> Vec<u8> {
+        let mut buffer = vec![0u8; 14 + 20 + 20];
+        let mut ethernet = MutableEthernetPacket::new(&mut buffer).unwrap();
+        ethernet.set_ethertype(pnet::packet::ethernet::EtherTypes::Ipv4);
+        let mut ip = MutableIpv4Packet::new(&mut buffer[14..]).unwrap();
+        ip.set_version(4);
+        ip.set_header_length(5);
+        ip.set_total_length(40);
+        ip.set_ttl(64);
+        ip.set_next_level_protocol(pnet::packet::ip::IpNextHeaderProtocols::Tcp);
+        ip.set_source([10, 0, 0, 2].into());
+        ip.set_destination([1, 1, 1, 1].into());
+        let sum = checksum(&ip.to_immutable());
+        ip.set_checksum(sum);
+        buffer[34..36].copy_from_slice(&50000u16.to_be_bytes());
+        buffer[36..38].copy_from_slice(&443u16.to_be_bytes());
+        buffer
+    }
+
+    #[rstest]
+    #[case("tcp", true)]
+    #[case("udp", false)]
+    #[case("ip", true)]
+    #[case("ip6", false)]
+    #[case("host 1.1.1.1", true)]
+    #[case("src host 1.1.1.1", false)]
+    #[case("dst host 1.1.1.1", true)]
+    #[case("net 10.0.0.0/8", true)]
+    #[case("dst net 10.0.0.0/8", false)]
+    #[case("net 10.0.0.2/31", true)]
+    #[case("net 10.0.0.0/31", false)]
+    #[case("net 10.0.0.0/31 and net 10.0.0.4/30", false)]
+    #[case("port 443", true)]
+    #[case("src port 443", false)]
+    #[case("tcp dst port 443", true)]
+    #[case("udp port 443", false)]
+    #[case("portrange 40000-50000", true)]
+    #[case("portrange 40000-49999", false)]
+    #[case("not port 443", false)]
+    #[case("port 80 or 443", true)]
+    #[case("tcp and not (port 80 or port 8080)", true)]
+    #[case("host ::1 or port 443", true)]
+    #[case("net ::/0", false)]
+    fn filters_ethernet_frames(#[case] expression: &str, #[case] expected: bool) {
+        let filter: CaptureFilter = expression.parse().unwrap();
+        let program = compile_bpf(&filter, LinkLayer::Ethernet, TunnelFlows::Outer).unwrap();
+        assert_eq!(run(&program, &frame()), expected);
+    }
+
+    #[test]
+    fn filters_raw_ip_frames() {
+        let frame = &frame()[14..];
+        let filter: CaptureFilter = "ip and dst port 443".parse().unwrap();
+        assert!(run(
+            &compile_bpf(&filter, LinkLayer::RawIp, TunnelFlows::Outer).unwrap(),
+            frame
+        ));
+        let filter: CaptureFilter = "ip6 or src port 443".parse().unwrap();
+        assert!(!run(
+            &compile_bpf(&filter, LinkLayer::RawIp, TunnelFlows::Outer).unwrap(),
+            frame
+        ));
+    }
+
+    #[test]
+    fn lets_vlan_tagged_frames_through() {
+        let untagged = frame();
+        let mut tagged = untagged[..12].to_vec();
+        tagged.extend([0x81, 0x00, 0x00, 0x64]);
+        tagged.extend(&untagged[12..]);
+        let filter: CaptureFilter = "udp".parse().unwrap();
+        let program = compile_bpf(&filter, LinkLayer::Ethernet, TunnelFlows::Outer).unwrap();
+        assert!(!run(&program, &untagged));
+        assert!(run(&program, &tagged));
+    }
+
+    /// An Ethernet frame carrying IPv6 with a hop-by-hop options header, then TCP to port 443.
+    fn ipv6_frame_with_extension_header() -> Vec<u8> {
+        let mut buffer = vec![0u8; 14 + 40 + 8 + 20];
+        buffer[12..14].copy_from_slice(&0x86ddu16.to_be_bytes());
+        buffer[14] = 0x60;
+        buffer[18..20].copy_from_slice(&28u16.to_be_bytes());
+        // hop-by-hop options, followed by TCP
+        buffer[20] = 0;
+        buffer[54] = 6;
+        buffer[62..64].copy_from_slice(&50000u16.to_be_bytes());
+        buffer[64..66].copy_from_slice(&443u16.to_be_bytes());
+        buffer
+    }
+
+    #[rstest]
+    #[case("tcp")]
+    #[case("udp")]
+    #[case("port 443")]
+    #[case("not port 443")]
+    fn lets_packets_with_extension_headers_through(#[case] expression: &str) {
+        let filter: CaptureFilter = expression.parse().unwrap();
+        let program = compile_bpf(&filter, LinkLayer::Ethernet, TunnelFlows::Outer).unwrap();
+        assert!(run(&program, &ipv6_frame_with_extension_header()));
+        // without them, the transport header is read
+        let mut frame = ipv6_frame_with_extension_header();
+        frame.drain(54..62);
+        frame[20] = 6;
+        frame[18..20].copy_from_slice(&20u16.to_be_bytes());
+        let tcp_to_443 = !expression.contains("udp") && !expression.contains("not");
+        assert_eq!(run(&program, &frame), tcp_to_443);
+    }
+
+    #[rstest]
+    #[case("port 443")]
+    #[case("src port 443")]
+    #[case("not port 443")]
+    fn lets_later_fragments_through(#[case] expression: &str) {
+        let mut frame = frame();
+        // at an offset of 1480 bytes, the ports are part of the payload
+        frame[20..22].copy_from_slice(&185u16.to_be_bytes());
+        let filter: CaptureFilter = expression.parse().unwrap();
+        let program = compile_bpf(&filter, LinkLayer::Ethernet, TunnelFlows::Outer).unwrap();
+        assert!(run(&program, &frame));
+    }
+
+    #[rstest]
+    #[case::vxlan(17, 4789, TunnelFlows::Inner, true)]
+    #[case::linux_vxlan(17, 8472, TunnelFlows::Inner, true)]
+    #[case::geneve(17, 6081, TunnelFlows::Inner, true)]
+    #[case::gre(47, 0, TunnelFlows::Inner, true)]
+    #[case::ip_in_ip(4, 0, TunnelFlows::Inner, true)]
+    #[case::not_a_tunnel(17, 53, TunnelFlows::Inner, false)]
+    #[case::outer_flows(17, 4789, TunnelFlows::Outer, false)]
+    fn lets_tunnels_through_for_their_inner_flows(
+        #[case] protocol: u8,
+        #[case] destination_port: u16,
+        #[case] tunnels: TunnelFlows,
+        #[case] expected: bool,
+    ) {
+        let mut frame = frame();
+        frame[23] = protocol;
+        frame[36..38].copy_from_slice(&destination_port.to_be_bytes());
+        // the pods behind the tunnel endpoints
+        let filter: CaptureFilter = "net 10.244.0.0/16".parse().unwrap();
+        let program = compile_bpf(&filter, LinkLayer::Ethernet, tunnels).unwrap();
+        assert_eq!(run(&program, &frame), expected);
+    }
+
+    #[test]
+    fn gives_up_when_jumps_are_too_long() {
+        let expression = (1..=100)
+            .map(|port| format!("port {port}"))
+            .collect::<Vec<_>>()
+            .join(" or ");
+        let filter: CaptureFilter = expression.parse().unwrap();
+        assert_eq!(
+            compile_bpf(&filter, LinkLayer::Ethernet, TunnelFlows::Outer),
+            None
+        );
+    }
+}
@@ -4,13 +4,36 @@ use std::{
     net::{IpAddr, SocketAddr},
 };
 
+use serde::{Serialize, Serializer};
+
 #[derive(PartialEq, Hash, Eq, Clone, PartialOrd, Ord, Debug, Copy)]
 pub enum Protocol {
     Tcp,
     Udp,
+    Icmp,
+    Icmpv6,
+    /// Any other IP protocol, by its number, eg. 47 for GRE.
+    Other(u8),
 }
 
 impl Protocol {
+    /// The protocol number of the IPv4 header, or the next header of IPv6.
+    pub fn number(self) -> u8 {
+        match self {
+            Protocol::Tcp => 6,
+            Protocol::Udp => 17,
+            Protocol::Icmp => 1,
+            Protocol::Icmpv6 => 58,
+            Protocol::Other(number) => number,
+        }
+    }
+
+    /// Whether connections of this protocol are told apart by ports. Those of the others are
+    /// only told apart by their addresses, and have both ports set to 0.
+    pub fn has_ports(self) -> bool {
+        matches!(self, Protocol::Tcp | Protocol::Udp)
+    }
+
     #[allow(dead_code)]
     pub fn from_str(string: &str) -> Option<Self> {
         match string {
@@ -26,10 +49,28 @@ impl fmt::Display for Protocol {
         match *self {
             Protocol::Tcp => write!(f, "tcp"),
             Protocol::Udp => write!(f, "udp"),
+            Protocol::Icmp => write!(f, "icmp"),
+            Protocol::Icmpv6 => write!(f, "icmpv6"),
+            Protocol::Other(2) => write!(f, "igmp"),
+            Protocol::Other(4) => write!(f, "ipip"),
+            Protocol::Other(41) => write!(f, "ipv6"),
+            Protocol::Other(47) => write!(f, "gre"),
+            Protocol::Other(50) => write!(f, "esp"),
+            Protocol::Other(51) => write!(f, "ah"),
+            Protocol::Other(89) => write!(f, "ospf"),
+            Protocol::Other(112) => write!(f, "vrrp"),
+            Protocol::Other(132) => write!(f, "sctp"),
+            Protocol::Other(number) => write!(f, "proto {number}"),
         }
     }
 }
 
+impl Serialize for Protocol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 #[derive(Clone, Ord, PartialOrd, PartialEq, Eq, Hash, Copy)]
 pub struct Socket {
     pub ip: IpAddr,
@@ -51,11 +92,15 @@ pub struct LocalSocket {
     pub ip: IpAddr,
     pub port: u16,
     pub protocol: Protocol,
+    /// The network namespace the socket lives in, or 0 for the one bandwhich runs in.
+    pub netns: u64,
 }
 
 impl fmt::Debug for LocalSocket {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let LocalSocket { ip, port, protocol } = self;
+        let LocalSocket {
+            ip, port, protocol, ..
+        } = self;
         match ip {
             IpAddr::V4(v4) => write!(f, "{protocol}://{v4}:{port}"),
             IpAddr::V6(v6) => write!(f, "{protocol}://[{v6}]:{port}"),
@@ -86,17 +131,37 @@ pub fn display_ip_or_host(ip: IpAddr, ip_to_host: &HashMap<IpAddr, String>) -> S
     }
 }
 
+/// Names an address by the server names its connections asked for, if any, rather than its
+/// hostname.
+pub fn display_remote_address(
+    ip: IpAddr,
+    server_names: &HashMap<IpAddr, Vec<&str>>,
+    ip_to_host: &HashMap<IpAddr, String>,
+) -> String {
+    match server_names.get(&ip) {
+        Some(names) => names.join(", "),
+        None => display_ip_or_host(ip, ip_to_host),
+    }
+}
+
+/// Names the remote end by the server name the client asked for, if any, rather than its IP.
 pub fn display_connection_string(
     connection: &Connection,
     ip_to_host: &HashMap<IpAddr, String>,
     interface_name: &str,
+    server_name: Option<&str>,
 ) -> String {
+    let remote_host = match server_name {
+        Some(server_name) => server_name.to_owned(),
+        None => display_ip_or_host(connection.remote_socket.ip, ip_to_host),
+    };
+    let protocol = connection.local_socket.protocol;
+    if !protocol.has_ports() {
+        return format!("<{interface_name}> => {remote_host} ({protocol})");
+    }
     format!(
-        "<{interface_name}>:{} => {}:{} ({})",
-        connection.local_socket.port,
-        display_ip_or_host(connection.remote_socket.ip, ip_to_host),
-        connection.remote_socket.port,
-        connection.local_socket.protocol,
+        "<{interface_name}>:{} => {remote_host}:{} ({protocol})",
+        connection.local_socket.port, connection.remote_socket.port,
     )
 }
 
@@ -116,6 +181,7 @@ impl Connection {
                 ip: local_ip,
                 port: local_port,
                 protocol,
+                netns: 0,
             },
         }
     }
@@ -0,0 +1,102 @@
+use hickory_proto::op::{Message, MessageType, ResponseCode};
+
+/// A DNS query or response seen on the wire, reduced to the question it is about.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsMessage {
+    pub question: DnsQuestion,
+    /// The response code, eg. `NXDOMAIN`, or `None` for a query.
+    pub response_code: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DnsQuestion {
+    /// The queried name, without the trailing dot.
+    pub name: String,
+    /// The queried record type, eg. `AAAA`.
+    pub record_type: String,
+}
+
+/// The messages seen about a question in a snapshot, counted rather than kept.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DnsExchange {
+    pub queries: u128,
+    /// The code of the latest response, eg. `NXDOMAIN`.
+    pub response_code: Option<String>,
+}
+
+impl DnsExchange {
+    pub fn add(&mut self, response_code: Option<String>) {
+        match response_code {
+            None => self.queries += 1,
+            Some(response_code) => self.response_code = Some(response_code),
+        }
+    }
+}
+
+impl DnsMessage {
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        let message = Message::from_vec(payload).ok()?;
+        let query = message.queries.first()?;
+        let response_code = match message.metadata.message_type {
+            MessageType::Query => None,
+            MessageType::Response => Some(display_response_code(message.metadata.response_code)),
+        };
+        Some(DnsMessage {
+            question: DnsQuestion {
+                name: query.name().to_utf8().trim_end_matches('.').to_owned(),
+                record_type: query.query_type().to_string(),
+            },
+            response_code,
+        })
+    }
+}
+
+/// Names a response code the way `dig` does.
+fn display_response_code(response_code: ResponseCode) -> String {
+    match u16::from(response_code) {
+        0 => "NOERROR".into(),
+        1 => "FORMERR".into(),
+        2 => "SERVFAIL".into(),
+        3 => "NXDOMAIN".into(),
+        4 => "NOTIMP".into(),
+        5 => "REFUSED".into(),
+        code => format!("RCODE{code}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hickory_proto::{
+        op::{OpCode, Query},
+        rr::{Name, RecordType},
+    };
+
+    use super::*;
+
+    #[test]
+    fn parses_queries_and_responses() {
+        let name = Name::from_ascii("nope.example.com.").unwrap();
+        let mut query = Message::query();
+        query.add_query(Query::query(name.clone(), RecordType::AAAA));
+        let mut response = Message::response(1, OpCode::Query);
+        response.add_query(Query::query(name, RecordType::AAAA));
+        response.metadata.response_code = ResponseCode::NXDomain;
+
+        let expected = |response_code: Option<&str>| DnsMessage {
+            question: DnsQuestion {
+                name: "nope.example.com".into(),
+                record_type: "AAAA".into(),
+            },
+            response_code: response_code.map(Into::into),
+        };
+        assert_eq!(
+            DnsMessage::parse(&query.to_vec().unwrap()),
+            Some(expected(None))
+        );
+        assert_eq!(
+            DnsMessage::parse(&response.to_vec().unwrap()),
+            Some(expected(Some("NXDOMAIN")))
+        );
+        assert_eq!(DnsMessage::parse(b"not a DNS message"), None);
+    }
+}
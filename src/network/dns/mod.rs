@@ -1,9 +1,13 @@
 use std::{collections::HashMap, net::IpAddr};
 
 mod client;
+mod message;
 mod resolver;
+mod snoop;
 
 pub use client::*;
+pub use message::*;
 pub use resolver::*;
+pub use snoop::*;
 
 pub type IpTable = HashMap<IpAddr, String>;
@@ -0,0 +1,391 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    hash::Hash,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use hickory_proto::{
+    op::{Message, MessageType},
+    rr::RData,
+};
+
+use crate::network::{dns::IpTable, Connection};
+
+/// How many names are remembered at most, for networks which resolve more than they connect to.
+const MAX_SNOOPED_NAMES: usize = 4096;
+/// How many queries are awaiting their response at most.
+const MAX_PENDING_QUERIES: usize = 1024;
+/// How long a response to a query is awaited, which is longer than resolvers retry after.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Hostnames learnt passively from the DNS responses bandwhich sees, for the addresses they
+/// resolved to.
+///
+/// Unlike reverse lookups, which tend to return the names of CDN hosts, these are the names
+/// that were actually looked up, so they take priority. Only the responses to queries sent from
+/// this host are trusted, so that other traffic from port 53 cannot name its addresses.
+#[derive(Clone, Default)]
+pub struct SnoopedHostnames(Arc<Mutex<Table>>);
+
+impl SnoopedHostnames {
+    /// Awaits the response to a DNS query sent over `connection`.
+    pub fn query(&self, connection: &Connection, payload: &[u8]) {
+        if let Some((query, _)) = parse(connection, payload, MessageType::Query) {
+            self.0.lock().unwrap().query(query, Instant::now());
+        }
+    }
+
+    /// Maps the addresses in the A and AAAA answers of a DNS response received over
+    /// `connection` to the queried name, if that query was sent over it.
+    pub fn learn(&self, connection: &Connection, payload: &[u8]) {
+        if let Some((query, message)) = parse(connection, payload, MessageType::Response) {
+            let answers = answers(&message);
+            self.0
+                .lock()
+                .unwrap()
+                .learn(&query, answers, Instant::now());
+        }
+    }
+
+    /// Names the addresses of `ip_to_host` which were snooped, and adds the names learnt since
+    /// the last call.
+    pub fn merge_into(&self, ip_to_host: &mut IpTable) {
+        self.0
+            .lock()
+            .unwrap()
+            .merge_into(ip_to_host, Instant::now());
+    }
+
+    /// Whether a name was snooped for `ip`, so that it needs no reverse lookup.
+    pub fn knows(&self, ip: &IpAddr) -> bool {
+        self.0.lock().unwrap().names.get(ip).is_some()
+    }
+}
+
+/// A DNS query, which is answered with the same ID and name over the same connection.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct SentQuery {
+    connection: Connection,
+    id: u16,
+    name: String,
+}
+
+struct Table {
+    names: ExpiringMap<IpAddr, String>,
+    /// The queries awaiting their response.
+    queries: ExpiringMap<SentQuery, ()>,
+    /// The names learnt since they were last merged.
+    new: IpTable,
+}
+
+impl Default for Table {
+    fn default() -> Self {
+        Self {
+            names: ExpiringMap::new(MAX_SNOOPED_NAMES),
+            queries: ExpiringMap::new(MAX_PENDING_QUERIES),
+            new: IpTable::new(),
+        }
+    }
+}
+
+impl Table {
+    fn query(&mut self, query: SentQuery, now: Instant) {
+        self.queries.expire(now);
+        self.queries.insert(query, (), now + QUERY_TIMEOUT);
+    }
+
+    fn learn(&mut self, query: &SentQuery, answers: Vec<(IpAddr, Duration)>, now: Instant) {
+        self.queries.expire(now);
+        if self.queries.remove(query).is_none() {
+            return;
+        }
+        for (ip, ttl) in answers {
+            self.new.insert(ip, query.name.clone());
+            self.names.insert(ip, query.name.clone(), now + ttl);
+        }
+    }
+
+    fn merge_into(&mut self, ip_to_host: &mut IpTable, now: Instant) {
+        self.names.expire(now);
+        for (ip, host) in ip_to_host.iter_mut() {
+            if let Some(name) = self.names.get(ip) {
+                host.clone_from(name);
+            }
+        }
+        ip_to_host.extend(self.new.drain());
+    }
+}
+
+/// A map from which entries expire, and which forgets the entries expiring soonest when full.
+struct ExpiringMap<K, V> {
+    capacity: usize,
+    entries: HashMap<K, (V, Instant)>,
+    /// The keys of the entries, in the order they expire in.
+    expiries: BTreeSet<(Instant, K)>,
+}
+
+impl<K: Clone + Eq + Hash + Ord, V> ExpiringMap<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            expiries: BTreeSet::new(),
+        }
+    }
+
+    fn insert(&mut self, key: K, value: V, expires: Instant) {
+        if let Some((_, old_expires)) = self.entries.remove(&key) {
+            self.expiries.remove(&(old_expires, key.clone()));
+        } else if self.entries.len() >= self.capacity {
+            if let Some((_, soonest)) = self.expiries.pop_first() {
+                self.entries.remove(&soonest);
+            }
+        }
+        self.expiries.insert((expires, key.clone()));
+        self.entries.insert(key, (value, expires));
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let (value, expires) = self.entries.remove(key)?;
+        self.expiries.remove(&(expires, key.clone()));
+        Some(value)
+    }
+
+    fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key).map(|(value, _)| value)
+    }
+
+    /// Removes the entries which expired by `now`.
+    fn expire(&mut self, now: Instant) {
+        while let Some((expires, _)) = self.expiries.first() {
+            if *expires > now {
+                break;
+            }
+            let (_, key) = self.expiries.pop_first().unwrap();
+            self.entries.remove(&key);
+        }
+    }
+}
+
+/// The query of a DNS message of type `message_type` sent over `connection`, and the message.
+fn parse(
+    connection: &Connection,
+    payload: &[u8],
+    message_type: MessageType,
+) -> Option<(SentQuery, Message)> {
+    let message = Message::from_vec(payload).ok()?;
+    if message.metadata.message_type != message_type {
+        return None;
+    }
+    let name = message.queries.first()?.name().to_utf8();
+    let query = SentQuery {
+        connection: *connection,
+        id: message.metadata.id,
+        name: name.trim_end_matches('.').to_string(),
+    };
+    Some((query, message))
+}
+
+/// The addresses answered in a DNS response, and how long they are valid.
+///
+/// They are named after the queried name, rather than the last link of a CNAME chain.
+fn answers(message: &Message) -> Vec<(IpAddr, Duration)> {
+    message
+        .answers
+        .iter()
+        .filter_map(|record| match &record.data {
+            RData::A(a) => Some((IpAddr::V4(a.0), record.ttl)),
+            RData::AAAA(aaaa) => Some((IpAddr::V6(aaaa.0), record.ttl)),
+            _ => None,
+        })
+        .map(|(ip, ttl)| (ip, Duration::from_secs(ttl.into())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use hickory_proto::{
+        op::{OpCode, Query},
+        rr::{
+            rdata::{A, AAAA, CNAME},
+            Name, Record, RecordType,
+        },
+    };
+
+    use super::*;
+    use crate::network::Protocol;
+
+    fn name(name: &str) -> Name {
+        Name::from_ascii(name).unwrap()
+    }
+
+    fn connection(server: &str) -> Connection {
+        let server = format!("{server}:53").parse().unwrap();
+        Connection::new(server, "10.0.0.2".parse().unwrap(), 40000, Protocol::Udp)
+    }
+
+    fn message(message_type: MessageType, query: &str, answers: Vec<Record>) -> Vec<u8> {
+        let mut message = Message::new(1, message_type, OpCode::Query);
+        message.add_query(Query::query(name(query), RecordType::A));
+        message.add_answers(answers);
+        message.to_vec().unwrap()
+    }
+
+    fn query(query: &str) -> Vec<u8> {
+        message(MessageType::Query, query, vec![])
+    }
+
+    fn response(query: &str, answers: Vec<Record>) -> Vec<u8> {
+        message(MessageType::Response, query, answers)
+    }
+
+    fn a_record(owner: &str, ip: [u8; 4]) -> Record {
+        let [a, b, c, d] = ip;
+        Record::from_rdata(name(owner), 60, RData::A(A::new(a, b, c, d)))
+    }
+
+    #[test]
+    fn maps_answers_to_the_queried_name() {
+        let payload = response(
+            "www.example.com.",
+            vec![
+                Record::from_rdata(
+                    name("www.example.com."),
+                    60,
+                    RData::CNAME(CNAME(name("edge.cdn.net."))),
+                ),
+                a_record("edge.cdn.net.", [1, 2, 3, 4]),
+                Record::from_rdata(
+                    name("edge.cdn.net."),
+                    60,
+                    RData::AAAA(AAAA::new(0xfd00, 0, 0, 0, 0, 0, 0, 1)),
+                ),
+            ],
+        );
+
+        let hostnames = SnoopedHostnames::default();
+        let connection = connection("10.0.0.1");
+        hostnames.query(&connection, &query("www.example.com."));
+        hostnames.learn(&connection, &payload);
+        let expected = IpTable::from([
+            ("1.2.3.4".parse().unwrap(), "www.example.com".into()),
+            ("fd00::1".parse().unwrap(), "www.example.com".into()),
+        ]);
+        assert_eq!(merged(&hostnames), expected);
+        // only new names are handed out
+        assert!(merged(&hostnames).is_empty());
+    }
+
+    fn merged(hostnames: &SnoopedHostnames) -> IpTable {
+        let mut ip_to_host = IpTable::new();
+        hostnames.merge_into(&mut ip_to_host);
+        ip_to_host
+    }
+
+    fn sent_query(name: &str) -> SentQuery {
+        SentQuery {
+            connection: connection("10.0.0.1"),
+            id: 1,
+            name: name.into(),
+        }
+    }
+
+    fn answer(ip: &str, ttl: u64) -> (IpAddr, Duration) {
+        (ip.parse().unwrap(), Duration::from_secs(ttl))
+    }
+
+    #[test]
+    fn overrides_reverse_lookups_until_the_names_expire() {
+        let start = Instant::now();
+        let mut table = Table::default();
+        let query = sent_query("www.example.com");
+        table.query(query.clone(), start);
+        table.learn(&query, vec![answer("1.2.3.4", 60)], start);
+        let ip = "1.2.3.4".parse().unwrap();
+        let reverse_lookup = || IpTable::from([(ip, "edge.cdn.net".to_string())]);
+
+        let mut ip_to_host = reverse_lookup();
+        table.merge_into(&mut ip_to_host, start + Duration::from_secs(30));
+        assert_eq!(ip_to_host[&ip], "www.example.com");
+
+        let mut ip_to_host = reverse_lookup();
+        table.merge_into(&mut ip_to_host, start + Duration::from_secs(60));
+        assert_eq!(ip_to_host[&ip], "edge.cdn.net");
+        assert!(table.names.entries.is_empty());
+        assert!(table.names.expiries.is_empty());
+    }
+
+    #[test]
+    fn forgets_the_names_expiring_soonest_when_full() {
+        let now = Instant::now();
+        let mut table = Table::default();
+        let query = sent_query("example.com");
+        let answers = (0..MAX_SNOOPED_NAMES as u64)
+            .map(|i| {
+                let ip = IpAddr::from((i as u32).to_be_bytes()).to_string();
+                answer(&ip, 60 + i)
+            })
+            .collect();
+        table.query(query.clone(), now);
+        table.learn(&query, answers, now);
+        table.query(query.clone(), now);
+        table.learn(&query, vec![answer("fd00::1", 60)], now);
+
+        assert_eq!(table.names.entries.len(), MAX_SNOOPED_NAMES);
+        assert_eq!(table.names.expiries.len(), MAX_SNOOPED_NAMES);
+        assert!(table.names.get(&IpAddr::from([0, 0, 0, 0])).is_none());
+        assert!(table.names.get(&"fd00::1".parse().unwrap()).is_some());
+    }
+
+    #[test]
+    fn forgets_the_oldest_queries_when_full() {
+        let now = Instant::now();
+        let mut table = Table::default();
+        for i in 0..=MAX_PENDING_QUERIES {
+            let query = sent_query(&format!("{i}.example.com"));
+            table.query(query, now + Duration::from_millis(i as u64));
+        }
+        assert_eq!(table.queries.entries.len(), MAX_PENDING_QUERIES);
+        assert!(table.queries.get(&sent_query("0.example.com")).is_none());
+
+        table.queries.expire(now + 2 * QUERY_TIMEOUT);
+        assert!(table.queries.entries.is_empty());
+        assert!(table.queries.expiries.is_empty());
+    }
+
+    #[test]
+    fn ignores_unsolicited_responses() {
+        let hostnames = SnoopedHostnames::default();
+        let server = connection("10.0.0.1");
+        let payload = response("example.com.", vec![a_record("example.com.", [1, 2, 3, 4])]);
+        // never asked
+        hostnames.learn(&server, &payload);
+        // asked another server
+        hostnames.query(&connection("10.0.0.3"), &query("example.com."));
+        hostnames.learn(&server, &payload);
+        // asked for another name
+        hostnames.query(&server, &query("example.org."));
+        hostnames.learn(&server, &payload);
+        assert!(merged(&hostnames).is_empty());
+
+        // only the first response to a query is trusted
+        hostnames.query(&server, &query("example.com."));
+        hostnames.learn(&server, &payload);
+        assert_eq!(merged(&hostnames).len(), 1);
+        hostnames.learn(&server, &payload);
+        assert!(merged(&hostnames).is_empty());
+    }
+
+    #[test]
+    fn ignores_queries_and_garbage() {
+        let hostnames = SnoopedHostnames::default();
+        let server = connection("10.0.0.1");
+        hostnames.query(&server, &query("example.com."));
+        hostnames.learn(&server, &query("example.com."));
+        hostnames.learn(&server, b"not a DNS message");
+        hostnames.learn(&server, &response("example.com.", vec![]));
+        assert!(merged(&hostnames).is_empty());
+    }
+}
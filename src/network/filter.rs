@@ -0,0 +1,477 @@
+//! Capture filters, written as a subset of the tcpdump (pcap-filter) syntax.
+//!
+//! Supported primitives are `[src|dst] host ADDR`, `[src|dst] net CIDR`,
+//! `[src|dst] port PORT`, `[src|dst] portrange LOW-HIGH`, `tcp`, `udp`, `ip` and `ip6`,
+//! optionally qualified by a protocol (eg. `tcp dst port 443`), and combined with
+//! `and`/`&&`, `or`/`||`, `not`/`!` and parentheses. Like in tcpdump, `and` and `or`
+//! have the same precedence, and a bare value after either reuses the qualifiers of
+//! the previous primitive, so `port 80 or 443` means `port 80 or port 443`.
+
+use std::{fmt, net::IpAddr, ops::RangeInclusive, str::FromStr};
+
+use pnet::ipnetwork::IpNetwork;
+use thiserror::Error;
+
+use crate::network::{Direction, Protocol, Segment};
+
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum FilterParseError {
+    #[error("the filter expression is empty")]
+    Empty,
+    #[error("unexpected end of filter expression, expected {0}")]
+    UnexpectedEnd(&'static str),
+    #[error("unexpected `{0}` in filter expression, expected {1}")]
+    Unexpected(String, &'static str),
+    #[error("`{0}` is not a valid {1}")]
+    InvalidValue(String, &'static str),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    Source,
+    Destination,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Primitive {
+    Family(IpFamily),
+    Transport(Protocol),
+    Host(Option<Endpoint>, IpAddr),
+    Net(Option<Endpoint>, IpNetwork),
+    Port(Option<Endpoint>, RangeInclusive<u16>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FilterExpr {
+    And(Box<FilterExpr>, Box<FilterExpr>),
+    Or(Box<FilterExpr>, Box<FilterExpr>),
+    Not(Box<FilterExpr>),
+    Primitive(Primitive),
+}
+
+/// A parsed `--filter` expression, along with the text it was parsed from.
+#[derive(Clone, Debug)]
+pub struct CaptureFilter {
+    expression: String,
+    root: FilterExpr,
+}
+
+impl CaptureFilter {
+    pub fn root(&self) -> &FilterExpr {
+        &self.root
+    }
+
+    /// Tests a segment already extracted by the sniffer, for when the kernel could not do it.
+    pub fn matches(&self, segment: &Segment) -> bool {
+        self.root.matches(segment)
+    }
+}
+
+impl fmt::Display for CaptureFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+impl FromStr for CaptureFilter {
+    type Err = FilterParseError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(expression);
+        if tokens.is_empty() {
+            return Err(FilterParseError::Empty);
+        }
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            last_qualifiers: None,
+        };
+        let root = parser.parse_expr()?;
+        if let Some(token) = parser.peek() {
+            return Err(FilterParseError::Unexpected(
+                token.into(),
+                "`and`, `or` or the end of the expression",
+            ));
+        }
+        Ok(CaptureFilter {
+            expression: expression.split_whitespace().collect::<Vec<_>>().join(" "),
+            root,
+        })
+    }
+}
+
+impl FilterExpr {
+    fn matches(&self, segment: &Segment) -> bool {
+        match self {
+            FilterExpr::And(a, b) => a.matches(segment) && b.matches(segment),
+            FilterExpr::Or(a, b) => a.matches(segment) || b.matches(segment),
+            FilterExpr::Not(a) => !a.matches(segment),
+            FilterExpr::Primitive(primitive) => primitive.matches(segment),
+        }
+    }
+}
+
+impl Primitive {
+    fn matches(&self, segment: &Segment) -> bool {
+        let connection = &segment.connection;
+        let local = (connection.local_socket.ip, connection.local_socket.port);
+        let remote = (connection.remote_socket.ip, connection.remote_socket.port);
+        let (source, destination) = match segment.direction {
+            Direction::Upload => (local, remote),
+            Direction::Download => (remote, local),
+        };
+        let endpoints = |endpoint: &Option<Endpoint>| match endpoint {
+            Some(Endpoint::Source) => vec![source],
+            Some(Endpoint::Destination) => vec![destination],
+            None => vec![source, destination],
+        };
+
+        match self {
+            Primitive::Family(IpFamily::V4) => remote.0.is_ipv4(),
+            Primitive::Family(IpFamily::V6) => remote.0.is_ipv6(),
+            Primitive::Transport(protocol) => connection.local_socket.protocol == *protocol,
+            Primitive::Host(endpoint, host) => endpoints(endpoint).iter().any(|(ip, _)| ip == host),
+            Primitive::Net(endpoint, net) => {
+                endpoints(endpoint).iter().any(|(ip, _)| net.contains(*ip))
+            }
+            Primitive::Port(endpoint, ports) => endpoints(endpoint)
+                .iter()
+                .any(|(_, port)| ports.contains(port)),
+        }
+    }
+}
+
+fn tokenize(expression: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' | ')' | '!' => tokens.push(c.to_string()),
+            '&' | '|' => {
+                // `&&` and `||`; a lone `&` or `|` is left for the parser to reject
+                if chars.next_if_eq(&c).is_some() {
+                    tokens.push(format!("{c}{c}"));
+                } else {
+                    tokens.push(c.to_string());
+                }
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !"()!&|".contains(*c)) {
+                    word.push(c);
+                }
+                tokens.push(word);
+            }
+        }
+    }
+    tokens
+}
+
+/// The qualifiers of a primitive, reused when a bare value follows `and` or `or`.
+#[derive(Copy, Clone, Debug)]
+struct Qualifiers {
+    protocol: Option<Protocol>,
+    endpoint: Option<Endpoint>,
+    kind: Kind,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    Host,
+    Net,
+    Port,
+    PortRange,
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+    last_qualifiers: Option<Qualifiers>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(String::as_str)
+    }
+
+    fn next(&mut self, expected: &'static str) -> Result<&str, FilterParseError> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .ok_or(FilterParseError::UnexpectedEnd(expected))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn parse_expr(&mut self) -> Result<FilterExpr, FilterParseError> {
+        let mut expr = self.parse_unary()?;
+        while let Some(token) = self.peek() {
+            let combine = match token {
+                "and" | "&&" => FilterExpr::And,
+                "or" | "||" => FilterExpr::Or,
+                _ => break,
+            };
+            self.position += 1;
+            let rhs = self.parse_unary()?;
+            expr = combine(Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<FilterExpr, FilterParseError> {
+        match self.peek() {
+            Some("not" | "!") => {
+                self.position += 1;
+                Ok(FilterExpr::Not(Box::new(self.parse_unary()?)))
+            }
+            Some("(") => {
+                self.position += 1;
+                let expr = self.parse_expr()?;
+                match self.next("`)`")? {
+                    ")" => Ok(expr),
+                    token => Err(FilterParseError::Unexpected(token.into(), "`)`")),
+                }
+            }
+            _ => self.parse_primitive(),
+        }
+    }
+
+    fn parse_primitive(&mut self) -> Result<FilterExpr, FilterParseError> {
+        const EXPECTED: &str = "a primitive such as `host`, `net`, `port` or `tcp`";
+
+        let mut token = self.next(EXPECTED)?.to_owned();
+
+        // a bare value reuses the qualifiers of the previous primitive
+        if let Some(last_qualifiers) = self.last_qualifiers {
+            if is_value(&token) {
+                return self.parse_value(last_qualifiers, &token);
+            }
+        }
+
+        let family = match token.as_str() {
+            "ip" => Some(IpFamily::V4),
+            "ip6" => Some(IpFamily::V6),
+            _ => None,
+        };
+        if let Some(family) = family {
+            let primitive = FilterExpr::Primitive(Primitive::Family(family));
+            // `ip host 10.0.0.1` is `host 10.0.0.1` restricted to that family
+            return match self.peek() {
+                Some("src" | "dst" | "host" | "net") => {
+                    let rest = self.parse_primitive()?;
+                    Ok(FilterExpr::And(Box::new(primitive), Box::new(rest)))
+                }
+                _ => Ok(primitive),
+            };
+        }
+
+        let protocol = match token.as_str() {
+            "tcp" => Some(Protocol::Tcp),
+            "udp" => Some(Protocol::Udp),
+            _ => None,
+        };
+        if let Some(protocol) = protocol {
+            if !matches!(self.peek(), Some("src" | "dst" | "port" | "portrange")) {
+                return Ok(FilterExpr::Primitive(Primitive::Transport(protocol)));
+            }
+            token = self.next(EXPECTED)?.to_owned();
+        }
+
+        let endpoint = match token.as_str() {
+            "src" => Some(Endpoint::Source),
+            "dst" => Some(Endpoint::Destination),
+            _ => None,
+        };
+        if endpoint.is_some() {
+            token = self
+                .next("`host`, `net`, `port` or `portrange`")?
+                .to_owned();
+        }
+
+        let kind = match token.as_str() {
+            "host" => Kind::Host,
+            "net" => Kind::Net,
+            "port" => Kind::Port,
+            "portrange" => Kind::PortRange,
+            // like tcpdump, a lone address means `host` or `net`
+            value if is_value(value) && protocol.is_none() && endpoint.is_none() => {
+                let kind = if value.contains('/') {
+                    Kind::Net
+                } else {
+                    Kind::Host
+                };
+                let qualifiers = Qualifiers {
+                    protocol,
+                    endpoint,
+                    kind,
+                };
+                return self.parse_value(qualifiers, value);
+            }
+            _ => return Err(FilterParseError::Unexpected(token, EXPECTED)),
+        };
+        if protocol.is_some() && matches!(kind, Kind::Host | Kind::Net) {
+            return Err(FilterParseError::Unexpected(
+                token,
+                "`port` or `portrange` after a protocol",
+            ));
+        }
+
+        let value = self.next("a value")?.to_owned();
+        let qualifiers = Qualifiers {
+            protocol,
+            endpoint,
+            kind,
+        };
+        self.parse_value(qualifiers, &value)
+    }
+
+    fn parse_value(
+        &mut self,
+        qualifiers: Qualifiers,
+        value: &str,
+    ) -> Result<FilterExpr, FilterParseError> {
+        let Qualifiers {
+            protocol,
+            endpoint,
+            kind,
+        } = qualifiers;
+        let primitive = match kind {
+            Kind::Host => Primitive::Host(
+                endpoint,
+                value
+                    .parse()
+                    .map_err(|_| FilterParseError::InvalidValue(value.into(), "IP address"))?,
+            ),
+            Kind::Net => Primitive::Net(
+                endpoint,
+                value.parse().map_err(|_| {
+                    FilterParseError::InvalidValue(value.into(), "network in CIDR notation")
+                })?,
+            ),
+            Kind::Port => {
+                let port = parse_port(value)?;
+                Primitive::Port(endpoint, port..=port)
+            }
+            Kind::PortRange => {
+                let invalid = || FilterParseError::InvalidValue(value.into(), "port range");
+                let (low, high) = value.split_once('-').ok_or_else(invalid)?;
+                let (low, high) = (parse_port(low)?, parse_port(high)?);
+                if low > high {
+                    return Err(invalid());
+                }
+                Primitive::Port(endpoint, low..=high)
+            }
+        };
+        self.last_qualifiers = Some(qualifiers);
+
+        let primitive = FilterExpr::Primitive(primitive);
+        Ok(match protocol {
+            Some(protocol) => FilterExpr::And(
+                Box::new(FilterExpr::Primitive(Primitive::Transport(protocol))),
+                Box::new(primitive),
+            ),
+            None => primitive,
+        })
+    }
+}
+
+/// Whether a token is an address, a network or a port, as opposed to a keyword.
+fn is_value(token: &str) -> bool {
+    token.starts_with(|c: char| c.is_ascii_digit()) || token.contains(':')
+}
+
+fn parse_port(value: &str) -> Result<u16, FilterParseError> {
+    value
+        .parse()
+        .map_err(|_| FilterParseError::InvalidValue(value.into(), "port"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use rstest::rstest;
+
+    use super::*;
+    use crate::network::Connection;
+
+    fn segment(direction: Direction, protocol: Protocol) -> Segment {
+        // 10.0.0.2:50000 is local, 1.1.1.1:443 is remote
+        Segment {
+            interface_name: "eth0".into(),
+            connection: Connection::new(
+                "1.1.1.1:443".parse().unwrap(),
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+                50000,
+                protocol,
+            ),
+            direction,
+            data_length: 100,
+            packets: 1,
+            server_name: None,
+            dns_message: None,
+        }
+    }
+
+    #[rstest]
+    #[case("tcp", true)]
+    #[case("udp", false)]
+    #[case("ip", true)]
+    #[case("ip6", false)]
+    #[case("host 1.1.1.1", true)]
+    #[case("1.1.1.1", true)]
+    #[case("src host 1.1.1.1", false)]
+    #[case("dst host 1.1.1.1", true)]
+    #[case("ip dst host 1.1.1.1", true)]
+    #[case("net 10.0.0.0/8", true)]
+    #[case("src net 10.0.0.0/8", true)]
+    #[case("dst net 10.0.0.0/8", false)]
+    #[case("port 443", true)]
+    #[case("tcp dst port 443", true)]
+    #[case("udp port 443", false)]
+    #[case("src port 443", false)]
+    #[case("portrange 40000-60000", true)]
+    #[case("port 80 or 443", true)]
+    #[case("port 80 or 8080", false)]
+    #[case("not port 443", false)]
+    #[case("! udp", true)]
+    #[case("tcp and not (port 80 || port 8080)", true)]
+    #[case("port 80 or port 443 and udp", false)]
+    #[case("port 443 and (udp or host 1.1.1.1)", true)]
+    fn matches_upload(#[case] expression: &str, #[case] expected: bool) {
+        let filter: CaptureFilter = expression.parse().unwrap();
+        assert_eq!(
+            filter.matches(&segment(Direction::Upload, Protocol::Tcp)),
+            expected
+        );
+    }
+
+    #[test]
+    fn source_follows_direction() {
+        let filter: CaptureFilter = "src host 1.1.1.1 and dst port 50000".parse().unwrap();
+        assert!(filter.matches(&segment(Direction::Download, Protocol::Tcp)));
+        assert!(!filter.matches(&segment(Direction::Upload, Protocol::Tcp)));
+    }
+
+    #[rstest]
+    #[case("", FilterParseError::Empty)]
+    #[case("port", FilterParseError::UnexpectedEnd("a value"))]
+    #[case("port http", FilterParseError::InvalidValue("http".into(), "port"))]
+    #[case("host example.com", FilterParseError::InvalidValue("example.com".into(), "IP address"))]
+    #[case("portrange 90-80", FilterParseError::InvalidValue("90-80".into(), "port range"))]
+    #[case("(tcp", FilterParseError::UnexpectedEnd("`)`"))]
+    #[case(
+        "tcp udp",
+        FilterParseError::Unexpected("udp".into(), "`and`, `or` or the end of the expression")
+    )]
+    fn rejects_invalid(#[case] expression: &str, #[case] expected: FilterParseError) {
+        assert_eq!(expression.parse::<CaptureFilter>().unwrap_err(), expected);
+    }
+}
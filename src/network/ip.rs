@@ -0,0 +1,423 @@
+//! The transport-layer payload of IP packets, past any IPv6 extension headers, and the
+//! datagrams their fragments belong to.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    mem,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use pnet::packet::{
+    ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
+    ipv4::{Ipv4Flags, Ipv4Packet},
+    ipv6::Ipv6Packet,
+};
+
+use crate::network::Protocol;
+
+/// How long the remaining fragments of a datagram are waited for after the first to arrive.
+///
+/// Fragments of a datagram are sent back to back, so anything later is lost or reordered beyond
+/// use, and remembering it would only fill the table.
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// How many datagrams are waited for at most, so that stray fragments cannot exhaust memory.
+const MAX_DATAGRAMS: usize = 4096;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Fragment {
+    /// Identifies the datagram among those between the same addresses.
+    pub id: u32,
+    /// Where the fragment starts in the datagram, in bytes.
+    pub offset: u16,
+    /// The protocol every fragment names, which for IPv6 is the header after the fragment
+    /// header, and may be another extension header.
+    pub protocol: IpNextHeaderProtocol,
+    /// Whether this is the final fragment, which has the more fragments flag cleared.
+    pub last: bool,
+}
+
+#[derive(Debug)]
+pub struct IpPayload<'a> {
+    pub protocol: IpNextHeaderProtocol,
+    pub bytes: &'a [u8],
+    /// The part of a fragmented datagram the payload is, if it is not all of it.
+    pub fragment: Option<Fragment>,
+}
+
+impl<'a> IpPayload<'a> {
+    /// The payload of an IPv4 packet, passed in as `payload` since the packet only lends it out.
+    pub fn ipv4(packet: &Ipv4Packet, payload: &'a [u8]) -> Self {
+        let offset = packet.get_fragment_offset() * 8;
+        let more_fragments = packet.get_flags() & Ipv4Flags::MoreFragments != 0;
+        let fragment = (offset != 0 || more_fragments).then(|| Fragment {
+            id: packet.get_identification().into(),
+            offset,
+            protocol: packet.get_next_level_protocol(),
+            last: !more_fragments,
+        });
+        IpPayload {
+            protocol: packet.get_next_level_protocol(),
+            bytes: payload,
+            fragment,
+        }
+    }
+
+    /// The payload of an IPv6 packet past its extension headers, given what follows its fixed
+    /// header, or `None` if an extension header is cut short.
+    pub fn ipv6(packet: &Ipv6Packet, mut payload: &'a [u8]) -> Option<Self> {
+        let mut protocol = packet.get_next_header();
+        let mut fragment = None;
+        loop {
+            // every extension header starts with the next header, and most with their length
+            let header_len = match protocol {
+                IpNextHeaderProtocols::Hopopt
+                | IpNextHeaderProtocols::Ipv6Route
+                | IpNextHeaderProtocols::Ipv6Opts => (usize::from(*payload.get(1)?) + 1) * 8,
+                IpNextHeaderProtocols::Ah => (usize::from(*payload.get(1)?) + 2) * 4,
+                IpNextHeaderProtocols::Ipv6Frag => {
+                    let header = payload.get(..8)?;
+                    let offset_and_flags = u16::from_be_bytes([header[2], header[3]]);
+                    let offset = offset_and_flags & 0xfff8;
+                    let more_fragments = offset_and_flags & 0x0001 != 0;
+                    // a lone fragment, eg. one sent in reply to a too small MTU, is not split
+                    if offset != 0 || more_fragments {
+                        let next_header = IpNextHeaderProtocol(header[0]);
+                        let this_fragment = Fragment {
+                            id: u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+                            offset,
+                            protocol: next_header,
+                            last: !more_fragments,
+                        };
+                        // the headers after the fragment header are only in the first fragment
+                        if offset != 0 {
+                            return Some(IpPayload {
+                                protocol: next_header,
+                                bytes: &payload[8..],
+                                fragment: Some(this_fragment),
+                            });
+                        }
+                        fragment = Some(this_fragment);
+                    }
+                    8
+                }
+                _ => {
+                    return Some(IpPayload {
+                        protocol,
+                        bytes: payload,
+                        fragment,
+                    })
+                }
+            };
+            protocol = IpNextHeaderProtocol(payload[0]);
+            payload = payload.get(header_len..)?;
+        }
+    }
+
+    /// Whether the payload starts with the transport-layer header, which only the first
+    /// fragment of a datagram does.
+    pub fn has_transport_header(&self) -> bool {
+        self.fragment.is_none_or(|fragment| fragment.offset == 0)
+    }
+}
+
+/// A fragmented datagram, as told apart by RFC 791 and RFC 8200.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct DatagramId {
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    /// The protocol of [`Fragment`], the same in every fragment of the datagram.
+    pub protocol: IpNextHeaderProtocol,
+    pub id: u32,
+}
+
+#[derive(Debug)]
+struct Datagram {
+    /// The transport protocol and the source and destination ports, once the first fragment has
+    /// arrived.
+    ports: Option<(Protocol, u16, u16)>,
+    /// The length of the fragments that arrived before the first one.
+    early_length: u128,
+    /// How many fragments arrived before the first one.
+    early_packets: u128,
+    /// Whether the final fragment has arrived.
+    last_arrived: bool,
+    /// When the first of its fragments to arrive did.
+    arrived: Instant,
+    /// Tells this datagram apart from an earlier one with the same ID in [`Fragments::arrivals`].
+    generation: u64,
+}
+
+impl Datagram {
+    /// Whether the fragments carrying the ports and the end of the datagram have both arrived.
+    fn is_complete(&self) -> bool {
+        self.ports.is_some() && self.last_arrived
+    }
+}
+
+/// Remembers the ports of fragmented datagrams, for the fragments which do not carry them.
+///
+/// A datagram is forgotten once its first and final fragments have arrived, or after
+/// [`REASSEMBLY_TIMEOUT`]. Past [`MAX_DATAGRAMS`], the oldest datagram makes way for a new one.
+#[derive(Debug, Default)]
+pub struct Fragments {
+    datagrams: HashMap<DatagramId, Datagram>,
+    /// The datagrams in the order they arrived in, including some already forgotten.
+    arrivals: VecDeque<(u64, DatagramId)>,
+    generations: u64,
+}
+
+impl Fragments {
+    /// Records the protocol and ports of a datagram when its first fragment arrives, and returns
+    /// the length and number of any fragments that arrived before it, which are yet to be counted.
+    pub fn first_arrived(
+        &mut self,
+        datagram_id: DatagramId,
+        ports: (Protocol, u16, u16),
+    ) -> (u128, u128) {
+        let datagram = self.datagram(datagram_id, Instant::now());
+        datagram.ports = Some(ports);
+        let early = (
+            mem::take(&mut datagram.early_length),
+            mem::take(&mut datagram.early_packets),
+        );
+        if datagram.is_complete() {
+            self.datagrams.remove(&datagram_id);
+        }
+        early
+    }
+
+    /// Returns the protocol and ports of the datagram a later fragment belongs to, or `None` if
+    /// its first fragment has not arrived yet, in which case its length is counted along with the
+    /// first.
+    pub fn later_arrived(
+        &mut self,
+        datagram_id: DatagramId,
+        length: u128,
+        last: bool,
+    ) -> Option<(Protocol, u16, u16)> {
+        let datagram = self.datagram(datagram_id, Instant::now());
+        datagram.last_arrived |= last;
+        if datagram.ports.is_none() {
+            datagram.early_length += length;
+            datagram.early_packets += 1;
+        }
+        let ports = datagram.ports;
+        if datagram.is_complete() {
+            self.datagrams.remove(&datagram_id);
+        }
+        ports
+    }
+
+    fn datagram(&mut self, datagram_id: DatagramId, now: Instant) -> &mut Datagram {
+        self.prune(now);
+        if !self.datagrams.contains_key(&datagram_id) {
+            if self.datagrams.len() >= MAX_DATAGRAMS {
+                self.forget_oldest(|_| true);
+            }
+            self.generations += 1;
+            self.arrivals.push_back((self.generations, datagram_id));
+        }
+        let generation = self.generations;
+        self.datagrams.entry(datagram_id).or_insert(Datagram {
+            ports: None,
+            early_length: 0,
+            early_packets: 0,
+            last_arrived: false,
+            arrived: now,
+            generation,
+        })
+    }
+
+    /// Forgets the datagrams whose remaining fragments are no longer waited for.
+    fn prune(&mut self, now: Instant) {
+        while self.forget_oldest(|datagram| now - datagram.arrived >= REASSEMBLY_TIMEOUT) {}
+        // completed datagrams leave their arrival behind, to be dropped here or when at the front
+        if self.arrivals.len() > 2 * MAX_DATAGRAMS {
+            let datagrams = &self.datagrams;
+            self.arrivals.retain(|(generation, datagram_id)| {
+                datagrams
+                    .get(datagram_id)
+                    .is_some_and(|datagram| datagram.generation == *generation)
+            });
+        }
+    }
+
+    /// Forgets the datagram which arrived first if `forget` says so, returning whether it did.
+    fn forget_oldest(&mut self, forget: impl Fn(&Datagram) -> bool) -> bool {
+        while let Some(&(generation, datagram_id)) = self.arrivals.front() {
+            match self.datagrams.get(&datagram_id) {
+                Some(datagram) if datagram.generation == generation => {
+                    if !forget(datagram) {
+                        return false;
+                    }
+                    self.datagrams.remove(&datagram_id);
+                    self.arrivals.pop_front();
+                    return true;
+                }
+                // already forgotten
+                _ => {
+                    self.arrivals.pop_front();
+                }
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pnet::packet::{ipv6::MutableIpv6Packet, Packet};
+
+    use super::*;
+
+    fn ipv6(next_header: u8, payload: &[u8]) -> Vec<u8> {
+        let mut buffer = vec![0; 40 + payload.len()];
+        let mut packet = MutableIpv6Packet::new(&mut buffer).unwrap();
+        packet.set_version(6);
+        packet.set_payload_length(payload.len() as u16);
+        packet.set_next_header(IpNextHeaderProtocol(next_header));
+        packet.set_payload(payload);
+        buffer
+    }
+
+    #[test]
+    fn walks_ipv6_extension_headers() {
+        let mut payload = vec![];
+        // hop-by-hop options, of 8 bytes
+        payload.extend([43, 0, 0, 0, 0, 0, 0, 0]);
+        // a routing header, of 24 bytes
+        payload.extend([44, 2]);
+        payload.extend([0; 22]);
+        // a fragment header at offset 1480, with more fragments to follow
+        payload.extend([17, 0, 0x05, 0xc9, 0, 0, 0x12, 0x34]);
+        payload.extend(b"not a UDP header");
+
+        let packet = ipv6(0, &payload);
+        let packet = Ipv6Packet::new(&packet).unwrap();
+        let payload = IpPayload::ipv6(&packet, packet.payload()).unwrap();
+        assert_eq!(payload.protocol, IpNextHeaderProtocols::Udp);
+        assert_eq!(payload.bytes, b"not a UDP header");
+        assert_eq!(
+            payload.fragment,
+            Some(Fragment {
+                id: 0x1234,
+                offset: 1480,
+                protocol: IpNextHeaderProtocols::Udp,
+                last: false,
+            })
+        );
+        assert!(!payload.has_transport_header());
+    }
+
+    #[test]
+    fn stops_at_the_fragment_header_of_later_fragments() {
+        let fragment = |offset_and_flags: u16, rest: &[u8]| {
+            // a fragment header followed by destination options
+            let mut payload = vec![60, 0];
+            payload.extend(offset_and_flags.to_be_bytes());
+            payload.extend([0, 0, 0x12, 0x34]);
+            payload.extend(rest);
+            ipv6(44, &payload)
+        };
+        let expected_fragment = |offset, last| Fragment {
+            id: 0x1234,
+            offset,
+            protocol: IpNextHeaderProtocols::Ipv6Opts,
+            last,
+        };
+
+        // the first fragment carries the destination options, then UDP
+        let first = fragment(0x0001, &[17, 0, 0, 0, 0, 0, 0, 0, 0x14, 0xe9, 0, 0x35]);
+        let first = Ipv6Packet::new(&first).unwrap();
+        let payload = IpPayload::ipv6(&first, first.payload()).unwrap();
+        assert_eq!(payload.protocol, IpNextHeaderProtocols::Udp);
+        assert_eq!(payload.bytes, [0x14, 0xe9, 0, 0x35]);
+        assert_eq!(payload.fragment, Some(expected_fragment(0, false)));
+
+        // later ones only carry the rest of the datagram, which is not a header
+        let later = fragment(0x05c8, &[0xff; 16]);
+        let later = Ipv6Packet::new(&later).unwrap();
+        let payload = IpPayload::ipv6(&later, later.payload()).unwrap();
+        assert_eq!(payload.protocol, IpNextHeaderProtocols::Ipv6Opts);
+        assert_eq!(payload.bytes, [0xff; 16]);
+        assert_eq!(payload.fragment, Some(expected_fragment(1480, true)));
+    }
+
+    #[test]
+    fn ignores_atomic_fragments_and_cut_short_headers() {
+        let packet = ipv6(44, &[6, 0, 0, 0, 0, 0, 0, 1]);
+        let packet = Ipv6Packet::new(&packet).unwrap();
+        let payload = IpPayload::ipv6(&packet, packet.payload()).unwrap();
+        assert_eq!(payload.protocol, IpNextHeaderProtocols::Tcp);
+        assert_eq!(payload.fragment, None);
+
+        let packet = ipv6(60, &[6, 1, 0, 0, 0, 0, 0, 0]);
+        let packet = Ipv6Packet::new(&packet).unwrap();
+        assert!(IpPayload::ipv6(&packet, packet.payload()).is_none());
+    }
+
+    fn datagram(id: u32) -> DatagramId {
+        DatagramId {
+            source: "10.0.0.1".parse().unwrap(),
+            destination: "10.0.0.2".parse().unwrap(),
+            protocol: IpNextHeaderProtocols::Udp,
+            id,
+        }
+    }
+
+    const UDP: (Protocol, u16, u16) = (Protocol::Udp, 5353, 53);
+
+    #[test]
+    fn gives_later_fragments_the_ports_of_the_first() {
+        let mut fragments = Fragments::default();
+        assert_eq!(fragments.first_arrived(datagram(1), UDP), (0, 0));
+        assert_eq!(fragments.later_arrived(datagram(1), 100, false), Some(UDP));
+
+        // out of order, the first fragment brings the length of those before it
+        assert_eq!(fragments.later_arrived(datagram(2), 100, false), None);
+        assert_eq!(fragments.later_arrived(datagram(2), 50, false), None);
+        assert_eq!(fragments.first_arrived(datagram(2), UDP), (150, 2));
+        assert_eq!(fragments.later_arrived(datagram(2), 100, false), Some(UDP));
+    }
+
+    #[test]
+    fn forgets_datagrams_once_their_first_and_last_fragments_arrived() {
+        let mut fragments = Fragments::default();
+        fragments.first_arrived(datagram(1), UDP);
+        assert_eq!(fragments.later_arrived(datagram(1), 100, true), Some(UDP));
+        // the final fragment may also overtake the first
+        fragments.later_arrived(datagram(2), 100, true);
+        assert_eq!(fragments.first_arrived(datagram(2), UDP), (100, 1));
+        assert!(fragments.datagrams.is_empty());
+    }
+
+    #[test]
+    fn forgets_datagrams_after_the_timeout() {
+        let start = Instant::now();
+        let mut fragments = Fragments::default();
+        fragments.datagram(datagram(1), start);
+        fragments.datagram(datagram(2), start + Duration::from_secs(3));
+        fragments.prune(start + REASSEMBLY_TIMEOUT);
+        assert_eq!(fragments.datagrams.len(), 1);
+        assert!(fragments.datagrams.contains_key(&datagram(2)));
+    }
+
+    #[test]
+    fn makes_way_for_new_datagrams_when_full() {
+        let mut fragments = Fragments::default();
+        for id in 0..MAX_DATAGRAMS as u32 {
+            fragments.later_arrived(datagram(id), 100, false);
+        }
+        // a completed datagram leaves its arrival behind
+        fragments.first_arrived(datagram(0), UDP);
+        fragments.later_arrived(datagram(0), 100, true);
+
+        fragments.later_arrived(datagram(u32::MAX - 1), 100, false);
+        fragments.later_arrived(datagram(u32::MAX), 100, false);
+        assert_eq!(fragments.datagrams.len(), MAX_DATAGRAMS);
+        assert!(!fragments.datagrams.contains_key(&datagram(1)));
+        assert!(fragments.datagrams.contains_key(&datagram(2)));
+        assert_eq!(fragments.first_arrived(datagram(u32::MAX), UDP), (100, 1));
+    }
+}
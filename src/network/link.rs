@@ -0,0 +1,440 @@
+//! Finds the IP packet in a captured frame, according to the link layer it was captured on,
+//! and optionally the packet a tunnel carries inside it.
+
+use std::net::IpAddr;
+
+use pnet::packet::{
+    ethernet::{EtherType, EtherTypes, EthernetPacket},
+    ip::IpNextHeaderProtocols,
+    ipv4::Ipv4Packet,
+    ipv6::Ipv6Packet,
+    udp::UdpPacket,
+    Packet,
+};
+
+use crate::network::IpPayload;
+
+/// The tag protocol identifiers of 802.1Q and 802.1ad (QinQ), and the one QinQ used before it
+/// was standardised.
+const VLAN_TAG_TYPES: [EtherType; 3] = [EtherTypes::Vlan, EtherTypes::PBridge, EtherTypes::QinQ];
+
+/// The protocol type GRE and GENEVE give an Ethernet frame.
+const TRANSPARENT_ETHERNET_BRIDGING: EtherType = EtherType(0x6558);
+/// The IANA port, and the one Linux defaults to, which flannel and Cilium use.
+pub(crate) const VXLAN_PORTS: [u16; 2] = [4789, 8472];
+pub(crate) const GENEVE_PORT: u16 = 6081;
+/// How many tunnels nested in each other are looked into.
+const MAX_TUNNEL_DEPTH: usize = 4;
+
+/// What precedes the IP header in the frames of an interface.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LinkLayer {
+    Ethernet,
+    RawIp,
+    /// The Linux "cooked" header libpcap gives packets of the "any" device, see
+    /// <https://www.tcpdump.org/linktypes/LINKTYPE_LINUX_SLL.html>.
+    LinuxSll,
+    /// The second version of the cooked header, which names the interface by its index.
+    LinuxSll2,
+}
+
+impl LinkLayer {
+    /// Where the EtherType of the protocol that follows is in the link-layer header, if it has one.
+    pub fn ethertype_offset(self) -> Option<usize> {
+        match self {
+            LinkLayer::Ethernet => Some(12),
+            LinkLayer::RawIp => None,
+            LinkLayer::LinuxSll => Some(14),
+            LinkLayer::LinuxSll2 => Some(0),
+        }
+    }
+
+    pub fn header_len(self) -> usize {
+        match self {
+            LinkLayer::Ethernet => 14,
+            LinkLayer::RawIp => 0,
+            LinkLayer::LinuxSll => 16,
+            LinkLayer::LinuxSll2 => 20,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum IpPacket<'a> {
+    V4(Ipv4Packet<'a>),
+    V6(Ipv6Packet<'a>),
+}
+
+impl IpPacket<'_> {
+    pub fn get_source(&self) -> IpAddr {
+        match self {
+            IpPacket::V4(packet) => packet.get_source().into(),
+            IpPacket::V6(packet) => packet.get_source().into(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Frame<'a> {
+    /// The VLAN IDs the frame was tagged with, outermost first.
+    pub vlan_ids: Vec<u16>,
+    pub ip_packet: IpPacket<'a>,
+    /// The bytes of `ip_packet`, which tunnelled packets are read from.
+    ip_bytes: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    pub fn decode(bytes: &'a [u8], link_layer: LinkLayer) -> Option<Self> {
+        match link_layer.ethertype_offset() {
+            Some(offset) => {
+                let ethertype = bytes.get(offset..offset + 2)?;
+                let ethertype = EtherType(u16::from_be_bytes([ethertype[0], ethertype[1]]));
+                Self::decode_ethertype(ethertype, bytes.get(link_layer.header_len()..)?)
+            }
+            None => Self::decode_ip(bytes, vec![]),
+        }
+    }
+
+    /// Decodes a frame of an interface whose link type is unknown, which holds either a bare IP
+    /// packet at `ip_offset` or an Ethernet frame.
+    pub fn guess(bytes: &'a [u8], ip_offset: usize) -> Option<Self> {
+        Self::decode_ip(bytes.get(ip_offset..)?, vec![]).or_else(|| Self::decode_ethernet(bytes))
+    }
+
+    /// Replaces the packet with the one it carries if it is a VXLAN, GENEVE, GRE or IP-in-IP
+    /// packet, and so on for tunnels inside tunnels.
+    pub fn decapsulate(mut self) -> Self {
+        for _ in 0..MAX_TUNNEL_DEPTH {
+            match self.tunnelled() {
+                Some(inner) => self = inner,
+                None => break,
+            }
+        }
+        self
+    }
+
+    fn tunnelled(&self) -> Option<Self> {
+        let IpPayload {
+            protocol,
+            bytes: payload,
+            fragment,
+        } = match &self.ip_packet {
+            IpPacket::V4(packet) => {
+                let header_len = usize::from(packet.get_header_length()) * 4;
+                let total_len = usize::from(packet.get_total_length()).min(self.ip_bytes.len());
+                IpPayload::ipv4(packet, self.ip_bytes.get(header_len..total_len)?)
+            }
+            IpPacket::V6(packet) => {
+                let total_len =
+                    (40 + usize::from(packet.get_payload_length())).min(self.ip_bytes.len());
+                IpPayload::ipv6(packet, self.ip_bytes.get(40..total_len)?)?
+            }
+        };
+        // the inner packet is only whole when the tunnel packet is
+        if fragment.is_some() {
+            return None;
+        }
+        // the inner frame is accounted to the VLANs the tunnel was on
+        let vlan_ids = self.vlan_ids.clone();
+        match protocol {
+            IpNextHeaderProtocols::Ipv4 | IpNextHeaderProtocols::Ipv6 => {
+                Self::decode_ip(payload, vlan_ids)
+            }
+            IpNextHeaderProtocols::Gre => {
+                let (protocol, payload) = gre_payload(payload)?;
+                Self::decode_protocol(protocol, payload, vlan_ids)
+            }
+            IpNextHeaderProtocols::Udp => {
+                // the source port only spreads tunnels over paths, so only the destination counts
+                let destination_port = UdpPacket::new(payload)?.get_destination();
+                let payload = payload.get(8..)?;
+                match destination_port {
+                    port if VXLAN_PORTS.contains(&port) => Self::decode_protocol(
+                        TRANSPARENT_ETHERNET_BRIDGING,
+                        vxlan_payload(payload)?,
+                        vlan_ids,
+                    ),
+                    GENEVE_PORT => {
+                        let (protocol, payload) = geneve_payload(payload)?;
+                        Self::decode_protocol(protocol, payload, vlan_ids)
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Decodes a packet of the protocol a tunnel header names.
+    fn decode_protocol(protocol: EtherType, bytes: &'a [u8], vlan_ids: Vec<u16>) -> Option<Self> {
+        match protocol {
+            EtherTypes::Ipv4 | EtherTypes::Ipv6 => Self::decode_ip(bytes, vlan_ids),
+            TRANSPARENT_ETHERNET_BRIDGING => Some(Frame {
+                vlan_ids,
+                ..Self::decode_ethernet(bytes)?
+            }),
+            _ => None,
+        }
+    }
+
+    fn decode_ethernet(bytes: &'a [u8]) -> Option<Self> {
+        let frame = EthernetPacket::new(bytes)?;
+        Self::decode_ethertype(
+            frame.get_ethertype(),
+            &bytes[bytes.len() - frame.payload().len()..],
+        )
+    }
+
+    /// Decodes the payload of a link-layer header, which may start with VLAN tags.
+    fn decode_ethertype(mut ethertype: EtherType, mut payload: &'a [u8]) -> Option<Self> {
+        let mut vlan_ids = vec![];
+        while VLAN_TAG_TYPES.contains(&ethertype) {
+            let tag = payload.get(..4)?;
+            vlan_ids.push(u16::from_be_bytes([tag[0], tag[1]]) & 0x0fff);
+            ethertype = EtherType(u16::from_be_bytes([tag[2], tag[3]]));
+            payload = &payload[4..];
+        }
+        let ip_packet = match ethertype {
+            EtherTypes::Ipv4 => IpPacket::V4(Ipv4Packet::new(payload)?),
+            EtherTypes::Ipv6 => IpPacket::V6(Ipv6Packet::new(payload)?),
+            _ => return None,
+        };
+        Some(Frame {
+            vlan_ids,
+            ip_packet,
+            ip_bytes: payload,
+        })
+    }
+
+    /// Decodes a bare IP packet of either version.
+    fn decode_ip(bytes: &'a [u8], vlan_ids: Vec<u16>) -> Option<Self> {
+        let ip_packet = match Ipv4Packet::new(bytes)?.get_version() {
+            4 => IpPacket::V4(Ipv4Packet::new(bytes)?),
+            6 => IpPacket::V6(Ipv6Packet::new(bytes)?),
+            _ => return None,
+        };
+        Some(Frame {
+            vlan_ids,
+            ip_packet,
+            ip_bytes: bytes,
+        })
+    }
+}
+
+/// The protocol and payload of a GRE packet, see RFC 2784 and RFC 2890.
+fn gre_payload(gre: &[u8]) -> Option<(EtherType, &[u8])> {
+    const CHECKSUM_PRESENT: u16 = 0x8000;
+    const ROUTING_PRESENT: u16 = 0x4000;
+    const KEY_PRESENT: u16 = 0x2000;
+    const SEQUENCE_NUMBER_PRESENT: u16 = 0x1000;
+    const VERSION: u16 = 0x0007;
+
+    let header = gre.get(..4)?;
+    let flags = u16::from_be_bytes([header[0], header[1]]);
+    // source routing is obsolete, and version 1 is PPTP's, which carries PPP
+    if flags & (ROUTING_PRESENT | VERSION) != 0 {
+        return None;
+    }
+    let optional_fields = [CHECKSUM_PRESENT, KEY_PRESENT, SEQUENCE_NUMBER_PRESENT]
+        .into_iter()
+        .filter(|&flag| flags & flag != 0)
+        .count();
+    let protocol = EtherType(u16::from_be_bytes([header[2], header[3]]));
+    Some((protocol, gre.get(4 + 4 * optional_fields..)?))
+}
+
+/// The Ethernet frame of a VXLAN packet, see RFC 7348.
+fn vxlan_payload(vxlan: &[u8]) -> Option<&[u8]> {
+    const VNI_PRESENT: u8 = 0x08;
+
+    let header = vxlan.get(..8)?;
+    if header[0] & VNI_PRESENT == 0 {
+        return None;
+    }
+    Some(&vxlan[8..])
+}
+
+/// The protocol and payload of a GENEVE packet, see RFC 8926.
+fn geneve_payload(geneve: &[u8]) -> Option<(EtherType, &[u8])> {
+    let header = geneve.get(..8)?;
+    let version = header[0] >> 6;
+    if version != 0 {
+        return None;
+    }
+    let options_len = usize::from(header[0] & 0x3f) * 4;
+    let protocol = EtherType(u16::from_be_bytes([header[2], header[3]]));
+    Some((protocol, geneve.get(8 + options_len..)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    /// The start of an IPv4 header, from 10.0.0.1 to 10.0.0.2.
+    const IPV4: [u8; 20] = [
+        0x45, 0, 0, 20, 0, 0, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+    ];
+
+    fn ethernet(ethertypes_and_tags: &[u16], payload: &[u8]) -> Vec<u8> {
+        // a destination MAC starting with 0x4, which only the link type tells from IPv4
+        let mut frame = vec![0x40, 0, 0, 0, 0, 1, 0x02, 0, 0, 0, 0, 2];
+        for field in ethertypes_and_tags {
+            frame.extend(field.to_be_bytes());
+        }
+        frame.extend(payload);
+        frame
+    }
+
+    /// An IPv4 packet from 10.0.0.`source`.
+    fn ipv4(source: u8, protocol: u8, payload: &[u8]) -> Vec<u8> {
+        let mut packet = IPV4.to_vec();
+        packet[2..4].copy_from_slice(&(20 + payload.len() as u16).to_be_bytes());
+        packet[9] = protocol;
+        packet[15] = source;
+        packet.extend(payload);
+        packet
+    }
+
+    fn udp(destination_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut datagram = vec![];
+        datagram.extend(50000u16.to_be_bytes());
+        datagram.extend(destination_port.to_be_bytes());
+        datagram.extend((8 + payload.len() as u16).to_be_bytes());
+        datagram.extend([0, 0]);
+        datagram.extend(payload);
+        datagram
+    }
+
+    fn decoded(frame: Option<Frame>) -> Option<(Vec<u16>, u8)> {
+        frame.map(|frame| {
+            let version = match frame.ip_packet {
+                IpPacket::V4(packet) => packet.get_version(),
+                IpPacket::V6(packet) => packet.get_version(),
+            };
+            (frame.vlan_ids, version)
+        })
+    }
+
+    #[rstest]
+    #[case::untagged(&[0x0800], Some(vec![]))]
+    #[case::dot1q(&[0x8100, 100, 0x0800], Some(vec![100]))]
+    #[case::priority_is_not_part_of_the_id(&[0x8100, 0xa064, 0x0800], Some(vec![100]))]
+    #[case::qinq(&[0x88a8, 100, 0x8100, 200, 0x0800], Some(vec![100, 200]))]
+    #[case::legacy_qinq(&[0x9100, 100, 0x8100, 200, 0x0800], Some(vec![100, 200]))]
+    #[case::not_ip(&[0x8100, 100, 0x0806], None)]
+    fn decodes_ethernet_frames(
+        #[case] ethertypes_and_tags: &[u16],
+        #[case] expected_vlan_ids: Option<Vec<u16>>,
+    ) {
+        let frame = ethernet(ethertypes_and_tags, &IPV4);
+        let expected = expected_vlan_ids.map(|vlan_ids| (vlan_ids, 4));
+        assert_eq!(
+            decoded(Frame::decode(&frame, LinkLayer::Ethernet)),
+            expected
+        );
+    }
+
+    #[test]
+    fn decodes_raw_ip_packets() {
+        assert_eq!(
+            decoded(Frame::decode(&IPV4, LinkLayer::RawIp)),
+            Some((vec![], 4))
+        );
+        assert_eq!(decoded(Frame::decode(&[0; 40], LinkLayer::RawIp)), None);
+    }
+
+    #[rstest]
+    #[case::sll(LinkLayer::LinuxSll, &[0, 4, 0, 1, 0, 6, 2, 0, 0, 0, 0, 1, 0, 0, 0x81, 0])]
+    #[case::sll2(
+        LinkLayer::LinuxSll2,
+        &[0x81, 0, 0, 0, 0, 0, 0, 2, 0, 1, 4, 6, 2, 0, 0, 0, 0, 1, 0, 0]
+    )]
+    fn decodes_linux_cooked_frames(#[case] link_layer: LinkLayer, #[case] header: &[u8]) {
+        let frame = [header, &[0, 100, 0x08, 0x00], &IPV4].concat();
+        assert_eq!(
+            decoded(Frame::decode(&frame, link_layer)),
+            Some((vec![100], 4))
+        );
+        assert_eq!(decoded(Frame::decode(&frame[..10], link_layer)), None);
+    }
+
+    #[test]
+    fn guesses_the_link_layer() {
+        assert_eq!(decoded(Frame::guess(&IPV4, 0)), Some((vec![], 4)));
+        let frame = ethernet(&[0x8100, 100, 0x0800], &IPV4);
+        assert_eq!(decoded(Frame::guess(&frame, 14)), Some((vec![100], 4)));
+        // a destination MAC that looks like an IP version confuses the guess, which is why
+        // interfaces and capture files whose link type is known are never guessed
+        assert_eq!(decoded(Frame::guess(&frame, 0)), Some((vec![], 4)));
+    }
+
+    /// The packet tunnels carry, from 10.0.0.9.
+    fn inner() -> Vec<u8> {
+        ipv4(9, 6, &[0; 20])
+    }
+
+    /// The same packet in an Ethernet frame.
+    fn bridged() -> Vec<u8> {
+        ethernet(&[0x0800], &inner())
+    }
+
+    /// The source of the innermost packet, as in 10.0.0.`source`.
+    fn decapsulated_source(frame: Frame) -> u8 {
+        match frame.decapsulate().ip_packet.get_source() {
+            IpAddr::V4(ip) => ip.octets()[3],
+            IpAddr::V6(ip) => panic!("unexpected {ip}"),
+        }
+    }
+
+    #[rstest]
+    #[case::ip(&[0, 0, 0x08, 0x00], inner(), 9)]
+    #[case::key_and_sequence(&[0x30, 0, 0x08, 0x00, 0, 0, 0, 1, 0, 0, 0, 2], inner(), 9)]
+    #[case::bridging(&[0, 0, 0x65, 0x58], bridged(), 9)]
+    #[case::pptp(&[0x30, 0x01, 0x88, 0x0b, 0, 0, 0, 1, 0, 0, 0, 2], inner(), 1)]
+    fn decapsulates_gre(#[case] header: &[u8], #[case] payload: Vec<u8>, #[case] expected: u8) {
+        let packet = ipv4(1, 47, &[header, &payload].concat());
+        let frame = Frame::decode(&packet, LinkLayer::RawIp).unwrap();
+        assert_eq!(decapsulated_source(frame), expected);
+    }
+
+    #[rstest]
+    #[case::vxlan(4789, &[0x08, 0, 0, 0, 0, 0, 1, 0], bridged(), 9)]
+    #[case::vxlan_without_vni(4789, &[0; 8], bridged(), 1)]
+    #[case::vxlan_on_the_linux_port(8472, &[0x08, 0, 0, 0, 0, 0, 1, 0], bridged(), 9)]
+    #[case::geneve(6081, &[0x01, 0, 0x65, 0x58, 0, 0, 1, 0, 1, 2, 3, 4], bridged(), 9)]
+    #[case::geneve_carrying_ip(6081, &[0, 0, 0x08, 0x00, 0, 0, 1, 0], inner(), 9)]
+    #[case::other_port(4790, &[0x08, 0, 0, 0, 0, 0, 1, 0], bridged(), 1)]
+    fn decapsulates_vxlan_and_geneve(
+        #[case] port: u16,
+        #[case] header: &[u8],
+        #[case] payload: Vec<u8>,
+        #[case] expected: u8,
+    ) {
+        let datagram = udp(port, &[header, &payload].concat());
+        let packet = ethernet(&[0x8100, 100, 0x0800], &ipv4(1, 17, &datagram));
+        let frame = Frame::decode(&packet, LinkLayer::Ethernet).unwrap();
+        // the inner frame stays on the VLAN of the tunnel
+        assert_eq!(frame.decapsulate().vlan_ids, [100]);
+        let frame = Frame::decode(&packet, LinkLayer::Ethernet).unwrap();
+        assert_eq!(decapsulated_source(frame), expected);
+    }
+
+    #[test]
+    fn decapsulates_ip_in_ip_but_not_fragments() {
+        let nested = ipv4(1, 4, &ipv4(2, 4, &inner()));
+        let frame = Frame::decode(&nested, LinkLayer::RawIp).unwrap();
+        assert_eq!(decapsulated_source(frame), 9);
+
+        let mut fragment = ipv4(1, 4, &inner());
+        // more fragments follow
+        fragment[6] = 0x20;
+        let frame = Frame::decode(&fragment, LinkLayer::RawIp).unwrap();
+        assert_eq!(decapsulated_source(frame), 1);
+
+        let not_a_tunnel = ipv4(1, 17, &udp(53, &[0; 12]));
+        let frame = Frame::decode(&not_a_tunnel, LinkLayer::RawIp).unwrap();
+        assert_eq!(decapsulated_source(frame), 1);
+    }
+}
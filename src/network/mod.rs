@@ -1,8 +1,20 @@
+#[cfg(any(target_os = "android", target_os = "linux"))]
+mod bpf;
 mod connection;
 pub mod dns;
+mod filter;
+mod ip;
+mod link;
+mod server_name;
 mod sniffer;
 mod utilization;
 
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub use bpf::*;
 pub use connection::*;
+pub use filter::*;
+pub use ip::*;
+pub use link::*;
+pub use server_name::*;
 pub use sniffer::*;
 pub use utilization::*;
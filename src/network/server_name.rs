@@ -0,0 +1,210 @@
+//! The name of the server a client asks for, read from the first bytes it sends.
+//!
+//! Only TLS over TCP and plaintext HTTP are read. QUIC is deliberately left out: its Initial
+//! packets are protected with keys derived from the connection ID (RFC 9001 section 5.2), so
+//! reading their ClientHello would mean decrypting them, and it may span several packets.
+
+const TLS_HANDSHAKE: u8 = 0x16;
+const TLS_CLIENT_HELLO: u8 = 0x01;
+const TLS_SERVER_NAME_EXTENSION: u16 = 0x0000;
+const TLS_HOST_NAME: u8 = 0x00;
+
+const HTTP_METHODS: [&[u8]; 9] = [
+    b"GET ",
+    b"POST ",
+    b"PUT ",
+    b"HEAD ",
+    b"DELETE ",
+    b"OPTIONS ",
+    b"PATCH ",
+    b"CONNECT ",
+    b"TRACE ",
+];
+
+/// The server name of a TLS ClientHello (SNI), or the `Host` of an HTTP request.
+pub fn server_name(payload: &[u8]) -> Option<String> {
+    tls_server_name(payload).or_else(|| http_host(payload))
+}
+
+/// Reads bytes off the front of a payload, failing when it is too short.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.take(2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// A field prefixed with its length, which takes `len_size` bytes.
+    fn prefixed(&mut self, len_size: usize) -> Option<Reader<'a>> {
+        let len = self
+            .take(len_size)?
+            .iter()
+            .fold(0, |len, &byte| len << 8 | byte as usize);
+        Some(Reader(self.take(len)?))
+    }
+}
+
+fn tls_server_name(payload: &[u8]) -> Option<String> {
+    let mut record = Reader(payload);
+    if record.u8()? != TLS_HANDSHAKE {
+        return None;
+    }
+    // the record version and length; a large ClientHello spans several segments, so the length
+    // is not checked against what was captured, and the fields before the cut are still read
+    record.take(4)?;
+    if record.u8()? != TLS_CLIENT_HELLO {
+        return None;
+    }
+    // the handshake length, client version and random
+    record.take(3 + 2 + 32)?;
+    // the session id, cipher suites and compression methods
+    record.prefixed(1)?;
+    record.prefixed(2)?;
+    record.prefixed(1)?;
+
+    let extensions_len = record.u16()? as usize;
+    let mut extensions = Reader(&record.0[..extensions_len.min(record.0.len())]);
+    while let Some(extension_type) = extensions.u16() {
+        let mut extension = extensions.prefixed(2)?;
+        if extension_type != TLS_SERVER_NAME_EXTENSION {
+            continue;
+        }
+        let mut names = extension.prefixed(2)?;
+        while let Some(name_type) = names.u8() {
+            let name = names.prefixed(2)?;
+            if name_type == TLS_HOST_NAME {
+                return valid_name(name.0);
+            }
+        }
+    }
+    None
+}
+
+fn http_host(payload: &[u8]) -> Option<String> {
+    if !HTTP_METHODS
+        .iter()
+        .any(|method| payload.starts_with(method))
+    {
+        return None;
+    }
+    let headers = payload
+        .split(|&byte| byte == b'\n')
+        .skip(1)
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .take_while(|line| !line.is_empty());
+    for header in headers {
+        let Some((name, value)) = header.split_at_checked(5) else {
+            continue;
+        };
+        if name.eq_ignore_ascii_case(b"host:") {
+            let host = std::str::from_utf8(value).ok()?.trim();
+            // the port is already part of the connection
+            let host = match host.strip_prefix('[') {
+                Some(ipv6) => ipv6.split(']').next()?,
+                None => host.split(':').next()?,
+            };
+            return valid_name(host.as_bytes());
+        }
+    }
+    None
+}
+
+/// Guards the terminal against whatever arbitrary bytes a packet holds.
+fn valid_name(name: &[u8]) -> Option<String> {
+    let is_valid = !name.is_empty()
+        && name.len() <= 255
+        && name
+            .iter()
+            .all(|&byte| byte.is_ascii_alphanumeric() || b"-._:".contains(&byte));
+    is_valid.then(|| String::from_utf8_lossy(name).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    /// Prefixes a field with its length, in `len_size` bytes.
+    fn prefixed(len_size: usize, field: &[u8]) -> Vec<u8> {
+        let mut bytes = field.len().to_be_bytes()[8 - len_size..].to_vec();
+        bytes.extend(field);
+        bytes
+    }
+
+    fn extension(extension_type: u16, data: &[u8]) -> Vec<u8> {
+        [extension_type.to_be_bytes().to_vec(), prefixed(2, data)].concat()
+    }
+
+    fn client_hello(extensions: &[Vec<u8>]) -> Vec<u8> {
+        let hello = [
+            vec![0x03, 0x03],
+            vec![7; 32],
+            prefixed(1, &[1; 32]),
+            prefixed(2, &[0x13, 0x01, 0x13, 0x02]),
+            prefixed(1, &[0]),
+            prefixed(2, &extensions.concat()),
+        ]
+        .concat();
+        let handshake = [vec![TLS_CLIENT_HELLO], prefixed(3, &hello)].concat();
+        [vec![TLS_HANDSHAKE, 0x03, 0x01], prefixed(2, &handshake)].concat()
+    }
+
+    fn sni(name: &str) -> Vec<u8> {
+        let entry = [vec![TLS_HOST_NAME], prefixed(2, name.as_bytes())].concat();
+        extension(TLS_SERVER_NAME_EXTENSION, &prefixed(2, &entry))
+    }
+
+    #[test]
+    fn reads_the_sni_of_a_client_hello() {
+        let supported_versions = extension(0x002b, &[0x02, 0x03, 0x04]);
+        let hello = client_hello(&[supported_versions, sni("api.example.com")]);
+        assert_eq!(server_name(&hello).as_deref(), Some("api.example.com"));
+    }
+
+    #[test]
+    fn reads_the_sni_of_a_truncated_client_hello() {
+        let key_share = extension(0x0033, &[0; 1200]);
+        let hello = client_hello(&[sni("api.example.com"), key_share]);
+        assert_eq!(
+            server_name(&hello[..200]).as_deref(),
+            Some("api.example.com")
+        );
+        // cut before the name
+        assert_eq!(server_name(&hello[..60]), None);
+    }
+
+    #[test]
+    fn ignores_client_hellos_without_sni() {
+        assert_eq!(server_name(&client_hello(&[])), None);
+        assert_eq!(server_name(&client_hello(&[sni("bad\x1b[name")])), None);
+    }
+
+    #[rstest]
+    #[case(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n", Some("example.com"))]
+    #[case(
+        b"POST /a HTTP/1.1\r\nAccept: */*\r\nhost:example.com:8080\r\n\r\n",
+        Some("example.com")
+    )]
+    #[case(b"GET / HTTP/1.1\nHOST: [fd00::1]:80\n\n", Some("fd00::1"))]
+    #[case(b"GET / HTTP/1.0\r\n\r\nHost: body.example.com\r\n", None)]
+    #[case(b"HTTP/1.1 200 OK\r\nHost: example.com\r\n\r\n", None)]
+    #[case(b"GETTING / HTTP/1.1\r\nHost: example.com\r\n\r\n", None)]
+    fn reads_the_host_of_an_http_request(#[case] payload: &[u8], #[case] expected: Option<&str>) {
+        assert_eq!(server_name(payload).as_deref(), expected);
+    }
+}
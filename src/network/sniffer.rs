@@ -1,4 +1,5 @@
 use std::{
+    fmt::Write,
     io::{self, Result},
     net::{IpAddr, SocketAddr},
     thread::park_timeout,
@@ -9,19 +10,19 @@ use pnet::{
     datalink::{DataLinkReceiver, NetworkInterface},
     ipnetwork::IpNetwork,
     packet::{
-        ethernet::{EtherTypes, EthernetPacket},
-        ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
-        ipv4::Ipv4Packet,
-        ipv6::Ipv6Packet,
-        tcp::TcpPacket,
-        udp::UdpPacket,
-        Packet,
+        ip::IpNextHeaderProtocols, ipv4::Ipv4Packet, ipv6::Ipv6Packet, tcp::TcpPacket,
+        udp::UdpPacket, Packet,
     },
 };
 
 use crate::{
-    network::{Connection, Protocol},
-    os::shared::get_datalink_channel,
+    cli::{CountMode, TunnelFlows},
+    network::{
+        dns::{DnsMessage, SnoopedHostnames},
+        server_name, CaptureFilter, Connection, DatagramId, Fragments, Frame, IpPacket, IpPayload,
+        LinkLayer, Protocol,
+    },
+    os::{shared::get_datalink_channel_in, NetworkNamespace},
 };
 
 const PACKET_WAIT_TIMEOUT: Duration = Duration::from_millis(10);
@@ -33,6 +34,13 @@ pub struct Segment {
     pub connection: Connection,
     pub direction: Direction,
     pub data_length: u128,
+    /// How many packets the segment stands for: one, or more for the first fragment of a datagram
+    /// whose later fragments arrived before it.
+    pub packets: u128,
+    /// The server name a client sent in this segment, see [`server_name`].
+    pub server_name: Option<String>,
+    /// The DNS query or response this segment carries, with `--show-dns`.
+    pub dns_message: Option<DnsMessage>,
 }
 
 #[derive(PartialEq, Hash, Eq, Debug, Clone, PartialOrd)]
@@ -54,61 +62,158 @@ impl Direction {
     }
 }
 
-trait NextLevelProtocol {
-    fn get_next_level_protocol(&self) -> IpNextHeaderProtocol;
+/// How many bytes of a packet count, see [`CountMode`].
+#[derive(Copy, Clone, Debug)]
+struct CountedLength {
+    count_mode: CountMode,
+    /// The length of the whole frame the packet was captured in.
+    frame_length: usize,
 }
 
-impl NextLevelProtocol for Ipv6Packet<'_> {
-    fn get_next_level_protocol(&self) -> IpNextHeaderProtocol {
-        self.get_next_header()
+impl CountedLength {
+    fn of(self, ip_length: usize, payload: &IpPayload) -> u128 {
+        let length = match self.count_mode {
+            CountMode::Frame => self.frame_length,
+            CountMode::Ip => ip_length,
+            CountMode::Transport => payload.bytes.len(),
+            CountMode::Payload if !payload.has_transport_header() => payload.bytes.len(),
+            CountMode::Payload => {
+                let header_length = match payload.protocol {
+                    IpNextHeaderProtocols::Tcp => TcpPacket::new(payload.bytes)
+                        .map_or(0, |message| usize::from(message.get_data_offset()) * 4),
+                    IpNextHeaderProtocols::Udp => 8,
+                    // the payload of other protocols is all there is
+                    _ => 0,
+                };
+                payload.bytes.len().saturating_sub(header_length)
+            }
+        };
+        length as u128
     }
 }
 
-macro_rules! extract_transport_protocol {
-    (  $ip_packet: ident ) => {{
-        match $ip_packet.get_next_level_protocol() {
-            IpNextHeaderProtocols::Tcp => {
-                let message = TcpPacket::new($ip_packet.payload())?;
-                (
-                    Protocol::Tcp,
-                    message.get_source(),
-                    message.get_destination(),
-                    $ip_packet.payload().len() as u128,
-                )
-            }
-            IpNextHeaderProtocols::Udp => {
-                let datagram = UdpPacket::new($ip_packet.payload())?;
+/// The protocol and ports of a packet, and the length and number of packets it counts as.
+///
+/// Fragments after the first lack the transport header, so they are given the ports of the first,
+/// and counted along with it if they arrive before it.
+fn extract_transport_protocol(
+    payload: &IpPayload,
+    source: IpAddr,
+    destination: IpAddr,
+    data_length: u128,
+    fragments: &mut Fragments,
+) -> Option<(Protocol, u16, u16, u128, u128)> {
+    let protocol = match payload.protocol {
+        IpNextHeaderProtocols::Tcp => Protocol::Tcp,
+        IpNextHeaderProtocols::Udp => Protocol::Udp,
+        IpNextHeaderProtocols::Icmp => Protocol::Icmp,
+        IpNextHeaderProtocols::Icmpv6 => Protocol::Icmpv6,
+        other => Protocol::Other(other.0),
+    };
+    let ports = || match protocol {
+        Protocol::Tcp => {
+            let message = TcpPacket::new(payload.bytes)?;
+            Some((message.get_source(), message.get_destination()))
+        }
+        Protocol::Udp => {
+            let datagram = UdpPacket::new(payload.bytes)?;
+            Some((datagram.get_source(), datagram.get_destination()))
+        }
+        // without ports, connections are only told apart by their addresses
+        _ => Some((0, 0)),
+    };
+
+    let (protocol, source_port, destination_port, data_length, packets) = match payload.fragment {
+        // only the first fragment tells the transport protocol, as IPv6 ones may name a header
+        Some(fragment) => {
+            let datagram = DatagramId {
+                source,
+                destination,
+                protocol: fragment.protocol,
+                id: fragment.id,
+            };
+            if fragment.offset == 0 {
+                let (source_port, destination_port) = ports()?;
+                let (early_length, early_packets) =
+                    fragments.first_arrived(datagram, (protocol, source_port, destination_port));
                 (
-                    Protocol::Udp,
-                    datagram.get_source(),
-                    datagram.get_destination(),
-                    $ip_packet.payload().len() as u128,
+                    protocol,
+                    source_port,
+                    destination_port,
+                    data_length + early_length,
+                    1 + early_packets,
                 )
+            } else {
+                let (protocol, source_port, destination_port) =
+                    fragments.later_arrived(datagram, data_length, fragment.last)?;
+                (protocol, source_port, destination_port, data_length, 1)
             }
-            _ => return None,
         }
-    }};
+        None => {
+            let (source_port, destination_port) = ports()?;
+            (protocol, source_port, destination_port, data_length, 1)
+        }
+    };
+    Some((
+        protocol,
+        source_port,
+        destination_port,
+        data_length,
+        packets,
+    ))
 }
 
 pub struct Sniffer {
     network_interface: NetworkInterface,
     network_frames: Box<dyn DataLinkReceiver>,
+    /// What precedes the IP header in the frames, or `None` if it is guessed for each frame.
+    link_layer: Option<LinkLayer>,
     show_dns: bool,
+    tunnel_flows: TunnelFlows,
+    count_mode: CountMode,
+    filter: Option<CaptureFilter>,
+    netns: Option<NetworkNamespace>,
+    hostnames: SnoopedHostnames,
+    fragments: Fragments,
 }
 
 impl Sniffer {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         network_interface: NetworkInterface,
         network_frames: Box<dyn DataLinkReceiver>,
+        link_layer: Option<LinkLayer>,
         show_dns: bool,
+        tunnel_flows: TunnelFlows,
+        count_mode: CountMode,
+        filter: Option<CaptureFilter>,
+        netns: Option<NetworkNamespace>,
+        hostnames: SnoopedHostnames,
     ) -> Self {
         Sniffer {
             network_interface,
             network_frames,
+            link_layer,
             show_dns,
+            tunnel_flows,
+            count_mode,
+            filter,
+            netns,
+            hostnames,
+            fragments: Fragments::default(),
         }
     }
     pub fn next(&mut self) -> Option<Segment> {
+        let mut segment = self.next_unfiltered()?;
+        if let Some(netns) = &self.netns {
+            segment.connection.local_socket.netns = netns.id;
+        }
+        match &self.filter {
+            Some(filter) if !filter.matches(&segment) => None,
+            _ => Some(segment),
+        }
+    }
+    fn next_unfiltered(&mut self) -> Option<Segment> {
         let bytes = match self.network_frames.next() {
             Ok(bytes) => bytes,
             Err(err) => match err.kind() {
@@ -123,53 +228,140 @@ impl Sniffer {
                 }
             },
         };
-        // See https://github.com/libpnet/libpnet/blob/master/examples/packetdump.rs
-        // VPN interfaces (such as utun0, utun1, etc) have POINT_TO_POINT bit set to 1
-        let payload_offset = if (self.network_interface.is_loopback()
-            || self.network_interface.is_point_to_point())
-            && cfg!(target_os = "macos")
-        {
-            // The pnet code for BPF loopback adds a zero'd out Ethernet header
-            14
-        } else {
-            0
+        let length = CountedLength {
+            count_mode: self.count_mode,
+            frame_length: bytes.len(),
+        };
+        let frame = match self.link_layer {
+            Some(link_layer) => Frame::decode(bytes, link_layer),
+            None => {
+                // See https://github.com/libpnet/libpnet/blob/master/examples/packetdump.rs
+                // VPN interfaces (such as utun0, utun1, etc) have POINT_TO_POINT bit set to 1
+                let ip_offset = if (self.network_interface.is_loopback()
+                    || self.network_interface.is_point_to_point())
+                    && cfg!(target_os = "macos")
+                {
+                    // The pnet code for BPF loopback adds a zero'd out Ethernet header
+                    14
+                } else {
+                    0
+                };
+                Frame::guess(bytes, ip_offset)
+            }
+        }?;
+        // a tunnelled packet goes the way the tunnel does, as neither of its addresses is local
+        let direction = Direction::new(&self.network_interface.ips, frame.ip_packet.get_source());
+        let frame = match self.tunnel_flows {
+            TunnelFlows::Outer => frame,
+            TunnelFlows::Inner => frame.decapsulate(),
         };
-        let ip_packet = Ipv4Packet::new(&bytes[payload_offset..])?;
-        let version = ip_packet.get_version();
 
-        match version {
-            4 => Self::handle_v4(ip_packet, &self.network_interface, self.show_dns),
-            6 => Self::handle_v6(
-                Ipv6Packet::new(&bytes[payload_offset..])?,
+        let vlan_ids = frame.vlan_ids;
+        let mut segment = match frame.ip_packet {
+            IpPacket::V4(ip_packet) => Self::handle_v4(
+                ip_packet,
+                direction,
                 &self.network_interface,
+                self.show_dns,
+                &self.hostnames,
+                &mut self.fragments,
+                length,
             ),
-            _ => {
-                let pkg = EthernetPacket::new(bytes)?;
-                match pkg.get_ethertype() {
-                    EtherTypes::Ipv4 => Self::handle_v4(
-                        Ipv4Packet::new(pkg.payload())?,
-                        &self.network_interface,
-                        self.show_dns,
-                    ),
-                    EtherTypes::Ipv6 => {
-                        Self::handle_v6(Ipv6Packet::new(pkg.payload())?, &self.network_interface)
-                    }
-                    _ => None,
-                }
-            }
+            IpPacket::V6(ip_packet) => Self::handle_v6(
+                ip_packet,
+                direction,
+                &self.network_interface,
+                self.show_dns,
+                &self.hostnames,
+                &mut self.fragments,
+                length,
+            ),
+        }?;
+        if !vlan_ids.is_empty() || self.netns.is_some() {
+            segment.interface_name = self.qualified_interface_name(&vlan_ids);
+        }
+        Some(segment)
+    }
+    /// Names the interface as `eth0.100` for traffic of VLAN 100, and as `eth0@ns` when it is in
+    /// another network namespace.
+    fn qualified_interface_name(&self, vlan_ids: &[u16]) -> String {
+        let mut name = self.network_interface.name.clone();
+        for vlan_id in vlan_ids {
+            write!(name, ".{vlan_id}").unwrap();
+        }
+        if let Some(netns) = &self.netns {
+            write!(name, "@{}", netns.label).unwrap();
         }
+        name
     }
     pub fn reset_channel(&mut self) -> Result<()> {
-        self.network_frames = get_datalink_channel(&self.network_interface)
-            .map_err(|_| io::Error::other("Interface not available"))?;
+        self.network_frames = get_datalink_channel_in(
+            &self.network_interface,
+            self.filter.as_ref(),
+            self.tunnel_flows,
+            self.netns.as_ref(),
+        )
+        .map_err(|_| io::Error::other("Interface not available"))?;
         Ok(())
     }
-    fn handle_v6(ip_packet: Ipv6Packet, network_interface: &NetworkInterface) -> Option<Segment> {
-        let (protocol, source_port, destination_port, data_length) =
-            extract_transport_protocol!(ip_packet);
+    /// Learns the hostnames in the DNS responses to this host's queries, and returns the server
+    /// name a client sent and, with `--show-dns`, the DNS message, or `None` if the transport
+    /// header is malformed.
+    fn snoop(
+        payload: &IpPayload,
+        direction: &Direction,
+        connection: &Connection,
+        show_dns: bool,
+        hostnames: &SnoopedHostnames,
+    ) -> Option<(Option<String>, Option<DnsMessage>)> {
+        // only the first fragment of a datagram starts with the transport header
+        if !payload.has_transport_header() {
+            return Some((None, None));
+        }
+        let protocol = connection.local_socket.protocol;
+        if protocol == Protocol::Udp && connection.remote_socket.port == 53 {
+            let datagram = UdpPacket::new(payload.bytes)?;
+            match direction {
+                Direction::Upload => hostnames.query(connection, datagram.payload()),
+                Direction::Download => hostnames.learn(connection, datagram.payload()),
+            }
+        }
 
+        let server_name = match (protocol, direction) {
+            (Protocol::Tcp, Direction::Upload) => {
+                server_name(TcpPacket::new(payload.bytes)?.payload())
+            }
+            _ => None,
+        };
+        let dns_message = match protocol {
+            Protocol::Udp if show_dns && connection.remote_socket.port == 53 => {
+                DnsMessage::parse(UdpPacket::new(payload.bytes)?.payload())
+            }
+            _ => None,
+        };
+        Some((server_name, dns_message))
+    }
+    fn handle_v6(
+        ip_packet: Ipv6Packet,
+        direction: Direction,
+        network_interface: &NetworkInterface,
+        show_dns: bool,
+        hostnames: &SnoopedHostnames,
+        fragments: &mut Fragments,
+        length: CountedLength,
+    ) -> Option<Segment> {
+        let payload = IpPayload::ipv6(&ip_packet, ip_packet.payload())?;
+        // the fixed header, then extension headers and the transport-layer payload
+        let ip_length = 40 + ip_packet.payload().len();
+        let (protocol, source_port, destination_port, data_length, packets) =
+            extract_transport_protocol(
+                &payload,
+                ip_packet.get_source().into(),
+                ip_packet.get_destination().into(),
+                length.of(ip_length, &payload),
+                fragments,
+            )?;
         let interface_name = network_interface.name.clone();
-        let direction = Direction::new(&network_interface.ips, ip_packet.get_source().into());
         let from = SocketAddr::new(ip_packet.get_source().into(), source_port);
         let to = SocketAddr::new(ip_packet.get_destination().into(), destination_port);
 
@@ -177,23 +369,38 @@ impl Sniffer {
             Direction::Download => Connection::new(from, to.ip(), destination_port, protocol),
             Direction::Upload => Connection::new(to, from.ip(), source_port, protocol),
         };
+        let (server_name, dns_message) =
+            Self::snoop(&payload, &direction, &connection, show_dns, hostnames)?;
         Some(Segment {
             interface_name,
             connection,
             data_length,
+            packets,
             direction,
+            server_name,
+            dns_message,
         })
     }
     fn handle_v4(
         ip_packet: Ipv4Packet,
+        direction: Direction,
         network_interface: &NetworkInterface,
         show_dns: bool,
+        hostnames: &SnoopedHostnames,
+        fragments: &mut Fragments,
+        length: CountedLength,
     ) -> Option<Segment> {
-        let (protocol, source_port, destination_port, data_length) =
-            extract_transport_protocol!(ip_packet);
-
+        let payload = IpPayload::ipv4(&ip_packet, ip_packet.payload());
+        let ip_length = usize::from(ip_packet.get_header_length()) * 4 + ip_packet.payload().len();
+        let (protocol, source_port, destination_port, data_length, packets) =
+            extract_transport_protocol(
+                &payload,
+                ip_packet.get_source().into(),
+                ip_packet.get_destination().into(),
+                length.of(ip_length, &payload),
+                fragments,
+            )?;
         let interface_name = network_interface.name.clone();
-        let direction = Direction::new(&network_interface.ips, ip_packet.get_source().into());
         let from = SocketAddr::new(ip_packet.get_source().into(), source_port);
         let to = SocketAddr::new(ip_packet.get_destination().into(), destination_port);
 
@@ -201,6 +408,8 @@ impl Sniffer {
             Direction::Download => Connection::new(from, to.ip(), destination_port, protocol),
             Direction::Upload => Connection::new(to, from.ip(), source_port, protocol),
         };
+        let (server_name, dns_message) =
+            Self::snoop(&payload, &direction, &connection, show_dns, hostnames)?;
 
         if !show_dns && connection.remote_socket.port == 53 {
             return None;
@@ -209,7 +418,10 @@ impl Sniffer {
             interface_name,
             connection,
             data_length,
+            packets,
             direction,
+            server_name,
+            dns_message,
         })
     }
 }
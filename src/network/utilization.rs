@@ -1,12 +1,21 @@
 use std::collections::HashMap;
 
-use crate::network::{Connection, Direction, Segment};
+use crate::network::{
+    dns::{DnsExchange, DnsQuestion},
+    Connection, Direction, Segment,
+};
 
 #[derive(Clone)]
 pub struct ConnectionInfo {
     pub interface_name: String,
     pub total_bytes_downloaded: u128,
     pub total_bytes_uploaded: u128,
+    pub total_packets_downloaded: u128,
+    pub total_packets_uploaded: u128,
+    /// The server name the client asked for, if it was seen in this snapshot.
+    pub server_name: Option<String>,
+    /// The DNS queries and responses seen in this snapshot, with `--show-dns`, per question.
+    pub dns_questions: HashMap<DnsQuestion, DnsExchange>,
 }
 
 #[derive(Clone)]
@@ -32,14 +41,72 @@ impl Utilization {
                 interface_name: seg.interface_name,
                 total_bytes_downloaded: 0,
                 total_bytes_uploaded: 0,
+                total_packets_downloaded: 0,
+                total_packets_uploaded: 0,
+                server_name: None,
+                dns_questions: HashMap::new(),
             });
+        if seg.server_name.is_some() {
+            total_bandwidth.server_name = seg.server_name;
+        }
+        if let Some(dns_message) = seg.dns_message {
+            total_bandwidth
+                .dns_questions
+                .entry(dns_message.question)
+                .or_default()
+                .add(dns_message.response_code);
+        }
         match seg.direction {
             Direction::Download => {
                 total_bandwidth.total_bytes_downloaded += seg.data_length;
+                total_bandwidth.total_packets_downloaded += seg.packets;
             }
             Direction::Upload => {
                 total_bandwidth.total_bytes_uploaded += seg.data_length;
+                total_bandwidth.total_packets_uploaded += seg.packets;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{dns::DnsMessage, Protocol};
+
+    #[test]
+    fn counts_dns_messages_per_question() {
+        let connection = Connection::new(
+            "8.8.8.8:53".parse().unwrap(),
+            "10.0.0.2".parse().unwrap(),
+            54321,
+            Protocol::Udp,
+        );
+        let question = DnsQuestion {
+            name: "example.com".into(),
+            record_type: "A".into(),
+        };
+        let mut utilization = Utilization::new();
+        for response_code in [None, None, Some("SERVFAIL"), None, Some("NOERROR")] {
+            utilization.ingest(Segment {
+                interface_name: "eth0".into(),
+                connection,
+                direction: Direction::Upload,
+                data_length: 40,
+                packets: 1,
+                server_name: None,
+                dns_message: Some(DnsMessage {
+                    question: question.clone(),
+                    response_code: response_code.map(Into::into),
+                }),
+            });
+        }
+
+        let expected = DnsExchange {
+            queries: 3,
+            response_code: Some("NOERROR".into()),
+        };
+        let dns_questions = &utilization.connections[&connection].dns_questions;
+        assert_eq!(dns_questions, &HashMap::from([(question, expected)]));
+    }
+}
@@ -1,51 +1,890 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io, mem,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::fs::MetadataExt,
+    },
+    panic,
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
 
-use procfs::process::FDTarget;
+use itertools::Itertools;
+use log::{debug, info};
+use once_cell::sync::Lazy;
+use pnet::datalink::{self, DataLinkReceiver, NetworkInterface};
+use procfs::{
+    net::{TcpNetEntry, UdpNetEntry},
+    process::{FDTarget, Process},
+    ProcResult, ProcessCGroups,
+};
 
 use crate::{
-    network::{LocalSocket, Protocol},
-    os::ProcessInfo,
+    cli::TunnelFlows,
+    network::{compile_bpf, CaptureFilter, LinkLayer, LocalSocket, Protocol},
+    os::{
+        sock_diag::{dump_sockets, SocketEntry},
+        Container, NetworkNamespace, ProcessInfo,
+    },
     OpenSockets,
 };
 
+/// Where named network namespaces are mounted by `ip netns`.
+const NAMED_NETNS_DIR: &str = "/var/run/netns";
+/// How long until a container whose name was not found is looked up again.
+const UNNAMED_CONTAINER_TTL: Duration = Duration::from_secs(10);
+/// How many container names are cached.
+const MAX_CONTAINER_NAMES: usize = 1024;
+/// The name libpcap gives the pseudo-interface that captures on all interfaces.
+pub(crate) const ANY_INTERFACE: &str = "any";
+/// The longest packet read on [`ANY_INTERFACE`], like the buffer pnet reads frames into.
+const COOKED_BUFFER_SIZE: usize = 65536;
+
 pub(crate) fn get_open_sockets() -> OpenSockets {
-    let mut open_sockets = HashMap::new();
-    let mut inode_to_proc = HashMap::new();
+    open_sockets(false)
+}
 
-    if let Ok(all_procs) = procfs::process::all_processes() {
+/// Like [`get_open_sockets`], also reading the socket tables of all other network namespaces.
+pub(crate) fn get_open_sockets_all_netns() -> OpenSockets {
+    open_sockets(true)
+}
+
+fn open_sockets(all_netns: bool) -> OpenSockets {
+    static PROCESSES: Lazy<Mutex<ProcessCache>> = Lazy::new(Default::default);
+    let mut processes = PROCESSES.lock().unwrap();
+    processes.update(all_netns);
+
+    let own_sockets = dump_sockets().unwrap_or_else(|err| {
+        debug!("Cannot list sockets over netlink, reading /proc/net instead: {err}");
+        table_sockets(
+            [procfs::net::tcp(), procfs::net::tcp6()],
+            [procfs::net::udp(), procfs::net::udp6()],
+        )
+    });
+    let mut sockets = own_sockets
+        .into_iter()
+        .map(|entry| (entry, 0))
+        .collect_vec();
+
+    if all_netns {
+        // the socket tables of a namespace can be read through any process in it
+        let own_netns = netns_id("/proc/self/ns/net");
+        let netns_to_pid: HashMap<u64, i32> = processes
+            .processes
+            .iter()
+            .filter(|(_, process)| !process.socket_inodes.is_empty())
+            .filter_map(|(&pid, process)| Some((process.netns?, pid)))
+            .filter(|&(netns, _)| Some(netns) != own_netns)
+            .collect();
+        for (netns, pid) in netns_to_pid {
+            let Ok(process) = Process::new(pid) else {
+                continue;
+            };
+            let entries = table_sockets(
+                [process.tcp(), process.tcp6()],
+                [process.udp(), process.udp6()],
+            );
+            sockets.extend(entries.into_iter().map(|entry| (entry, netns)));
+        }
+    }
+
+    let inodes = sockets.iter().map(|(entry, _)| entry.inode).collect();
+    processes.find_owners(&inodes, all_netns);
+
+    let sockets_to_procs = sockets
+        .into_iter()
+        .filter_map(|(entry, netns)| {
+            let socket = LocalSocket {
+                ip: entry.local_address.ip(),
+                port: entry.local_address.port(),
+                protocol: entry.protocol,
+                netns,
+            };
+            Some((socket, processes.owner(entry.inode)?.clone()))
+        })
+        .collect();
+
+    OpenSockets { sockets_to_procs }
+}
+
+/// Lists the sockets of a namespace from its `/proc/net` tables.
+fn table_sockets(
+    tcp: impl IntoIterator<Item = ProcResult<Vec<TcpNetEntry>>>,
+    udp: impl IntoIterator<Item = ProcResult<Vec<UdpNetEntry>>>,
+) -> Vec<SocketEntry> {
+    let tcp = tcp.into_iter().filter_map(|res| res.ok()).flatten();
+    let udp = udp.into_iter().filter_map(|res| res.ok()).flatten();
+    tcp.map(|entry| (entry.local_address, entry.inode, Protocol::Tcp))
+        .chain(udp.map(|entry| (entry.local_address, entry.inode, Protocol::Udp)))
+        .map(|(local_address, inode, protocol)| SocketEntry {
+            local_address,
+            protocol,
+            inode,
+        })
+        .collect()
+}
+
+/// The processes holding sockets, kept between refreshes so that the open files of every
+/// process needn't be read each time.
+#[derive(Debug, Default)]
+struct ProcessCache {
+    processes: HashMap<i32, CachedProcess>,
+    inode_to_pid: HashMap<u64, i32>,
+    /// Socket inodes no process could be found for, eg. because they belong to another user.
+    /// These don't cause another rescan.
+    unowned: HashSet<u64>,
+}
+
+#[derive(Debug)]
+struct CachedProcess {
+    start_time: u64,
+    info: ProcessInfo,
+    netns: Option<u64>,
+    /// The number of open files when last scanned, which the kernel reports since Linux 6.2.
+    fd_count: Option<u64>,
+    socket_inodes: Vec<u64>,
+}
+
+impl ProcessCache {
+    /// Forgets exited processes, and scans the ones started since the last update, including
+    /// those which reuse the PID of an exited one. Processes which exec'd are renamed.
+    ///
+    /// Containers not named yet are looked up again, as their runtime may write their names late.
+    fn update(&mut self, all_netns: bool) {
+        let Ok(all_procs) = procfs::process::all_processes() else {
+            return;
+        };
+        let mut alive = HashSet::new();
         for process in all_procs.filter_map(|res| res.ok()) {
-            let Ok(fds) = process.fd() else { continue };
+            alive.insert(process.pid);
+            let Some(cached) = self.processes.get_mut(&process.pid) else {
+                self.scan(&process, all_netns);
+                continue;
+            };
             let Ok(stat) = process.stat() else { continue };
-            let proc_name = stat.comm;
-            let proc_info = ProcessInfo::new(&proc_name, stat.pid as u32);
-            for fd in fds.filter_map(|res| res.ok()) {
-                if let FDTarget::Socket(inode) = fd.target {
-                    inode_to_proc.insert(inode, proc_info.clone());
+            if stat.starttime != cached.start_time {
+                self.scan(&process, all_netns);
+            } else if stat.comm != cached.info.name {
+                cached.info.name = stat.comm;
+            }
+        }
+        let exited = self
+            .processes
+            .keys()
+            .filter(|pid| !alive.contains(pid))
+            .copied()
+            .collect_vec();
+        for pid in exited {
+            self.forget(pid);
+        }
+        for process in self.processes.values_mut() {
+            if let Some(container) = process.info.container.as_mut() {
+                if container.name.is_none() {
+                    container.name = container_name(container.runtime, &container.id);
                 }
             }
         }
     }
 
-    macro_rules! insert_proto {
-        ($source: expr, $proto: expr) => {
-            let entries = $source.into_iter().filter_map(|res| res.ok()).flatten();
-            for entry in entries {
-                if let Some(proc_info) = inode_to_proc.get(&entry.inode) {
-                    let socket = LocalSocket {
-                        ip: entry.local_address.ip(),
-                        port: entry.local_address.port(),
-                        protocol: $proto,
-                    };
-                    open_sockets.insert(socket, proc_info.clone());
+    /// Makes sure the owners of all `inodes` are known, if they can be found.
+    ///
+    /// Processes which opened or closed files since they were last scanned are rescanned
+    /// first, then the others, until no inode is missing.
+    fn find_owners(&mut self, inodes: &HashSet<u64>, all_netns: bool) {
+        self.unowned.retain(|inode| inodes.contains(inode));
+        let mut missing: HashSet<u64> = inodes
+            .iter()
+            .filter(|inode| !self.inode_to_pid.contains_key(inode) && !self.unowned.contains(inode))
+            .copied()
+            .collect();
+        if missing.is_empty() {
+            return;
+        }
+
+        let (changed, unchanged): (Vec<_>, Vec<_>) = self
+            .processes
+            .iter()
+            .map(|(&pid, process)| {
+                let fd_count = fd_count(pid);
+                (pid, fd_count.is_none() || fd_count != process.fd_count)
+            })
+            .partition(|&(_, changed)| changed);
+        for (pid, _) in changed.into_iter().chain(unchanged) {
+            if missing.is_empty() {
+                break;
+            }
+            let Ok(process) = Process::new(pid) else {
+                continue;
+            };
+            self.scan(&process, all_netns);
+            if let Some(process) = self.processes.get(&pid) {
+                for inode in &process.socket_inodes {
+                    missing.remove(inode);
                 }
             }
+        }
+        self.unowned.extend(missing);
+    }
+
+    fn owner(&self, inode: u64) -> Option<&ProcessInfo> {
+        let pid = self.inode_to_pid.get(&inode)?;
+        Some(&self.processes.get(pid)?.info)
+    }
+
+    /// Reads the open sockets of a process, replacing what was known about it.
+    fn scan(&mut self, process: &Process, all_netns: bool) {
+        let previous = self.forget(process.pid);
+        let Ok(stat) = process.stat() else { return };
+        let previous = previous.filter(|previous| previous.start_time == stat.starttime);
+
+        // counted before listing, so that files opened meanwhile show up as a change later
+        let fd_count = fd_count(process.pid);
+        let socket_inodes = match process.fd() {
+            Ok(fds) => fds
+                .filter_map(|res| res.ok())
+                .filter_map(|fd| match fd.target {
+                    FDTarget::Socket(inode) => Some(inode),
+                    _ => None,
+                })
+                .collect_vec(),
+            Err(_) => vec![],
+        };
+
+        let mut info = ProcessInfo::new(&stat.comm, stat.pid as u32);
+        match &previous {
+            Some(previous) if previous.info.cgroup.is_some() => {
+                info.cgroup.clone_from(&previous.info.cgroup);
+                info.container.clone_from(&previous.info.container);
+            }
+            // only processes with sockets are worth reading the cgroup of
+            _ if !socket_inodes.is_empty() => {
+                if let Ok(cgroups) = process.cgroups() {
+                    (info.cgroup, info.container) = cgroup_and_container(&cgroups);
+                }
+            }
+            _ => (),
+        }
+        let netns = match previous {
+            Some(previous) => previous.netns,
+            None if all_netns => netns_id(format!("/proc/{}/ns/net", process.pid)),
+            None => None,
         };
+
+        for &inode in &socket_inodes {
+            self.inode_to_pid.insert(inode, process.pid);
+            self.unowned.remove(&inode);
+        }
+        self.processes.insert(
+            process.pid,
+            CachedProcess {
+                start_time: stat.starttime,
+                info,
+                netns,
+                fd_count,
+                socket_inodes,
+            },
+        );
+    }
+
+    fn forget(&mut self, pid: i32) -> Option<CachedProcess> {
+        let process = self.processes.remove(&pid)?;
+        for inode in &process.socket_inodes {
+            if self.inode_to_pid.get(inode) == Some(&pid) {
+                self.inode_to_pid.remove(inode);
+            }
+        }
+        Some(process)
+    }
+}
+
+/// The number of files a process has open, if the kernel reports it.
+fn fd_count(pid: i32) -> Option<u64> {
+    let size = fs::metadata(format!("/proc/{pid}/fd")).ok()?.size();
+    // older kernels always report 0
+    (size > 0).then_some(size)
+}
+
+/// The inode of a namespace file, which identifies the namespace.
+fn netns_id(path: impl AsRef<Path>) -> Option<u64> {
+    fs::metadata(path).ok().map(|metadata| metadata.ino())
+}
+
+/// Lists the network namespaces other than bandwhich's own, named ones first.
+///
+/// Namespaces without a name are labelled with the container they belong to, if any.
+pub(crate) fn network_namespaces() -> Vec<NetworkNamespace> {
+    let mut seen: HashSet<u64> = netns_id("/proc/self/ns/net").into_iter().collect();
+    let mut namespaces = vec![];
+    let mut add = |path: &Path, label: &dyn Fn() -> String| {
+        let Ok(file) = File::open(path) else { return };
+        let Ok(id) = file.metadata().map(|metadata| metadata.ino()) else {
+            return;
+        };
+        if seen.insert(id) {
+            namespaces.push(NetworkNamespace {
+                id,
+                label: label(),
+                handle: Arc::new(file),
+            });
+        }
+    };
+
+    if let Ok(entries) = fs::read_dir(NAMED_NETNS_DIR) {
+        for entry in entries.filter_map(|res| res.ok()) {
+            add(&entry.path(), &|| {
+                entry.file_name().to_string_lossy().into_owned()
+            });
+        }
+    }
+    if let Ok(all_procs) = procfs::process::all_processes() {
+        for process in all_procs.filter_map(|res| res.ok()) {
+            let path = format!("/proc/{}/ns/net", process.pid);
+            add(Path::new(&path), &|| {
+                let container = process
+                    .cgroups()
+                    .ok()
+                    .and_then(|cgroups| cgroup_and_container(&cgroups).1);
+                match container {
+                    Some(container) => container.to_string(),
+                    None => format!("netns:{}", netns_id(&path).unwrap_or_default()),
+                }
+            });
+        }
     }
+    namespaces
+}
+
+/// Runs `f` on a thread which has entered the network namespace.
+///
+/// Sockets opened by `f` stay in that namespace after it returns.
+pub(crate) fn in_netns<T: Send>(
+    netns: &NetworkNamespace,
+    f: impl FnOnce() -> T + Send,
+) -> io::Result<T> {
+    thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                // setns only affects the calling thread, which is discarded afterwards
+                if unsafe { libc::setns(netns.handle.as_raw_fd(), libc::CLONE_NEWNET) } == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                debug!("Entered network namespace {}", netns.label);
+                Ok(f())
+            })
+            .join()
+            .unwrap_or_else(|payload| panic::resume_unwind(payload))
+    })
+}
+
+fn cgroup_and_container(cgroups: &ProcessCGroups) -> (Option<String>, Option<Container>) {
+    // prefer the unified (v2) hierarchy, which always has the ID 0
+    let cgroup = cgroups
+        .into_iter()
+        .min_by_key(|cgroup| cgroup.hierarchy)
+        .map(|cgroup| cgroup.pathname.clone());
+    let container = cgroups
+        .into_iter()
+        .find_map(|cgroup| container_from_cgroup(&cgroup.pathname))
+        .map(|(runtime, id)| {
+            let name = container_name(runtime, &id);
+            Container { runtime, id, name }
+        });
+    (cgroup, container)
+}
+
+/// Derives the runtime and ID of a container from a cgroup path, such as
+/// `/system.slice/docker-<id>.scope` or `/kubepods/burstable/pod<uid>/<id>`.
+fn container_from_cgroup(path: &str) -> Option<(&'static str, String)> {
+    let is_id = |id: &str| id.len() == 64 && id.bytes().all(|b| b.is_ascii_hexdigit());
+    let components = path.split('/').collect_vec();
+
+    for (i, component) in components.iter().enumerate().rev() {
+        let unit = component.strip_suffix(".scope").unwrap_or(component);
+        // the conmon monitors of podman and cri-o are not part of the container
+        if unit.starts_with("libpod-conmon-") || unit.starts_with("crio-conmon-") {
+            return None;
+        }
+        let prefixed = [
+            ("docker-", "docker"),
+            ("libpod-", "podman"),
+            ("cri-containerd-", "containerd"),
+            ("crio-", "cri-o"),
+        ]
+        .into_iter()
+        .find_map(|(prefix, runtime)| Some((runtime, unit.strip_prefix(prefix)?)));
+        if let Some((runtime, id)) = prefixed.filter(|(_, id)| is_id(id)) {
+            return Some((runtime, id.to_owned()));
+        }
+        if let Some(name) = unit.strip_prefix("lxc.payload.") {
+            return Some(("lxc", name.to_owned()));
+        }
+
+        // cgroup v1 layouts, where the ID is a component of its own
+        let parents = &components[..i];
+        if is_id(unit) {
+            let runtime = if parents.contains(&"docker") {
+                "docker"
+            } else if parents.iter().any(|parent| parent.starts_with("kubepods")) {
+                "kubernetes"
+            } else {
+                "container"
+            };
+            return Some((runtime, unit.to_owned()));
+        }
+        if parents.last() == Some(&"lxc") {
+            return Some(("lxc", unit.to_owned()));
+        }
+    }
+    None
+}
+
+/// Looks up the name of a container, see [`ContainerNames`].
+fn container_name(runtime: &'static str, id: &str) -> Option<String> {
+    static NAMES: Lazy<Mutex<ContainerNames>> = Lazy::new(Default::default);
+
+    NAMES
+        .lock()
+        .unwrap()
+        .get(id, Instant::now(), || read_container_name(runtime, id))
+}
+
+/// The names of containers by ID, cached since they are looked up on every refresh.
+///
+/// A container whose name is not found, eg. because the runtime has yet to write it, is looked
+/// up again after [`UNNAMED_CONTAINER_TTL`]. The least recently used names are evicted past
+/// [`MAX_CONTAINER_NAMES`].
+#[derive(Debug, Default)]
+struct ContainerNames {
+    names: HashMap<String, CachedName>,
+}
+
+#[derive(Debug)]
+struct CachedName {
+    name: Option<String>,
+    looked_up: Instant,
+    last_used: Instant,
+}
+
+impl ContainerNames {
+    fn get(
+        &mut self,
+        id: &str,
+        now: Instant,
+        look_up: impl FnOnce() -> Option<String>,
+    ) -> Option<String> {
+        if let Some(cached) = self.names.get_mut(id) {
+            if cached.name.is_some() || now - cached.looked_up < UNNAMED_CONTAINER_TTL {
+                cached.last_used = now;
+                return cached.name.clone();
+            }
+        }
+        let name = look_up();
+        if self.names.len() >= MAX_CONTAINER_NAMES && !self.names.contains_key(id) {
+            let least_recent = self
+                .names
+                .iter()
+                .min_by_key(|(_, cached)| cached.last_used)
+                .map(|(id, _)| id.clone());
+            if let Some(least_recent) = least_recent {
+                self.names.remove(&least_recent);
+            }
+        }
+        self.names.insert(
+            id.to_owned(),
+            CachedName {
+                name: name.clone(),
+                looked_up: now,
+                last_used: now,
+            },
+        );
+        name
+    }
+}
+
+/// Reads the name of a container from the runtime's state on disk.
+fn read_container_name(runtime: &str, id: &str) -> Option<String> {
+    match runtime {
+        "docker" => {
+            let path = format!("/var/lib/docker/containers/{id}/config.v2.json");
+            let config: serde_json::Value = serde_json::from_slice(&fs::read(path).ok()?).ok()?;
+            config["Name"]
+                .as_str()
+                .map(|name| name.trim_start_matches('/').to_owned())
+        }
+        "podman" => {
+            let path = "/var/lib/containers/storage/overlay-containers/containers.json";
+            let containers: serde_json::Value =
+                serde_json::from_slice(&fs::read(path).ok()?).ok()?;
+            containers
+                .as_array()?
+                .iter()
+                .find(|container| container["id"] == id)
+                .and_then(|container| container["names"][0].as_str())
+                .map(str::to_owned)
+        }
+        // lxc containers are already known by their name
+        _ => None,
+    }
+}
+
+/// Opens an `AF_PACKET` socket with the filter attached to it, for pnet to capture from.
+pub(crate) fn filtered_packet_socket(
+    interface: &NetworkInterface,
+    filter: &CaptureFilter,
+    tunnels: TunnelFlows,
+) -> io::Result<OwnedFd> {
+    let protocol = i32::from((libc::ETH_P_ALL as u16).to_be());
+    let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, protocol) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the descriptor was just opened, and nothing else owns it
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // asked through the socket rather than sysfs, which may belong to another namespace
+    let link_type = link_type(&socket, &interface.name)?;
+    let link_layer = link_layer(link_type).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("unsupported link type {link_type}"),
+        )
+    })?;
+    attach_filter(&socket, filter, link_layer, tunnels)?;
+    Ok(socket)
+}
+
+/// Compiles the filter for frames of the link layer, and has the kernel run it on the socket.
+fn attach_filter(
+    socket: &OwnedFd,
+    filter: &CaptureFilter,
+    link_layer: LinkLayer,
+    tunnels: TunnelFlows,
+) -> io::Result<()> {
+    let program = compile_bpf(filter, link_layer, tunnels)
+        .ok_or_else(|| io::Error::other("the filter is too large"))?;
+
+    let program = libc::sock_fprog {
+        len: program.len() as u16,
+        // the kernel copies the program, and `BpfInstruction` has the layout of `sock_filter`
+        filter: program.as_ptr() as *mut libc::sock_filter,
+    };
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_ATTACH_FILTER,
+            (&program as *const libc::sock_fprog).cast(),
+            mem::size_of::<libc::sock_fprog>() as libc::socklen_t,
+        )
+    };
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// The interface which stands for all interfaces, including those that come up later, as with
+/// libpcap.
+pub(crate) fn any_interface() -> NetworkInterface {
+    NetworkInterface {
+        name: ANY_INTERFACE.to_owned(),
+        description: "Pseudo-device that captures on all interfaces".to_owned(),
+        index: 0,
+        mac: None,
+        ips: datalink::interfaces()
+            .into_iter()
+            .flat_map(|interface| interface.ips)
+            .collect(),
+        flags: libc::IFF_UP as u32,
+    }
+}
+
+/// Captures on all interfaces at once, whose link layers differ, so each packet is read without
+/// its link-layer header and given a Linux cooked one instead, as libpcap does.
+pub(crate) struct CookedReceiver {
+    socket: OwnedFd,
+    /// The cooked header, followed by the packet.
+    buffer: Vec<u8>,
+}
+
+impl CookedReceiver {
+    pub(crate) fn new(
+        filter: Option<&CaptureFilter>,
+        tunnels: TunnelFlows,
+        read_timeout: Duration,
+    ) -> io::Result<Self> {
+        let protocol = i32::from((libc::ETH_P_ALL as u16).to_be());
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_DGRAM, protocol) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the descriptor was just opened, and nothing else owns it
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let timeout = libc::timeval {
+            tv_sec: read_timeout.as_secs() as libc::time_t,
+            tv_usec: read_timeout.subsec_micros() as libc::suseconds_t,
+        };
+        let res = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                (&timeout as *const libc::timeval).cast(),
+                mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        if res == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // the filter sees the packets before they are given a header
+        if let Some(filter) = filter {
+            if let Err(err) = attach_filter(&socket, filter, LinkLayer::RawIp, tunnels) {
+                info!(
+                    "{ANY_INTERFACE}: cannot attach filter to the socket, \
+                     filtering in userspace instead: {err}"
+                );
+            }
+        }
+        Ok(Self {
+            socket,
+            buffer: vec![0; LinkLayer::LinuxSll.header_len() + COOKED_BUFFER_SIZE],
+        })
+    }
+}
+
+impl DataLinkReceiver for CookedReceiver {
+    fn next(&mut self) -> io::Result<&[u8]> {
+        let header_len = LinkLayer::LinuxSll.header_len();
+        loop {
+            // SAFETY: `sockaddr_ll` is plain old data, for which all zeroes is valid
+            let mut address: libc::sockaddr_ll = unsafe { mem::zeroed() };
+            let mut address_len = mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+            let packet = &mut self.buffer[header_len..];
+            let len = unsafe {
+                libc::recvfrom(
+                    self.socket.as_raw_fd(),
+                    packet.as_mut_ptr().cast(),
+                    packet.len(),
+                    0,
+                    (&mut address as *mut libc::sockaddr_ll).cast(),
+                    &mut address_len,
+                )
+            };
+            if len == -1 {
+                let err = io::Error::last_os_error();
+                // the read timeout passed, as it does for pnet's receivers
+                return Err(match err.kind() {
+                    io::ErrorKind::WouldBlock => io::Error::from(io::ErrorKind::TimedOut),
+                    _ => err,
+                });
+            }
+            // like libpcap, only count packets over loopback as they are received
+            if address.sll_hatype == libc::ARPHRD_LOOPBACK
+                && address.sll_pkttype == libc::PACKET_OUTGOING
+            {
+                continue;
+            }
+
+            let header = &mut self.buffer[..header_len];
+            header[0..2].copy_from_slice(&u16::from(address.sll_pkttype).to_be_bytes());
+            header[2..4].copy_from_slice(&address.sll_hatype.to_be_bytes());
+            header[4..6].copy_from_slice(&u16::from(address.sll_halen).to_be_bytes());
+            header[6..14].copy_from_slice(&address.sll_addr);
+            // already in network byte order
+            header[14..16].copy_from_slice(&address.sll_protocol.to_ne_bytes());
+            return Ok(&self.buffer[..header_len + len as usize]);
+        }
+    }
+}
+
+/// What precedes the IP header in the frames captured on an interface, if its link type is known.
+///
+/// Like [`filtered_packet_socket`], this asks the network namespace of the calling thread.
+pub(crate) fn interface_link_layer(interface_name: &str) -> io::Result<Option<LinkLayer>> {
+    if interface_name == ANY_INTERFACE {
+        return Ok(Some(LinkLayer::LinuxSll));
+    }
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the descriptor was just opened, and nothing else owns it
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+    Ok(link_layer(link_type(&socket, interface_name)?))
+}
+
+fn link_layer(link_type: u16) -> Option<LinkLayer> {
+    match link_type {
+        libc::ARPHRD_ETHER | libc::ARPHRD_LOOPBACK => Some(LinkLayer::Ethernet),
+        // eg. tun, wireguard, ppp and IP-in-IP interfaces, which have no link-layer header
+        libc::ARPHRD_NONE
+        | libc::ARPHRD_PPP
+        | libc::ARPHRD_TUNNEL
+        | libc::ARPHRD_TUNNEL6
+        | libc::ARPHRD_SIT => Some(LinkLayer::RawIp),
+        _ => None,
+    }
+}
+
+/// The `ARPHRD_*` hardware type of an interface.
+fn link_type(socket: &OwnedFd, interface_name: &str) -> io::Result<u16> {
+    // SAFETY: `ifreq` is plain old data, for which all zeroes is valid
+    let mut request: libc::ifreq = unsafe { mem::zeroed() };
+    let name = interface_name.as_bytes();
+    if name.len() >= request.ifr_name.len() {
+        return Err(io::Error::from(io::ErrorKind::InvalidInput));
+    }
+    for (dst, src) in request.ifr_name.iter_mut().zip(name) {
+        *dst = *src as libc::c_char;
+    }
+    if unsafe { libc::ioctl(socket.as_raw_fd(), libc::SIOCGIFHWADDR, &mut request) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: SIOCGIFHWADDR fills in the hardware address variant
+    Ok(unsafe { request.ifr_ifru.ifru_hwaddr.sa_family })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, process};
+
+    use rstest::rstest;
+
+    use super::*;
+
+    /// Whether the tests reading our own process can run, as sandboxes may hide `/proc`.
+    fn proc_available() -> bool {
+        let available = Path::new("/proc/self/fd").is_dir();
+        if !available {
+            eprintln!("skipped: /proc is unavailable");
+        }
+        available
+    }
+
+    const ID: &str = "3f4e5a6b7c8d9e0f1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b1c2d3e4f";
+
+    #[rstest]
+    #[case("/system.slice/docker-{ID}.scope", Some("docker"))]
+    #[case("/docker/{ID}", Some("docker"))]
+    #[case("/machine.slice/libpod-{ID}.scope/container", Some("podman"))]
+    #[case(
+        "/user.slice/user-1000.slice/user@1000.service/user.slice/libpod-{ID}.scope",
+        Some("podman")
+    )]
+    #[case("/machine.slice/libpod-conmon-{ID}.scope", None)]
+    #[case(
+        "/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod1234.slice/cri-containerd-{ID}.scope",
+        Some("containerd")
+    )]
+    #[case("/kubepods/besteffort/pod1234/{ID}", Some("kubernetes"))]
+    #[case("/user.slice/user-1000.slice/session-2.scope", None)]
+    #[case("/", None)]
+    fn derives_containers_from_cgroups(#[case] path: &str, #[case] runtime: Option<&str>) {
+        let path = path.replace("{ID}", ID);
+        let expected = runtime.map(|runtime| (runtime, ID.to_owned()));
+        assert_eq!(container_from_cgroup(&path), expected);
+    }
+
+    #[test]
+    fn lxc_containers_are_named_by_their_cgroup() {
+        assert_eq!(
+            container_from_cgroup("/lxc.payload.web/init.scope"),
+            Some(("lxc", "web".to_owned()))
+        );
+        assert_eq!(
+            container_from_cgroup("/lxc/web"),
+            Some(("lxc", "web".to_owned()))
+        );
+    }
+
+    #[test]
+    fn looks_up_unnamed_containers_again() {
+        let mut names = ContainerNames::default();
+        let start = Instant::now();
+        assert_eq!(names.get("a", start, || None), None);
+        // not yet written by the runtime, then named
+        let later = start + Duration::from_secs(1);
+        assert_eq!(names.get("a", later, || Some("web".into())), None);
+        let later = start + UNNAMED_CONTAINER_TTL;
+        assert_eq!(
+            names.get("a", later, || Some("web".into())),
+            Some("web".into())
+        );
+        assert_eq!(names.get("a", later, || unreachable!()), Some("web".into()));
+    }
+
+    #[test]
+    fn evicts_least_recently_used_container_names() {
+        let mut names = ContainerNames::default();
+        let start = Instant::now();
+        for i in 0..MAX_CONTAINER_NAMES as u64 {
+            let now = start + Duration::from_millis(i);
+            names.get(&i.to_string(), now, || Some(format!("container-{i}")));
+        }
+        // the oldest is used again, so the second oldest goes
+        let now = start + Duration::from_secs(60);
+        names.get("0", now, || unreachable!());
+        names.get("new", now, || Some("new".into()));
+        assert_eq!(names.names.len(), MAX_CONTAINER_NAMES);
+        assert!(names.names.contains_key("0"));
+        assert!(!names.names.contains_key("1"));
+    }
+
+    #[test]
+    fn rescans_processes_for_new_sockets() {
+        if !proc_available() {
+            return;
+        }
+        let mut cache = ProcessCache::default();
+        cache.update(false);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let local_address = listener.local_addr().unwrap();
+        let Ok(sockets) = dump_sockets() else {
+            eprintln!("skipped: NETLINK_SOCK_DIAG is unavailable");
+            return;
+        };
+        let inode = sockets
+            .into_iter()
+            .find(|entry| entry.local_address == local_address)
+            .unwrap()
+            .inode;
+        // only new processes are scanned on update
+        cache.update(false);
+        assert!(cache.owner(inode).is_none());
+
+        cache.find_owners(&HashSet::from([inode]), false);
+        assert_eq!(cache.owner(inode).unwrap().pid, process::id());
+    }
+
+    #[test]
+    fn rescans_reused_pids_and_renames_after_exec() {
+        if !proc_available() {
+            return;
+        }
+        let mut cache = ProcessCache::default();
+        cache.update(false);
+        let pid = process::id() as i32;
+        let own = &cache.processes[&pid];
+        let (name, start_time) = (own.info.name.clone(), own.start_time);
 
-    insert_proto!([procfs::net::tcp(), procfs::net::tcp6()], Protocol::Tcp);
-    insert_proto!([procfs::net::udp(), procfs::net::udp6()], Protocol::Udp);
+        cache.processes.get_mut(&pid).unwrap().info.name = "before-exec".into();
+        cache.update(false);
+        assert_eq!(cache.processes[&pid].info.name, name);
 
-    OpenSockets {
-        sockets_to_procs: open_sockets,
+        // as if another process had the PID before
+        let stale = cache.processes.get_mut(&pid).unwrap();
+        stale.start_time += 1;
+        stale.socket_inodes = vec![u64::MAX];
+        cache.inode_to_pid.insert(u64::MAX, pid);
+        cache.update(false);
+        assert_eq!(cache.processes[&pid].start_time, start_time);
+        assert!(cache.owner(u64::MAX).is_none());
     }
 }
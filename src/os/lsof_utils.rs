@@ -135,7 +135,12 @@ impl RawConnection {
             return None;
         };
 
-        Some(LocalSocket { ip, port, protocol })
+        Some(LocalSocket {
+            ip,
+            port,
+            protocol,
+            netns: 0,
+        })
     }
 }
 
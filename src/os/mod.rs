@@ -1,6 +1,9 @@
 #[cfg(any(target_os = "android", target_os = "linux"))]
 mod linux;
 
+#[cfg(any(target_os = "android", target_os = "linux"))]
+mod sock_diag;
+
 #[cfg(any(target_os = "macos", target_os = "freebsd"))]
 mod lsof;
 
@@ -11,6 +14,7 @@ mod lsof_utils;
 mod windows;
 
 mod errors;
+mod pcap;
 pub(crate) mod shared;
 
 pub use shared::*;
@@ -0,0 +1,663 @@
+use std::{
+    io::{self, BufReader, ErrorKind, Read},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+use pnet::datalink::DataLinkReceiver;
+
+use crate::{cli::ReplaySpeed, network::LinkLayer};
+
+/// The longest we sleep in one go while waiting for the next packet's timestamp.
+/// Returning in between lets the sniffer thread check whether it should stop.
+const MAX_REPLAY_WAIT: Duration = Duration::from_millis(100);
+
+/// The longest packet read, as the largest snaplen tcpdump and Wireshark capture with, so that a
+/// corrupt length cannot make us allocate gigabytes.
+const MAX_PACKET_LEN: usize = 256 * 1024;
+/// The longest pcapng block read, leaving room for the options of a packet of [`MAX_PACKET_LEN`].
+const MAX_BLOCK_LEN: usize = MAX_PACKET_LEN + 64 * 1024;
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+const PCAPNG_OBSOLETE_PACKET: u32 = 0x0000_0002;
+const PCAPNG_SIMPLE_PACKET: u32 = 0x0000_0003;
+const PCAPNG_ENHANCED_PACKET: u32 = 0x0000_0006;
+const PCAPNG_OPTION_END: u16 = 0;
+const PCAPNG_OPTION_IF_TSRESOL: u16 = 9;
+
+// See https://www.tcpdump.org/linktypes.html
+const LINKTYPE_NULL: u16 = 0;
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_RAW: u16 = 101;
+const LINKTYPE_LOOP: u16 = 108;
+const LINKTYPE_LINUX_SLL: u16 = 113;
+const LINKTYPE_IPV4: u16 = 228;
+const LINKTYPE_IPV6: u16 = 229;
+const LINKTYPE_LINUX_SLL2: u16 = 276;
+
+/// Returns the link layer of the frames of a link type, and the number of bytes to skip to get to
+/// a frame of that link layer, or `None` if the link type is unsupported.
+fn link_layer(link_type: u16) -> Option<(LinkLayer, usize)> {
+    match link_type {
+        LINKTYPE_ETHERNET => Some((LinkLayer::Ethernet, 0)),
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => Some((LinkLayer::RawIp, 0)),
+        LINKTYPE_NULL | LINKTYPE_LOOP => Some((LinkLayer::RawIp, 4)),
+        LINKTYPE_LINUX_SLL => Some((LinkLayer::LinuxSll, 0)),
+        LINKTYPE_LINUX_SLL2 => Some((LinkLayer::LinuxSll2, 0)),
+        _ => None,
+    }
+}
+
+/// Replaces the link-layer header of a frame with an Ethernet one with zeroed out addresses, or
+/// adds one to a bare IP packet, returning `None` if the frame is too short.
+///
+/// The protocol after a cooked header may be a VLAN tag rather than IP, so the header is replaced
+/// rather than dropped.
+fn to_ethernet(link_layer: LinkLayer, mut frame: Vec<u8>) -> Option<Vec<u8>> {
+    let ethertype = match link_layer {
+        LinkLayer::Ethernet => return Some(frame),
+        LinkLayer::RawIp => match frame.first()? >> 4 {
+            4 => [0x08, 0x00],
+            6 => [0x86, 0xdd],
+            _ => return None,
+        },
+        LinkLayer::LinuxSll | LinkLayer::LinuxSll2 => {
+            let offset = link_layer.ethertype_offset()?;
+            frame.get(offset..offset + 2)?.try_into().ok()?
+        }
+    };
+    frame.splice(
+        ..link_layer.header_len().min(frame.len()),
+        [[0; 12].as_slice(), &ethertype].concat(),
+    );
+    Some(frame)
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Endianness {
+    Little,
+    Big,
+}
+
+impl Endianness {
+    fn u16(self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        match self {
+            Self::Little => u16::from_le_bytes(bytes),
+            Self::Big => u16::from_be_bytes(bytes),
+        }
+    }
+    fn u32(self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        match self {
+            Self::Little => u32::from_le_bytes(bytes),
+            Self::Big => u32::from_be_bytes(bytes),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct PcapngInterface {
+    link_type: u16,
+    /// Number of timestamp units per second.
+    ticks_per_second: u64,
+}
+
+#[derive(Debug)]
+enum Format {
+    Pcap {
+        endianness: Endianness,
+        link_type: u16,
+        ticks_per_second: u64,
+    },
+    Pcapng {
+        endianness: Endianness,
+        interfaces: Vec<PcapngInterface>,
+    },
+}
+
+/// A frame receiver that reads packets from a pcap or pcapng capture file.
+pub struct CaptureFileReader<R> {
+    reader: R,
+    format: Format,
+    replay_speed: ReplaySpeed,
+    /// The frame most recently read from the file, stripped of its link-layer header if needed.
+    frame: Vec<u8>,
+    /// Whether `frame` has been read but not yet handed out.
+    pending: bool,
+    /// The capture timestamp of the first packet, and when we replayed it.
+    replay_start: Option<(Duration, Instant)>,
+    timestamp: Duration,
+    finished: bool,
+}
+
+impl<R> CaptureFileReader<BufReader<R>>
+where
+    R: Read,
+{
+    pub fn new(reader: R, replay_speed: ReplaySpeed) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+
+        let format = if u32::from_be_bytes(magic) == PCAPNG_SECTION_HEADER {
+            let endianness = read_section_header_body(&mut reader)?;
+            Format::Pcapng {
+                endianness,
+                interfaces: vec![],
+            }
+        } else {
+            let (endianness, ticks_per_second) = match magic {
+                m if u32::from_le_bytes(m) == PCAP_MAGIC_MICROS => (Endianness::Little, 1_000_000),
+                m if u32::from_be_bytes(m) == PCAP_MAGIC_MICROS => (Endianness::Big, 1_000_000),
+                m if u32::from_le_bytes(m) == PCAP_MAGIC_NANOS => {
+                    (Endianness::Little, 1_000_000_000)
+                }
+                m if u32::from_be_bytes(m) == PCAP_MAGIC_NANOS => (Endianness::Big, 1_000_000_000),
+                _ => return Err(invalid_data("not a pcap or pcapng file")),
+            };
+            // version (4), thiszone (4), sigfigs (4), snaplen (4), network (4)
+            let mut header = [0; 20];
+            reader.read_exact(&mut header)?;
+            let link_type = endianness.u32(&header[16..]) as u16;
+            if link_layer(link_type).is_none() {
+                return Err(invalid_data(format!("unsupported link type {link_type}")));
+            }
+            Format::Pcap {
+                endianness,
+                link_type,
+                ticks_per_second,
+            }
+        };
+
+        Ok(Self {
+            reader,
+            format,
+            replay_speed,
+            frame: vec![],
+            pending: false,
+            replay_start: None,
+            timestamp: Duration::ZERO,
+            finished: false,
+        })
+    }
+}
+
+impl<R> CaptureFileReader<R>
+where
+    R: Read,
+{
+    /// Reads the next packet into `self.frame`.
+    ///
+    /// Returns `Ok(false)` when the end of the file has been reached.
+    fn read_packet(&mut self) -> io::Result<bool> {
+        loop {
+            let read = match self.format {
+                Format::Pcap {
+                    endianness,
+                    link_type,
+                    ticks_per_second,
+                } => {
+                    let mut header = [0; 16];
+                    if !read_exact_or_eof(&mut self.reader, &mut header)? {
+                        return Ok(false);
+                    }
+                    let seconds = endianness.u32(&header[0..]) as u64;
+                    let fraction = endianness.u32(&header[4..]) as u64;
+                    let captured_len = endianness.u32(&header[8..]) as usize;
+                    if captured_len > MAX_PACKET_LEN {
+                        return Err(invalid_data(format!(
+                            "invalid pcap packet length {captured_len}"
+                        )));
+                    }
+                    let mut data = vec![0; captured_len];
+                    self.reader.read_exact(&mut data)?;
+                    self.timestamp = Duration::from_secs(seconds)
+                        + ticks_to_duration(fraction, ticks_per_second);
+                    self.set_frame(link_type, data)
+                }
+                Format::Pcapng { .. } => match self.read_pcapng_block()? {
+                    Some(read) => read,
+                    None => return Ok(false),
+                },
+            };
+            if read {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Reads a single pcapng block, returning whether it contained a usable packet.
+    ///
+    /// Returns `Ok(None)` when the end of the file has been reached.
+    fn read_pcapng_block(&mut self) -> io::Result<Option<bool>> {
+        let Format::Pcapng { endianness, .. } = self.format else {
+            unreachable!("read_pcapng_block called on a pcap file");
+        };
+        let mut header = [0; 8];
+        if !read_exact_or_eof(&mut self.reader, &mut header)? {
+            return Ok(None);
+        }
+        let block_type = endianness.u32(&header[0..]);
+        if block_type == PCAPNG_SECTION_HEADER {
+            // a new section may switch byte order and resets the interface list
+            let endianness = read_section_header_body(&mut (&header[4..]).chain(&mut self.reader))?;
+            self.format = Format::Pcapng {
+                endianness,
+                interfaces: vec![],
+            };
+            return Ok(Some(false));
+        }
+        let block_len = endianness.u32(&header[4..]) as usize;
+        if block_len < 12 || !block_len.is_multiple_of(4) || block_len > MAX_BLOCK_LEN {
+            return Err(invalid_data(format!(
+                "invalid pcapng block length {block_len}"
+            )));
+        }
+        // body plus the trailing copy of the block length
+        let mut body = vec![0; block_len - 8];
+        self.reader.read_exact(&mut body)?;
+        body.truncate(block_len - 12);
+
+        let Format::Pcapng {
+            endianness,
+            ref mut interfaces,
+        } = self.format
+        else {
+            unreachable!();
+        };
+        let too_short = || invalid_data(format!("truncated pcapng block of type {block_type}"));
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION => {
+                if body.len() < 8 {
+                    return Err(too_short());
+                }
+                let link_type = endianness.u16(&body[0..]);
+                let ticks_per_second = read_tsresol(endianness, &body[8..]);
+                interfaces.push(PcapngInterface {
+                    link_type,
+                    ticks_per_second,
+                });
+                Ok(Some(false))
+            }
+            PCAPNG_ENHANCED_PACKET | PCAPNG_OBSOLETE_PACKET => {
+                if body.len() < 20 {
+                    return Err(too_short());
+                }
+                let interface_id = if block_type == PCAPNG_ENHANCED_PACKET {
+                    endianness.u32(&body[0..]) as usize
+                } else {
+                    endianness.u16(&body[0..]) as usize
+                };
+                let Some(&interface) = interfaces.get(interface_id) else {
+                    return Err(invalid_data(format!(
+                        "packet refers to unknown interface {interface_id}"
+                    )));
+                };
+                let ticks =
+                    (endianness.u32(&body[4..]) as u64) << 32 | endianness.u32(&body[8..]) as u64;
+                let captured_len = endianness.u32(&body[12..]) as usize;
+                let Some(data) = body.get(20..20 + captured_len) else {
+                    return Err(too_short());
+                };
+                let data = data.to_vec();
+                self.timestamp = ticks_to_duration(ticks, interface.ticks_per_second);
+                Ok(Some(self.set_frame(interface.link_type, data)))
+            }
+            PCAPNG_SIMPLE_PACKET => {
+                // simple packets carry no timestamp and always belong to the first interface
+                let Some(&interface) = interfaces.first() else {
+                    return Err(invalid_data("packet refers to unknown interface 0"));
+                };
+                if body.len() < 4 {
+                    return Err(too_short());
+                }
+                let original_len = endianness.u32(&body[0..]) as usize;
+                let data = body[4..].iter().take(original_len).copied().collect();
+                Ok(Some(self.set_frame(interface.link_type, data)))
+            }
+            // statistics, name resolution, custom blocks etc.
+            _ => Ok(Some(false)),
+        }
+    }
+
+    /// What precedes the IP header in the frames read from the file.
+    ///
+    /// Every interface of a pcapng file can have its own link type, so their frames are all given
+    /// an Ethernet header.
+    pub fn link_layer(&self) -> LinkLayer {
+        match self.format {
+            Format::Pcap { link_type, .. } => {
+                let (link_layer, _) =
+                    link_layer(link_type).expect("the link type is checked on opening");
+                link_layer
+            }
+            Format::Pcapng { .. } => LinkLayer::Ethernet,
+        }
+    }
+
+    /// Stores a packet as the current frame, returning `false` if it cannot be used.
+    fn set_frame(&mut self, link_type: u16, mut data: Vec<u8>) -> bool {
+        let Some((link_layer, skipped_len)) = link_layer(link_type) else {
+            warn!("Skipping packet with unsupported link type {link_type}");
+            return false;
+        };
+        if data.len() < skipped_len {
+            return false;
+        }
+        data.drain(..skipped_len);
+        if let Format::Pcapng { .. } = self.format {
+            let Some(frame) = to_ethernet(link_layer, data) else {
+                return false;
+            };
+            data = frame;
+        }
+        self.frame = data;
+        true
+    }
+
+    /// How long to wait before the current frame should be replayed.
+    fn replay_delay(&mut self) -> Duration {
+        match self.replay_speed {
+            ReplaySpeed::Fast => Duration::ZERO,
+            ReplaySpeed::Realtime => {
+                let &mut (first_timestamp, started) = self
+                    .replay_start
+                    .get_or_insert((self.timestamp, Instant::now()));
+                let offset = self.timestamp.saturating_sub(first_timestamp);
+                offset.saturating_sub(started.elapsed())
+            }
+        }
+    }
+}
+
+impl<R> DataLinkReceiver for CaptureFileReader<R>
+where
+    R: Read + Send,
+{
+    fn next(&mut self) -> io::Result<&[u8]> {
+        if self.finished {
+            // behave like an idle interface once the whole file has been replayed
+            thread::sleep(MAX_REPLAY_WAIT);
+            return Err(ErrorKind::TimedOut.into());
+        }
+        if !self.pending {
+            match self.read_packet() {
+                Ok(true) => self.pending = true,
+                Ok(false) => {
+                    info!("Reached the end of the capture file");
+                    self.finished = true;
+                    return Err(ErrorKind::TimedOut.into());
+                }
+                Err(err) => {
+                    warn!("Stopped reading the capture file: {err}");
+                    self.finished = true;
+                    return Err(ErrorKind::TimedOut.into());
+                }
+            }
+        }
+
+        let delay = self.replay_delay();
+        if delay > MAX_REPLAY_WAIT {
+            thread::sleep(MAX_REPLAY_WAIT);
+            return Err(ErrorKind::TimedOut.into());
+        }
+        thread::sleep(delay);
+        self.pending = false;
+        Ok(&self.frame)
+    }
+}
+
+/// Reads the remainder of a pcapng section header block, after its block type,
+/// and returns the byte order of the section.
+fn read_section_header_body(reader: &mut impl Read) -> io::Result<Endianness> {
+    let mut header = [0; 8];
+    reader.read_exact(&mut header)?;
+    let endianness = match [header[4], header[5], header[6], header[7]] {
+        m if u32::from_le_bytes(m) == PCAPNG_BYTE_ORDER_MAGIC => Endianness::Little,
+        m if u32::from_be_bytes(m) == PCAPNG_BYTE_ORDER_MAGIC => Endianness::Big,
+        _ => return Err(invalid_data("invalid pcapng byte order magic")),
+    };
+    let block_len = endianness.u32(&header[0..]) as usize;
+    if block_len < 16 {
+        return Err(invalid_data(format!(
+            "invalid pcapng block length {block_len}"
+        )));
+    }
+    // skip the version, section length, options and trailing block length
+    io::copy(&mut reader.take((block_len - 12) as u64), &mut io::sink())?;
+    Ok(endianness)
+}
+
+/// Finds the `if_tsresol` option of an interface description block, if any.
+fn read_tsresol(endianness: Endianness, mut options: &[u8]) -> u64 {
+    const DEFAULT_TICKS_PER_SECOND: u64 = 1_000_000;
+
+    while options.len() >= 4 {
+        let code = endianness.u16(&options[0..]);
+        let len = endianness.u16(&options[2..]) as usize;
+        if code == PCAPNG_OPTION_END {
+            break;
+        }
+        if code == PCAPNG_OPTION_IF_TSRESOL && len == 1 && options.len() > 4 {
+            let resolution = options[4];
+            let exponent = (resolution & 0x7f) as u32;
+            let base: u64 = if resolution & 0x80 == 0 { 10 } else { 2 };
+            return base
+                .checked_pow(exponent)
+                .unwrap_or(DEFAULT_TICKS_PER_SECOND);
+        }
+        // option values are padded to 32 bits
+        let padded_len = 4 + len.div_ceil(4) * 4;
+        options = options.get(padded_len..).unwrap_or_default();
+    }
+    DEFAULT_TICKS_PER_SECOND
+}
+
+fn ticks_to_duration(ticks: u64, ticks_per_second: u64) -> Duration {
+    let seconds = ticks / ticks_per_second;
+    let nanos = (ticks % ticks_per_second) as u128 * 1_000_000_000 / ticks_per_second as u128;
+    Duration::new(seconds, nanos as u32)
+}
+
+/// Like [`Read::read_exact`], but returns `Ok(false)` if the reader is already at EOF.
+fn read_exact_or_eof(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(true)
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const FRAME_1: &[u8] = b"first frame";
+    const FRAME_2: &[u8] = b"another frame!";
+
+    fn pcap_file(link_type: u32, frames: &[&[u8]]) -> Vec<u8> {
+        let mut file = vec![];
+        file.extend(PCAP_MAGIC_MICROS.to_le_bytes());
+        file.extend(2u16.to_le_bytes());
+        file.extend(4u16.to_le_bytes());
+        file.extend([0; 8]);
+        file.extend(65535u32.to_le_bytes());
+        file.extend(link_type.to_le_bytes());
+        for (i, frame) in frames.iter().enumerate() {
+            file.extend((i as u32).to_le_bytes());
+            file.extend(0u32.to_le_bytes());
+            file.extend((frame.len() as u32).to_le_bytes());
+            file.extend((frame.len() as u32).to_le_bytes());
+            file.extend(*frame);
+        }
+        file
+    }
+
+    fn pcapng_block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let padded_len = body.len().div_ceil(4) * 4;
+        let block_len = (12 + padded_len) as u32;
+        let mut block = vec![];
+        block.extend(block_type.to_be_bytes());
+        block.extend(block_len.to_be_bytes());
+        block.extend(body);
+        block.resize(8 + padded_len, 0);
+        block.extend(block_len.to_be_bytes());
+        block
+    }
+
+    fn pcapng_file(frames: &[&[u8]]) -> Vec<u8> {
+        let mut section_header = vec![];
+        section_header.extend(PCAPNG_BYTE_ORDER_MAGIC.to_be_bytes());
+        section_header.extend(1u16.to_be_bytes());
+        section_header.extend(0u16.to_be_bytes());
+        section_header.extend(u64::MAX.to_be_bytes());
+
+        let mut interface = vec![];
+        interface.extend((LINKTYPE_NULL).to_be_bytes());
+        interface.extend(0u16.to_be_bytes());
+        interface.extend(0u32.to_be_bytes());
+        // if_tsresol: milliseconds
+        interface.extend(PCAPNG_OPTION_IF_TSRESOL.to_be_bytes());
+        interface.extend(1u16.to_be_bytes());
+        interface.extend([3, 0, 0, 0]);
+        interface.extend(PCAPNG_OPTION_END.to_be_bytes());
+        interface.extend(0u16.to_be_bytes());
+
+        let mut file = pcapng_block(PCAPNG_SECTION_HEADER, &section_header);
+        file.extend(pcapng_block(PCAPNG_INTERFACE_DESCRIPTION, &interface));
+        for (i, frame) in frames.iter().enumerate() {
+            let mut packet = vec![];
+            packet.extend(0u32.to_be_bytes());
+            packet.extend(0u32.to_be_bytes());
+            packet.extend((i as u32 * 1000).to_be_bytes());
+            packet.extend((frame.len() as u32 + 4).to_be_bytes());
+            packet.extend((frame.len() as u32 + 4).to_be_bytes());
+            // loopback header
+            packet.extend([2, 0, 0, 0]);
+            packet.extend(*frame);
+            file.extend(pcapng_block(PCAPNG_ENHANCED_PACKET, &packet));
+        }
+        file
+    }
+
+    fn read_all(mut reader: impl DataLinkReceiver) -> Vec<Vec<u8>> {
+        let mut frames = vec![];
+        while let Ok(frame) = reader.next() {
+            frames.push(frame.to_vec());
+        }
+        frames
+    }
+
+    fn ethernet(ethertype: [u8; 2], payload: &[u8]) -> Vec<u8> {
+        [&[0; 12][..], &ethertype, payload].concat()
+    }
+
+    #[test]
+    fn reads_pcap() {
+        let file = pcap_file(LINKTYPE_ETHERNET as u32, &[FRAME_1, FRAME_2]);
+        let reader = CaptureFileReader::new(Cursor::new(file), ReplaySpeed::Fast).unwrap();
+        assert_eq!(reader.link_layer(), LinkLayer::Ethernet);
+        assert_eq!(read_all(reader), [FRAME_1, FRAME_2]);
+    }
+
+    #[test]
+    fn reads_pcapng_and_replaces_link_header() {
+        let file = pcapng_file(&[FRAME_1, FRAME_2]);
+        let mut reader = CaptureFileReader::new(Cursor::new(file), ReplaySpeed::Fast).unwrap();
+        assert_eq!(reader.link_layer(), LinkLayer::Ethernet);
+        // the frames start with a lowercase letter from 0x60, as IPv6 packets do
+        assert_eq!(reader.next().unwrap(), ethernet([0x86, 0xdd], FRAME_1));
+        assert_eq!(reader.next().unwrap(), ethernet([0x86, 0xdd], FRAME_2));
+        // 1000 ticks at millisecond resolution
+        assert_eq!(reader.timestamp, Duration::from_secs(1));
+        assert_eq!(reader.next().unwrap_err().kind(), ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn reads_linux_cooked_captures() {
+        let mut sll = vec![0; 14];
+        sll.extend([0x81, 0x00]);
+        sll.extend(FRAME_1);
+        let mut sll2 = vec![0x86, 0xdd];
+        sll2.extend([0; 18]);
+        sll2.extend(FRAME_2);
+
+        let file = pcap_file(LINKTYPE_LINUX_SLL as u32, &[&sll]);
+        let reader = CaptureFileReader::new(Cursor::new(file), ReplaySpeed::Fast).unwrap();
+        assert_eq!(reader.link_layer(), LinkLayer::LinuxSll);
+        assert_eq!(read_all(reader), [sll.clone()]);
+        let file = pcap_file(LINKTYPE_LINUX_SLL2 as u32, &[&sll2]);
+        let reader = CaptureFileReader::new(Cursor::new(file), ReplaySpeed::Fast).unwrap();
+        assert_eq!(reader.link_layer(), LinkLayer::LinuxSll2);
+        assert_eq!(read_all(reader), [sll2.clone()]);
+
+        // pcapng files get an Ethernet header instead
+        assert_eq!(
+            to_ethernet(LinkLayer::LinuxSll, sll),
+            Some(ethernet([0x81, 0x00], FRAME_1))
+        );
+        assert_eq!(
+            to_ethernet(LinkLayer::LinuxSll2, sll2),
+            Some(ethernet([0x86, 0xdd], FRAME_2))
+        );
+        assert_eq!(to_ethernet(LinkLayer::LinuxSll2, vec![0x86]), None);
+    }
+
+    #[test]
+    fn replays_at_original_timestamps() {
+        let file = pcap_file(LINKTYPE_RAW as u32, &[FRAME_1, FRAME_2]);
+        let mut reader = CaptureFileReader::new(Cursor::new(file), ReplaySpeed::Realtime).unwrap();
+        assert_eq!(reader.link_layer(), LinkLayer::RawIp);
+        assert_eq!(reader.next().unwrap(), FRAME_1);
+        // the second frame was captured one second later
+        assert_eq!(reader.next().unwrap_err().kind(), ErrorKind::TimedOut);
+        assert!(reader.pending);
+    }
+
+    #[test]
+    fn rejects_bogus_lengths() {
+        let mut file = pcap_file(LINKTYPE_ETHERNET as u32, &[FRAME_1]);
+        // the captured length of the packet
+        file[32..36].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut reader = CaptureFileReader::new(Cursor::new(file), ReplaySpeed::Fast).unwrap();
+        assert_eq!(
+            reader.read_packet().unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+
+        let mut file = pcapng_file(&[FRAME_1]);
+        // the length of the packet block, the last one
+        let block_start = file.len() - pcapng_block(PCAPNG_ENHANCED_PACKET, &[0; 36]).len();
+        file[block_start + 4..block_start + 8].copy_from_slice(&0xffff_fffcu32.to_be_bytes());
+        let mut reader = CaptureFileReader::new(Cursor::new(file), ReplaySpeed::Fast).unwrap();
+        assert_eq!(
+            reader.read_packet().unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let file = b"definitely not a capture file".to_vec();
+        assert!(CaptureFileReader::new(Cursor::new(file), ReplaySpeed::Fast).is_err());
+    }
+}
@@ -1,29 +1,52 @@
 use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::File,
     io::{self, ErrorKind, Write},
-    net::Ipv4Addr,
-    time::{self, Duration},
+    net::{IpAddr, TcpListener},
+    path::Path,
+    sync::Arc,
+    time::{self, Duration, Instant},
 };
 
 use crossterm::event::{poll, read, Event};
-use eyre::{bail, eyre};
+use eyre::{bail, eyre, WrapErr};
 use itertools::Itertools;
-use log::{debug, warn};
-use pnet::datalink::{self, Channel::Ethernet, Config, DataLinkReceiver, NetworkInterface};
+use log::{debug, info, warn};
+use pnet::{
+    datalink::{self, Channel::Ethernet, Config, DataLinkReceiver, NetworkInterface},
+    ipnetwork::IpNetwork,
+};
 use tokio::runtime::Runtime;
 
-use crate::{network::dns, os::errors::GetInterfaceError, OsInputOutput};
+use crate::{
+    cli::{Opt, ReplaySpeed, TunnelFlows},
+    display::CsvFiles,
+    network::{dns, CaptureFilter, LinkLayer},
+    os::{errors::GetInterfaceError, pcap::CaptureFileReader},
+    OsInputOutput,
+};
 
 #[cfg(any(target_os = "android", target_os = "linux"))]
-use crate::os::linux::get_open_sockets;
+use crate::os::linux::{
+    any_interface, filtered_packet_socket, get_open_sockets, get_open_sockets_all_netns, in_netns,
+    interface_link_layer, network_namespaces, CookedReceiver, ANY_INTERFACE,
+};
 #[cfg(any(target_os = "macos", target_os = "freebsd"))]
 use crate::os::lsof::get_open_sockets;
 #[cfg(target_os = "windows")]
 use crate::os::windows::get_open_sockets;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use std::os::fd::IntoRawFd;
 
 #[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
 pub struct ProcessInfo {
     pub name: String,
     pub pid: u32,
+    /// The cgroup the process belongs to, on Linux.
+    pub cgroup: Option<String>,
+    /// The container the process runs in, derived from its cgroup.
+    pub container: Option<Container>,
 }
 
 impl ProcessInfo {
@@ -31,10 +54,44 @@ impl ProcessInfo {
         Self {
             name: name.to_string(),
             pid,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Container {
+    /// The container runtime or orchestrator, eg. `docker` or `kubernetes`.
+    pub runtime: &'static str,
+    pub id: String,
+    /// The name given to the container, when the runtime's state can be read.
+    pub name: Option<String>,
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{name}"),
+            // like `docker ps`, only show the first 12 characters of long IDs
+            None => {
+                let short_id = self.id.get(..12).unwrap_or(&self.id);
+                write!(f, "{}:{short_id}", self.runtime)
+            }
         }
     }
 }
 
+/// A network namespace other than the one bandwhich runs in.
+#[derive(Clone, Debug)]
+pub struct NetworkNamespace {
+    /// The inode of the namespace, which identifies it.
+    pub id: u64,
+    /// The name given by `ip netns`, else the container using the namespace, else its inode.
+    pub label: String,
+    /// An open handle to the namespace, which keeps it alive and allows entering it.
+    pub handle: Arc<File>,
+}
+
 /// Poll timeout for terminal events.
 /// This allows the event loop to periodically check the `running` flag
 /// for graceful shutdown on SIGINT.
@@ -57,15 +114,71 @@ impl Iterator for TerminalEvents {
     }
 }
 
+/// Like [`get_datalink_channel`], opening the channel in another network namespace if given.
+pub(crate) fn get_datalink_channel_in(
+    interface: &NetworkInterface,
+    filter: Option<&CaptureFilter>,
+    tunnels: TunnelFlows,
+    netns: Option<&NetworkNamespace>,
+) -> Result<Box<dyn DataLinkReceiver>, GetInterfaceError> {
+    let Some(netns) = netns else {
+        return get_datalink_channel(interface, filter, tunnels);
+    };
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    return in_netns(netns, || get_datalink_channel(interface, filter, tunnels)).unwrap_or_else(
+        |err| {
+            Err(GetInterfaceError::OtherError(format!(
+                "{}: cannot enter network namespace {}: {err}",
+                interface.name, netns.label
+            )))
+        },
+    );
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    unreachable!("network namespaces only exist on Linux, not for {netns:?}");
+}
+
 pub(crate) fn get_datalink_channel(
     interface: &NetworkInterface,
+    filter: Option<&CaptureFilter>,
+    tunnels: TunnelFlows,
 ) -> Result<Box<dyn DataLinkReceiver>, GetInterfaceError> {
-    let config = Config {
+    #[allow(unused_mut)]
+    let mut config = Config {
         read_timeout: Some(time::Duration::new(1, 0)),
         read_buffer_size: 65536,
         ..Default::default()
     };
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    if interface.name == ANY_INTERFACE {
+        let read_timeout = config.read_timeout.unwrap_or_default();
+        return match CookedReceiver::new(filter, tunnels, read_timeout) {
+            Ok(receiver) => Ok(Box::new(receiver)),
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => Err(
+                GetInterfaceError::PermissionError(interface.name.to_owned()),
+            ),
+            Err(e) => Err(GetInterfaceError::OtherError(format!(
+                "{}: {e}",
+                interface.name
+            ))),
+        };
+    }
+
+    // the sniffer checks every segment against the filter anyway,
+    // so letting the kernel drop frames early is only an optimisation
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    if let Some(filter) = filter {
+        match filtered_packet_socket(interface, filter, tunnels) {
+            Ok(socket) => config.socket_fd = Some(socket.into_raw_fd()),
+            Err(err) => info!(
+                "{}: cannot attach filter to the socket, filtering in userspace instead: {err}",
+                interface.name
+            ),
+        }
+    }
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    let _ = (filter, tunnels);
+
     match datalink::channel(interface, config) {
         Ok(Ethernet(_tx, rx)) => Ok(rx),
         Ok(_) => Err(GetInterfaceError::OtherError(format!(
@@ -84,7 +197,26 @@ pub(crate) fn get_datalink_channel(
     }
 }
 
+/// What precedes the IP header in the frames of an interface, or `None` if it has to be guessed
+/// from each frame.
+fn get_link_layer(interface: &NetworkInterface) -> Option<LinkLayer> {
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    return interface_link_layer(&interface.name).unwrap_or_else(|err| {
+        debug!("{}: cannot tell the link type: {err}", interface.name);
+        None
+    });
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    {
+        let _ = interface;
+        None
+    }
+}
+
 fn get_interface(interface_name: &str) -> Option<NetworkInterface> {
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    if interface_name == ANY_INTERFACE {
+        return Some(any_interface());
+    }
     datalink::interfaces()
         .into_iter()
         .find(|iface| iface.name == interface_name)
@@ -105,11 +237,66 @@ fn create_write_to_stdout() -> Box<dyn FnMut(&str) + Send> {
     })
 }
 
-pub fn get_input(
+/// An interface to sniff on, with the link layer of its frames if known, and the network namespace
+/// it is in if it isn't bandwhich's own.
+pub type InterfaceWithFrames = (
+    NetworkInterface,
+    Box<dyn DataLinkReceiver>,
+    Option<LinkLayer>,
+    Option<NetworkNamespace>,
+);
+pub type InterfacesWithFrames = Vec<InterfaceWithFrames>;
+
+/// The network namespaces found by a [`NetnsScanner`].
+pub struct NetnsScan {
+    /// The inodes of all other network namespaces which currently exist.
+    pub namespaces: HashSet<u64>,
+    /// Frame receivers for the interfaces which were not scanned before.
+    pub interfaces_with_frames: InterfacesWithFrames,
+}
+
+/// Looks for network namespaces with `--all-netns`, which come and go with containers.
+pub type NetnsScanner = Box<dyn FnMut() -> NetnsScan + Send>;
+
+/// Opens a capture file and presents it as a single interface.
+fn get_capture_file_frames(
+    path: &Path,
+    replay_speed: ReplaySpeed,
+    local_ips: &[IpAddr],
+) -> eyre::Result<InterfacesWithFrames> {
+    let file = File::open(path).wrap_err_with(|| format!("Cannot open {}", path.display()))?;
+    let frames = CaptureFileReader::new(file, replay_speed)
+        .wrap_err_with(|| format!("Cannot read {}", path.display()))?;
+
+    // without an explicit list, assume the capture was recorded on this machine
+    let ips = if local_ips.is_empty() {
+        datalink::interfaces()
+            .into_iter()
+            .flat_map(|interface| interface.ips)
+            .collect()
+    } else {
+        local_ips.iter().copied().map(IpNetwork::from).collect()
+    };
+    let interface = NetworkInterface {
+        name: path
+            .file_name()
+            .map_or_else(|| "pcap".into(), |name| name.to_string_lossy().into_owned()),
+        description: format!("Capture file {}", path.display()),
+        index: 0,
+        mac: None,
+        ips,
+        flags: 0,
+    };
+
+    let link_layer = frames.link_layer();
+    Ok(vec![(interface, Box::new(frames), Some(link_layer), None)])
+}
+
+fn get_live_frames(
     interface_name: Option<&str>,
-    resolve: bool,
-    dns_server: Option<Ipv4Addr>,
-) -> eyre::Result<OsInputOutput> {
+    filter: Option<&CaptureFilter>,
+    tunnels: TunnelFlows,
+) -> eyre::Result<InterfacesWithFrames> {
     // get the user's requested interface, if any
     // IDEA: allow requesting multiple interfaces
     let requested_interfaces = interface_name
@@ -144,7 +331,7 @@ pub fn get_input(
     let interfaces_with_frames_res = available_interfaces
         .into_iter()
         .map(|interface| {
-            let frames_res = get_datalink_channel(&interface);
+            let frames_res = get_datalink_channel(&interface, filter, tunnels);
             (interface, frames_res)
         })
         .collect_vec();
@@ -202,13 +389,128 @@ pub fn get_input(
     // filter out interfaces for which we failed to acquire a frame receiver
     let interfaces_with_frames = interfaces_with_frames_res
         .into_iter()
-        .filter_map(|(interface, res)| res.ok().map(|frames| (interface, frames)))
-        .collect();
+        .filter_map(|(interface, res)| {
+            let frames = res.ok()?;
+            let link_layer = get_link_layer(&interface);
+            Some((interface, frames, link_layer, None))
+        })
+        .collect_vec();
+
+    Ok(interfaces_with_frames)
+}
+
+/// Returns a scanner which opens a frame receiver for each up interface in every other network
+/// namespace, skipping the interfaces it already opened, or failed to open, before.
+///
+/// Failures only warn, since namespaces come and go, and many have nothing but a loopback.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn namespaced_frames_scanner(
+    interface_name: Option<String>,
+    filter: Option<CaptureFilter>,
+    tunnels: TunnelFlows,
+) -> NetnsScanner {
+    // the names of the interfaces scanned in each namespace
+    let mut scanned: HashMap<u64, HashSet<String>> = HashMap::new();
+    Box::new(move || {
+        let all_netns = network_namespaces();
+        let namespaces: HashSet<u64> = all_netns.iter().map(|netns| netns.id).collect();
+        scanned.retain(|id, _| namespaces.contains(id));
+
+        let mut interfaces_with_frames: InterfacesWithFrames = vec![];
+        for netns in all_netns {
+            let first_scan = !scanned.contains_key(&netns.id);
+            let scanned = scanned.entry(netns.id).or_default();
+            let (interface_name, filter) = (interface_name.as_deref(), filter.as_ref());
+            // interfaces are listed from inside the namespace, as each has its own
+            let opened = in_netns(&netns, || {
+                datalink::interfaces()
+                    .into_iter()
+                    .filter(|interface| interface_name.is_none_or(|name| interface.name == name))
+                    .filter(|interface| interface.is_up() && !interface.ips.is_empty())
+                    .filter(|interface| scanned.insert(interface.name.clone()))
+                    .map(|interface| {
+                        let frames_res = get_datalink_channel(&interface, filter, tunnels);
+                        let link_layer = get_link_layer(&interface);
+                        (interface, frames_res, link_layer)
+                    })
+                    .collect_vec()
+            });
+            let opened = match opened {
+                Ok(opened) => opened,
+                Err(err) => {
+                    // retried on every scan, but only worth a warning once
+                    if first_scan {
+                        warn!("Cannot enter network namespace {}: {err}", netns.label);
+                    }
+                    continue;
+                }
+            };
+            for (interface, frames_res, link_layer) in opened {
+                match frames_res {
+                    Ok(frames) => interfaces_with_frames.push((
+                        interface,
+                        frames,
+                        link_layer,
+                        Some(netns.clone()),
+                    )),
+                    Err(err) => warn!(
+                        "Failed to acquire a frame receiver for {} in network namespace {}: {err}",
+                        interface.name, netns.label
+                    ),
+                }
+            }
+        }
+        NetnsScan {
+            namespaces,
+            interfaces_with_frames,
+        }
+    })
+}
+
+pub fn get_input(opts: &Opt) -> eyre::Result<OsInputOutput> {
+    let capture_filter = opts
+        .filter
+        .as_deref()
+        .map(str::parse::<CaptureFilter>)
+        .transpose()
+        .wrap_err("Invalid --filter expression")?;
+
+    #[allow(unused_mut)]
+    let mut interfaces_with_frames = match opts.pcap {
+        Some(ref path) => get_capture_file_frames(path, opts.replay_speed, &opts.local_ip)?,
+        None => get_live_frames(
+            opts.interface.as_deref(),
+            capture_filter.as_ref(),
+            opts.tunnels,
+        )?,
+    };
 
-    let dns_client = if resolve {
+    // namespaces are scanned again on every refresh, as containers start and stop
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    let netns_scanner = opts.all_netns.then(|| {
+        let mut scanner =
+            namespaced_frames_scanner(opts.interface.clone(), capture_filter.clone(), opts.tunnels);
+        interfaces_with_frames.extend(scanner().interfaces_with_frames);
+        scanner
+    });
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    let netns_scanner = None;
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    let get_open_sockets: fn() -> crate::OpenSockets = if opts.all_netns {
+        get_open_sockets_all_netns
+    } else {
+        get_open_sockets
+    };
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    if opts.all_netns {
+        bail!("--all-netns is only supported on Linux");
+    }
+
+    let dns_client = if !opts.no_resolve {
         let runtime = Runtime::new()?;
         let resolver = runtime
-            .block_on(dns::Resolver::new(dns_server))
+            .block_on(dns::Resolver::new(opts.dns_server))
             .map_err(|err| {
                 eyre!("Could not initialize the DNS resolver. Are you offline?\n\nReason: {err}")
             })?;
@@ -220,12 +522,34 @@ pub fn get_input(
 
     let write_to_stdout = create_write_to_stdout();
 
+    let csv_files = opts
+        .csv_dir
+        .as_deref()
+        .map(|dir| {
+            CsvFiles::create(dir, &opts.render_opts)
+                .wrap_err_with(|| format!("Cannot create CSV files in {}", dir.display()))
+        })
+        .transpose()?;
+
+    let metrics_listener = opts
+        .metrics
+        .map(|address| {
+            TcpListener::bind(address)
+                .wrap_err_with(|| format!("Cannot serve metrics on {address}"))
+        })
+        .transpose()?;
+
     Ok(OsInputOutput {
         interfaces_with_frames,
         get_open_sockets,
         terminal_events: Box::new(TerminalEvents),
         dns_client,
         write_to_stdout,
+        csv_files,
+        metrics_listener,
+        capture_filter,
+        netns_scanner,
+        clock: Box::new(Instant::now),
     })
 }
 
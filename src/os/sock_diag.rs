@@ -0,0 +1,348 @@
+//! Lists the sockets of a network namespace with a single `NETLINK_SOCK_DIAG` query per
+//! family and protocol, instead of parsing the text tables in `/proc/net`.
+//!
+//! See `sock_diag(7)` and `linux/inet_diag.h` for the message layouts.
+
+use std::{
+    io, mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    ptr,
+};
+
+use crate::network::Protocol;
+
+/// `SOCK_DIAG_BY_FAMILY` from `linux/sock_diag.h`.
+const SOCK_DIAG_BY_FAMILY: u16 = 20;
+/// Large enough for a few hundred sockets per `recv`, like `ss` uses.
+const RECV_BUFFER_SIZE: usize = 32 * 1024;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct InetDiagSockId {
+    sport: [u8; 2],
+    dport: [u8; 2],
+    src: [u8; 16],
+    dst: [u8; 16],
+    interface: u32,
+    cookie: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct InetDiagReqV2 {
+    family: u8,
+    protocol: u8,
+    ext: u8,
+    pad: u8,
+    states: u32,
+    id: InetDiagSockId,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct InetDiagMsg {
+    family: u8,
+    state: u8,
+    timer: u8,
+    retrans: u8,
+    id: InetDiagSockId,
+    expires: u32,
+    rqueue: u32,
+    wqueue: u32,
+    uid: u32,
+    inode: u32,
+}
+
+#[repr(C)]
+struct Request {
+    header: libc::nlmsghdr,
+    body: InetDiagReqV2,
+}
+
+/// A socket as reported by the kernel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct SocketEntry {
+    pub local_address: SocketAddr,
+    pub protocol: Protocol,
+    /// The inode of the socket, through which it is found among the open files of processes.
+    pub inode: u64,
+}
+
+/// Lists the TCP and UDP sockets of the network namespace bandwhich runs in.
+///
+/// Sockets without an inode, such as those in `TIME_WAIT`, are left out.
+pub(crate) fn dump_sockets() -> io::Result<Vec<SocketEntry>> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            libc::NETLINK_SOCK_DIAG,
+        )
+    };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the descriptor was just opened, and nothing else owns it
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut entries = vec![];
+    for family in [libc::AF_INET, libc::AF_INET6] {
+        for protocol in [Protocol::Tcp, Protocol::Udp] {
+            request(&socket, family as u8, protocol)?;
+            receive(&socket, protocol, &mut entries)?;
+        }
+    }
+    Ok(entries)
+}
+
+fn request(socket: &OwnedFd, family: u8, protocol: Protocol) -> io::Result<()> {
+    let request = Request {
+        header: libc::nlmsghdr {
+            nlmsg_len: mem::size_of::<Request>() as u32,
+            nlmsg_type: SOCK_DIAG_BY_FAMILY,
+            nlmsg_flags: (libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16,
+            nlmsg_seq: 0,
+            nlmsg_pid: 0,
+        },
+        body: InetDiagReqV2 {
+            family,
+            protocol: protocol.number(),
+            // all states
+            states: u32::MAX,
+            ..Default::default()
+        },
+    };
+    // SAFETY: all zeroes is a valid `sockaddr_nl`, addressing the kernel
+    let mut kernel: libc::sockaddr_nl = unsafe { mem::zeroed() };
+    kernel.nl_family = libc::AF_NETLINK as u16;
+
+    let sent = unsafe {
+        libc::sendto(
+            socket.as_raw_fd(),
+            ptr::from_ref(&request).cast(),
+            mem::size_of::<Request>(),
+            0,
+            ptr::from_ref(&kernel).cast(),
+            mem::size_of::<libc::sockaddr_nl>() as u32,
+        )
+    };
+    if sent == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn receive(socket: &OwnedFd, protocol: Protocol, entries: &mut Vec<SocketEntry>) -> io::Result<()> {
+    // u32s to keep the messages aligned
+    let mut buffer = vec![0u32; RECV_BUFFER_SIZE / 4];
+    loop {
+        let received = unsafe {
+            libc::recv(
+                socket.as_raw_fd(),
+                buffer.as_mut_ptr().cast(),
+                RECV_BUFFER_SIZE,
+                0,
+            )
+        };
+        if received == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        // SAFETY: `recv` initialised this many bytes of the buffer
+        let bytes =
+            unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), received as usize) };
+        if parse_messages(bytes, protocol, entries)? {
+            return Ok(());
+        }
+    }
+}
+
+/// Parses the messages of one datagram into `entries`, returning whether the dump is complete.
+fn parse_messages(
+    mut bytes: &[u8],
+    protocol: Protocol,
+    entries: &mut Vec<SocketEntry>,
+) -> io::Result<bool> {
+    let header_len = mem::size_of::<libc::nlmsghdr>();
+    while bytes.len() >= header_len {
+        // SAFETY: the length was checked, and any bit pattern is a valid `nlmsghdr`
+        let header = unsafe { ptr::read_unaligned(bytes.as_ptr().cast::<libc::nlmsghdr>()) };
+        let len = header.nlmsg_len as usize;
+        if len < header_len || len > bytes.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated netlink message",
+            ));
+        }
+        let payload = &bytes[header_len..len];
+
+        match i32::from(header.nlmsg_type) {
+            libc::NLMSG_DONE => return Ok(true),
+            libc::NLMSG_ERROR => {
+                let error = payload
+                    .get(..4)
+                    .map_or(0, |code| i32::from_ne_bytes(code.try_into().unwrap()));
+                // an error code of 0 acknowledges the request
+                if error != 0 {
+                    return Err(io::Error::from_raw_os_error(-error));
+                }
+            }
+            _ if header.nlmsg_type == SOCK_DIAG_BY_FAMILY => {
+                if let Some(entry) = parse_socket(payload, protocol) {
+                    entries.push(entry);
+                }
+            }
+            _ => (),
+        }
+        // messages are padded to 4 bytes
+        bytes = bytes.get(len.next_multiple_of(4)..).unwrap_or_default();
+    }
+    Ok(false)
+}
+
+fn parse_socket(payload: &[u8], protocol: Protocol) -> Option<SocketEntry> {
+    if payload.len() < mem::size_of::<InetDiagMsg>() {
+        return None;
+    }
+    // SAFETY: the length was checked, and any bit pattern is a valid `InetDiagMsg`
+    let message = unsafe { ptr::read_unaligned(payload.as_ptr().cast::<InetDiagMsg>()) };
+    if message.inode == 0 {
+        return None;
+    }
+    let src = message.id.src;
+    let ip = match i32::from(message.family) {
+        libc::AF_INET => IpAddr::V4(Ipv4Addr::new(src[0], src[1], src[2], src[3])),
+        libc::AF_INET6 => IpAddr::V6(Ipv6Addr::from(src)),
+        _ => return None,
+    };
+    Some(SocketEntry {
+        local_address: SocketAddr::new(ip, u16::from_be_bytes(message.id.sport)),
+        protocol,
+        inode: u64::from(message.inode),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        net::{TcpListener, UdpSocket},
+    };
+
+    use super::*;
+
+    fn message(message_type: u16, payload: &[u8]) -> Vec<u8> {
+        let header = libc::nlmsghdr {
+            nlmsg_len: (mem::size_of::<libc::nlmsghdr>() + payload.len()) as u32,
+            nlmsg_type: message_type,
+            nlmsg_flags: libc::NLM_F_MULTI as u16,
+            nlmsg_seq: 0,
+            nlmsg_pid: 0,
+        };
+        // SAFETY: `nlmsghdr` has no padding
+        let header = unsafe {
+            std::slice::from_raw_parts(
+                ptr::from_ref(&header).cast::<u8>(),
+                mem::size_of::<libc::nlmsghdr>(),
+            )
+        };
+        let mut bytes = [header, payload].concat();
+        bytes.resize(bytes.len().next_multiple_of(4), 0);
+        bytes
+    }
+
+    fn socket_message(family: i32, src: [u8; 16], port: u16, inode: u32) -> Vec<u8> {
+        let diag = InetDiagMsg {
+            family: family as u8,
+            id: InetDiagSockId {
+                sport: port.to_be_bytes(),
+                src,
+                ..Default::default()
+            },
+            inode,
+            ..Default::default()
+        };
+        // SAFETY: `InetDiagMsg` has no padding
+        let payload = unsafe {
+            std::slice::from_raw_parts(
+                ptr::from_ref(&diag).cast::<u8>(),
+                mem::size_of::<InetDiagMsg>(),
+            )
+        };
+        message(SOCK_DIAG_BY_FAMILY, payload)
+    }
+
+    #[test]
+    fn parses_a_dump() {
+        let mut v4 = [0; 16];
+        v4[..4].copy_from_slice(&[10, 0, 0, 2]);
+        let v6 = Ipv6Addr::LOCALHOST.octets();
+        let bytes = [
+            socket_message(libc::AF_INET, v4, 443, 1234),
+            // in TIME_WAIT
+            socket_message(libc::AF_INET, v4, 444, 0),
+            socket_message(libc::AF_INET6, v6, 8080, 5678),
+            message(libc::NLMSG_DONE as u16, &0i32.to_ne_bytes()),
+        ]
+        .concat();
+
+        let mut entries = vec![];
+        assert!(parse_messages(&bytes, Protocol::Tcp, &mut entries).unwrap());
+        assert_eq!(
+            entries,
+            [
+                SocketEntry {
+                    local_address: "10.0.0.2:443".parse().unwrap(),
+                    protocol: Protocol::Tcp,
+                    inode: 1234,
+                },
+                SocketEntry {
+                    local_address: "[::1]:8080".parse().unwrap(),
+                    protocol: Protocol::Tcp,
+                    inode: 5678,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_errors() {
+        let bytes = message(libc::NLMSG_ERROR as u16, &(-libc::EACCES).to_ne_bytes());
+        let err = parse_messages(&bytes, Protocol::Udp, &mut vec![]).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EACCES));
+    }
+
+    #[test]
+    fn dumps_own_sockets() {
+        let inode_of = |fd: i32| {
+            let target = fs::read_link(format!("/proc/self/fd/{fd}")).unwrap();
+            let target = target.to_str().unwrap();
+            target["socket:[".len()..target.len() - 1].parse().unwrap()
+        };
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let udp = UdpSocket::bind("[::1]:0").unwrap();
+
+        let Ok(entries) = dump_sockets() else {
+            eprintln!("skipped: NETLINK_SOCK_DIAG is unavailable");
+            return;
+        };
+        if fs::read_dir("/proc/self/fd").is_err() {
+            eprintln!("skipped: /proc is unavailable");
+            return;
+        }
+        assert!(entries.contains(&SocketEntry {
+            local_address: tcp.local_addr().unwrap(),
+            protocol: Protocol::Tcp,
+            inode: inode_of(tcp.as_raw_fd()),
+        }));
+        assert!(entries.contains(&SocketEntry {
+            local_address: udp.local_addr().unwrap(),
+            protocol: Protocol::Udp,
+            inode: inode_of(udp.as_raw_fd()),
+        }));
+    }
+}
@@ -35,6 +35,7 @@ pub(crate) fn get_open_sockets() -> OpenSockets {
                             ip: tcp_si.local_addr,
                             port: tcp_si.local_port,
                             protocol: Protocol::Tcp,
+                            netns: 0,
                         },
                         proc_info,
                     );
@@ -45,6 +46,7 @@ pub(crate) fn get_open_sockets() -> OpenSockets {
                             ip: udp_si.local_addr,
                             port: udp_si.local_port,
                             protocol: Protocol::Udp,
+                            netns: 0,
                         },
                         proc_info,
                     );
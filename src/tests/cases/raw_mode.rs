@@ -9,17 +9,20 @@ use once_cell::sync::Lazy;
 use packet_builder::*;
 use pnet::{datalink::DataLinkReceiver, packet::Packet};
 use regex::Regex;
+use rstest::rstest;
 
 use crate::{
+    cli::{CountMode, OutputFormat, RenderOpts, TunnelFlows},
+    os::Container,
     start,
     tests::{
         cases::test_utils::{
-            build_tcp_packet, opts_raw, os_input_output_dns, os_input_output_stdout,
-            test_backend_factory,
+            build_ipv4_packet, build_tcp_packet, build_udp_packet, opts_raw, opts_structured,
+            os_input_output_dns, os_input_output_stdout, test_backend_factory,
         },
-        fakes::{create_fake_dns_client, NetworkFrames},
+        fakes::{create_fake_dns_client, get_open_sockets, NetworkFrames},
     },
-    Opt,
+    OpenSockets, Opt,
 };
 
 fn build_ip_tcp_packet(
@@ -41,9 +44,15 @@ fn build_ip_tcp_packet(
 
 fn format_raw_stdout(raw: &Mutex<Vec<u8>>) -> String {
     static TIMESTAMP_MATCHER: Lazy<Regex> = Lazy::new(|| Regex::new(r"<\d+>").unwrap());
+    static JSON_TIMESTAMP_MATCHER: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#""timestamp":\d+"#).unwrap());
+    static CSV_TIMESTAMP_MATCHER: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)^\d+,").unwrap());
     let stdout = raw.lock().unwrap();
-    TIMESTAMP_MATCHER
-        .replace_all(std::str::from_utf8(&stdout).unwrap(), "<TIMESTAMP_REMOVED>")
+    let stdout =
+        TIMESTAMP_MATCHER.replace_all(std::str::from_utf8(&stdout).unwrap(), "<TIMESTAMP_REMOVED>");
+    let stdout = JSON_TIMESTAMP_MATCHER.replace_all(&stdout, r#""timestamp":"TIMESTAMP_REMOVED""#);
+    CSV_TIMESTAMP_MATCHER
+        .replace_all(&stdout, "TIMESTAMP_REMOVED,")
         .into()
 }
 
@@ -81,6 +90,55 @@ fn one_packet_of_traffic() {
     assert_snapshot!(format_raw_stdout(&stdout));
 }
 
+fn get_open_sockets_in_container() -> OpenSockets {
+    let OpenSockets { sockets_to_procs } = get_open_sockets();
+    let sockets_to_procs = sockets_to_procs
+        .into_iter()
+        .map(|(socket, mut proc_info)| {
+            proc_info.container = Some(Container {
+                runtime: "docker",
+                id: format!("{}{}", proc_info.pid, "0".repeat(63)),
+                name: (proc_info.pid == 1).then(|| "web".to_string()),
+            });
+            (socket, proc_info)
+        })
+        .collect();
+    OpenSockets { sockets_to_procs }
+}
+
+#[test]
+fn traffic_in_containers() {
+    let network_frames = vec![NetworkFrames::new(vec![
+        Some(build_tcp_packet(
+            "10.0.0.2",
+            "1.1.1.1",
+            443,
+            12345,
+            b"I am a fake tcp packet",
+        )),
+        Some(build_tcp_packet(
+            "10.0.0.2",
+            "2.2.2.2",
+            4434,
+            54321,
+            b"I am another fake tcp packet",
+        )),
+    ]) as Box<dyn DataLinkReceiver>];
+    let (_, _, backend) = test_backend_factory(190, 50);
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let mut os_input = os_input_output_stdout(network_frames, 2, Some(stdout.clone()));
+    os_input.get_open_sockets = get_open_sockets_in_container;
+    let opts = Opt {
+        render_opts: RenderOpts {
+            containers: true,
+            ..Default::default()
+        },
+        ..opts_raw()
+    };
+    start(backend, os_input, opts);
+    assert_snapshot!(format_raw_stdout(&stdout));
+}
+
 #[test]
 fn bi_directional_traffic() {
     let network_frames = vec![NetworkFrames::new(vec![
@@ -107,6 +165,491 @@ fn bi_directional_traffic() {
     assert_snapshot!(format_raw_stdout(&stdout));
 }
 
+#[test]
+fn summary_after_a_bounded_run() {
+    let network_frames = vec![NetworkFrames::new(vec![
+        Some(build_tcp_packet(
+            "10.0.0.2",
+            "1.1.1.1",
+            443,
+            12345,
+            b"I am a fake tcp upload packet",
+        )),
+        Some(build_tcp_packet(
+            "1.1.1.1",
+            "10.0.0.2",
+            12345,
+            443,
+            b"I am a fake tcp download packet",
+        )),
+    ]) as Box<dyn DataLinkReceiver>];
+    let (_, _, backend) = test_backend_factory(190, 50);
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    // quit only well after the run is over
+    let os_input = os_input_output_stdout(network_frames, 5, Some(stdout.clone()));
+    let opts = Opt {
+        count: Some(2),
+        ..opts_raw()
+    };
+    start(backend, os_input, opts);
+    assert_snapshot!(format_raw_stdout(&stdout));
+}
+
+#[test]
+fn summary_stays_out_of_structured_output() {
+    let network_frames = vec![NetworkFrames::new(vec![Some(build_tcp_packet(
+        "1.1.1.1",
+        "10.0.0.2",
+        12345,
+        443,
+        b"I am a fake tcp download packet",
+    ))]) as Box<dyn DataLinkReceiver>];
+    let (_, _, backend) = test_backend_factory(190, 50);
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let os_input = os_input_output_stdout(network_frames, 5, Some(stdout.clone()));
+    let opts = Opt {
+        count: Some(2),
+        ..opts_structured(OutputFormat::Ndjson)
+    };
+    start(backend, os_input, opts);
+    let stdout = String::from_utf8(stdout.lock().unwrap().clone()).unwrap();
+    assert!(!stdout.is_empty());
+    // the summary goes to stderr, so that every line is still a JSON record
+    assert!(stdout
+        .lines()
+        .all(|line| serde_json::from_str::<serde_json::Value>(line).is_ok()));
+}
+
+#[test]
+fn remote_address_named_by_a_dns_response() {
+    use hickory_proto::{
+        op::{Message, MessageType, OpCode, Query},
+        rr::{rdata::A, Name, RData, Record, RecordType},
+    };
+
+    let name = Name::from_ascii("one.one.one.one.").unwrap();
+    let mut query = Message::new(1, MessageType::Query, OpCode::Query);
+    query.add_query(Query::query(name.clone(), RecordType::A));
+    let query = Box::leak(query.to_vec().unwrap().into_boxed_slice());
+    let mut response = Message::response(1, OpCode::Query);
+    response.add_query(Query::query(name.clone(), RecordType::A));
+    response.add_answer(Record::from_rdata(name, 60, RData::A(A::new(1, 1, 1, 1))));
+    let response = Box::leak(response.to_vec().unwrap().into_boxed_slice());
+
+    // only the responses to queries sent from this host are trusted
+    let network_frames = vec![NetworkFrames::new(vec![
+        Some(build_udp_packet("10.0.0.2", "8.8.8.8", 54321, 53, query)),
+        Some(build_udp_packet("8.8.8.8", "10.0.0.2", 53, 54321, response)),
+        Some(build_tcp_packet(
+            "1.1.1.1",
+            "10.0.0.2",
+            12345,
+            443,
+            b"I am a fake tcp download packet",
+        )),
+    ]) as Box<dyn DataLinkReceiver>];
+    let (_, _, backend) = test_backend_factory(190, 50);
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let os_input = os_input_output_stdout(network_frames, 2, Some(stdout.clone()));
+    let opts = opts_raw();
+    start(backend, os_input, opts);
+    assert_snapshot!(format_raw_stdout(&stdout));
+}
+
+#[test]
+fn connection_named_by_an_http_request() {
+    let network_frames = vec![NetworkFrames::new(vec![
+        Some(build_tcp_packet(
+            "10.0.0.2",
+            "1.1.1.1",
+            12345,
+            80,
+            b"GET / HTTP/1.1\r\nHost: example.com\r\nAccept: */*\r\n\r\n",
+        )),
+        Some(build_tcp_packet(
+            "1.1.1.1",
+            "10.0.0.2",
+            80,
+            12345,
+            b"HTTP/1.1 200 OK\r\n\r\n",
+        )),
+    ]) as Box<dyn DataLinkReceiver>];
+    let (_, _, backend) = test_backend_factory(190, 50);
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let os_input = os_input_output_stdout(network_frames, 2, Some(stdout.clone()));
+    let opts = opts_raw();
+    start(backend, os_input, opts);
+    assert_snapshot!(format_raw_stdout(&stdout));
+}
+
+#[test]
+fn dns_queries_with_show_dns() {
+    use hickory_proto::{
+        op::{Message, OpCode, Query, ResponseCode},
+        rr::{Name, RecordType},
+    };
+
+    let name = Name::from_ascii("nope.example.com.").unwrap();
+    let mut query = Message::query();
+    query.add_query(Query::query(name.clone(), RecordType::AAAA));
+    let query = Box::leak(query.to_vec().unwrap().into_boxed_slice());
+    let mut response = Message::response(1, OpCode::Query);
+    response.add_query(Query::query(name, RecordType::AAAA));
+    response.metadata.response_code = ResponseCode::NXDomain;
+    let response = Box::leak(response.to_vec().unwrap().into_boxed_slice());
+
+    let network_frames = vec![NetworkFrames::new(vec![
+        Some(build_udp_packet("10.0.0.2", "8.8.8.8", 54321, 53, query)),
+        Some(build_udp_packet("8.8.8.8", "10.0.0.2", 53, 54321, response)),
+    ]) as Box<dyn DataLinkReceiver>];
+    let (_, _, backend) = test_backend_factory(190, 50);
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let os_input = os_input_output_stdout(network_frames, 2, Some(stdout.clone()));
+    let opts = Opt {
+        show_dns: true,
+        ..opts_raw()
+    };
+    start(backend, os_input, opts);
+    assert_snapshot!(format_raw_stdout(&stdout));
+}
+
+#[test]
+fn icmp_and_other_protocols() {
+    use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+
+    let echo_request = [8, 0, 0, 0, 0, 1, 0, 1];
+    let echo_reply = [0, 0, 0, 0, 0, 1, 0, 1];
+    let network_frames = vec![NetworkFrames::new(vec![
+        Some(build_ipv4_packet(
+            "10.0.0.2",
+            "1.1.1.1",
+            IpNextHeaderProtocols::Icmp,
+            &echo_request,
+        )),
+        Some(build_ipv4_packet(
+            "1.1.1.1",
+            "10.0.0.2",
+            IpNextHeaderProtocols::Icmp,
+            &echo_reply,
+        )),
+        Some(build_ipv4_packet(
+            "10.0.0.2",
+            "3.3.3.3",
+            IpNextHeaderProtocols::Gre,
+            b"I am a fake gre packet",
+        )),
+        Some(build_ipv4_packet(
+            "3.3.3.3",
+            "10.0.0.2",
+            IpNextHeaderProtocol(253),
+            b"I am an experimental packet",
+        )),
+    ]) as Box<dyn DataLinkReceiver>];
+    let (_, _, backend) = test_backend_factory(190, 50);
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let os_input = os_input_output_stdout(network_frames, 2, Some(stdout.clone()));
+    let opts = opts_raw();
+    start(backend, os_input, opts);
+    assert_snapshot!(format_raw_stdout(&stdout));
+}
+
+#[test]
+fn vlan_tagged_traffic() {
+    // inserts 802.1Q or 802.1ad tags between the MAC addresses and the EtherType
+    let tagged = |frame: Vec<u8>, tags: &[(u16, u16)]| {
+        let mut tagged = frame[..12].to_vec();
+        for (tag_type, vlan_id) in tags {
+            tagged.extend(tag_type.to_be_bytes());
+            tagged.extend(vlan_id.to_be_bytes());
+        }
+        tagged.extend(&frame[12..]);
+        tagged
+    };
+    let network_frames = vec![NetworkFrames::new(vec![
+        Some(tagged(
+            build_tcp_packet(
+                "10.0.0.2",
+                "1.1.1.1",
+                443,
+                12345,
+                b"I am a fake tcp upload packet",
+            ),
+            &[(0x8100, 100)],
+        )),
+        Some(tagged(
+            build_tcp_packet(
+                "1.1.1.1",
+                "10.0.0.2",
+                12345,
+                443,
+                b"I am a fake tcp download packet",
+            ),
+            &[(0x8100, 100)],
+        )),
+        Some(tagged(
+            build_tcp_packet(
+                "10.0.0.2",
+                "3.3.3.3",
+                443,
+                1337,
+                b"I am a fake tcp upload packet",
+            ),
+            &[(0x88a8, 200), (0x8100, 300)],
+        )),
+    ]) as Box<dyn DataLinkReceiver>];
+    let (_, _, backend) = test_backend_factory(190, 50);
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let os_input = os_input_output_stdout(network_frames, 2, Some(stdout.clone()));
+    let opts = opts_raw();
+    start(backend, os_input, opts);
+    assert_snapshot!(format_raw_stdout(&stdout));
+}
+
+#[test]
+fn tunnelled_traffic_with_inner_flows() {
+    use pnet::packet::ip::IpNextHeaderProtocols;
+
+    let vxlan_header = [0x08, 0, 0, 0, 0, 0, 1, 0];
+    // leaked, as packets are built from static payloads
+    let vxlan = |frame: Vec<u8>| -> &'static [u8] { [&vxlan_header[..], &frame].concat().leak() };
+    let network_frames = vec![NetworkFrames::new(vec![
+        Some(build_udp_packet(
+            "10.0.0.2",
+            "3.3.3.3",
+            51234,
+            4789,
+            vxlan(build_tcp_packet(
+                "10.244.0.5",
+                "10.244.1.7",
+                443,
+                40000,
+                b"I am a fake tcp upload packet",
+            )),
+        )),
+        Some(build_udp_packet(
+            "3.3.3.3",
+            "10.0.0.2",
+            51234,
+            4789,
+            vxlan(build_tcp_packet(
+                "10.244.1.7",
+                "10.244.0.5",
+                40000,
+                443,
+                b"I am a fake tcp download packet",
+            )),
+        )),
+        Some(build_ipv4_packet(
+            "10.0.0.2",
+            "4.4.4.4",
+            IpNextHeaderProtocols::Ipv4,
+            // without the Ethernet header
+            &build_tcp_packet(
+                "192.168.0.2",
+                "192.168.1.2",
+                443,
+                1337,
+                b"I am a fake tcp upload packet",
+            )[14..],
+        )),
+    ]) as Box<dyn DataLinkReceiver>];
+    let (_, _, backend) = test_backend_factory(190, 50);
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let os_input = os_input_output_stdout(network_frames, 2, Some(stdout.clone()));
+    let opts = Opt {
+        tunnels: TunnelFlows::Inner,
+        ..opts_raw()
+    };
+    start(backend, os_input, opts);
+    assert_snapshot!(format_raw_stdout(&stdout));
+}
+
+#[test]
+fn fragmented_datagrams() {
+    use pnet::packet::ip::IpNextHeaderProtocols;
+
+    // a UDP datagram from port 443 to 12345, of 32 bytes, in fragments of 16
+    let mut datagram = vec![];
+    datagram.extend(443u16.to_be_bytes());
+    datagram.extend(12345u16.to_be_bytes());
+    datagram.extend(32u16.to_be_bytes());
+    datagram.extend([0; 2]);
+    datagram.extend(b"I am a fake udp payload!");
+    let fragment = |id: u16, offset: usize| {
+        let mut frame = build_ipv4_packet(
+            "10.0.0.2",
+            "1.1.1.1",
+            IpNextHeaderProtocols::Udp,
+            &datagram[offset..offset + 16],
+        );
+        let more_fragments = if offset == 0 { 0x2000 } else { 0 };
+        frame[18..20].copy_from_slice(&id.to_be_bytes());
+        frame[20..22].copy_from_slice(&(more_fragments | (offset as u16 / 8)).to_be_bytes());
+        frame
+    };
+    let network_frames = vec![NetworkFrames::new(vec![
+        Some(fragment(1, 0)),
+        Some(fragment(1, 16)),
+        // out of order
+        Some(fragment(2, 16)),
+        Some(fragment(2, 0)),
+    ]) as Box<dyn DataLinkReceiver>];
+    let (_, _, backend) = test_backend_factory(190, 50);
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let os_input = os_input_output_stdout(network_frames, 2, Some(stdout.clone()));
+    let opts = opts_raw();
+    start(backend, os_input, opts);
+    assert_snapshot!(format_raw_stdout(&stdout));
+}
+
+#[rstest]
+#[case::frame(CountMode::Frame, 76)]
+#[case::ip(CountMode::Ip, 62)]
+#[case::transport(CountMode::Transport, 42)]
+#[case::payload(CountMode::Payload, 22)]
+fn counts_the_bytes_of_the_count_mode(#[case] count_mode: CountMode, #[case] expected_bytes: u32) {
+    let network_frames = vec![NetworkFrames::new(vec![Some(build_tcp_packet(
+        "10.0.0.2",
+        "1.1.1.1",
+        443,
+        12345,
+        b"I am a fake tcp packet",
+    ))]) as Box<dyn DataLinkReceiver>];
+    let (_, _, backend) = test_backend_factory(190, 50);
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let os_input = os_input_output_stdout(network_frames, 2, Some(stdout.clone()));
+    let opts = Opt {
+        count_mode,
+        ..opts_raw()
+    };
+    start(backend, os_input, opts);
+    let stdout = format_raw_stdout(&stdout);
+    // the rate is averaged over the two refreshes
+    let expected = format!(
+        "=> 1.1.1.1:12345 (tcp) up/down Bps: {}/0",
+        expected_bytes / 2
+    );
+    assert!(stdout.contains(&expected), "{stdout}");
+}
+
+#[test]
+fn one_packet_of_traffic_json() {
+    let network_frames = vec![NetworkFrames::new(vec![Some(build_tcp_packet(
+        "10.0.0.2",
+        "1.1.1.1",
+        443,
+        12345,
+        b"I am a fake tcp packet",
+    ))]) as Box<dyn DataLinkReceiver>];
+    let (_, _, backend) = test_backend_factory(190, 50);
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let os_input = os_input_output_stdout(network_frames, 2, Some(stdout.clone()));
+    let opts = opts_structured(OutputFormat::Json);
+    start(backend, os_input, opts);
+    assert_snapshot!(format_raw_stdout(&stdout));
+}
+
+#[test]
+fn one_packet_of_traffic_csv() {
+    let network_frames = vec![NetworkFrames::new(vec![Some(build_tcp_packet(
+        "10.0.0.2",
+        "1.1.1.1",
+        443,
+        12345,
+        b"I am a fake tcp packet",
+    ))]) as Box<dyn DataLinkReceiver>];
+    let (_, _, backend) = test_backend_factory(190, 50);
+    let mut ips_to_hostnames = HashMap::new();
+    ips_to_hostnames.insert(
+        IpAddr::V4("1.1.1.1".parse().unwrap()),
+        String::from(r#"one, "one".one.one"#),
+    );
+    let dns_client = create_fake_dns_client(ips_to_hostnames);
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let os_input = os_input_output_dns(network_frames, 3, Some(stdout.clone()), dns_client);
+    let opts = opts_structured(OutputFormat::Csv);
+    start(backend, os_input, opts);
+    assert_snapshot!(format_raw_stdout(&stdout));
+}
+
+#[test]
+fn bi_directional_traffic_ndjson() {
+    let network_frames = vec![NetworkFrames::new(vec![
+        Some(build_tcp_packet(
+            "10.0.0.2",
+            "1.1.1.1",
+            443,
+            12345,
+            b"I am a fake tcp upload packet",
+        )),
+        Some(build_tcp_packet(
+            "1.1.1.1",
+            "10.0.0.2",
+            12345,
+            443,
+            b"I am a fake tcp download packet",
+        )),
+    ]) as Box<dyn DataLinkReceiver>];
+    let (_, _, backend) = test_backend_factory(190, 50);
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let os_input = os_input_output_stdout(network_frames, 2, Some(stdout.clone()));
+    let opts = opts_structured(OutputFormat::Ndjson);
+    start(backend, os_input, opts);
+    assert_snapshot!(format_raw_stdout(&stdout));
+}
+
+/// A chatty connection: four small uploads, each answered by a download.
+fn chatty_connection() -> Vec<Box<dyn DataLinkReceiver>> {
+    let mut frames = vec![];
+    for _ in 0..4 {
+        frames.push(Some(build_tcp_packet(
+            "10.0.0.2", "1.1.1.1", 443, 12345, b"ping",
+        )));
+        frames.push(Some(build_tcp_packet(
+            "1.1.1.1", "10.0.0.2", 12345, 443, b"pong",
+        )));
+    }
+    vec![NetworkFrames::new(frames) as Box<dyn DataLinkReceiver>]
+}
+
+#[test]
+fn packet_rates_alongside_byte_rates() {
+    let (_, _, backend) = test_backend_factory(190, 50);
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let os_input = os_input_output_stdout(chatty_connection(), 2, Some(stdout.clone()));
+    let opts = Opt {
+        render_opts: RenderOpts {
+            packets: true,
+            ..Default::default()
+        },
+        ..opts_raw()
+    };
+    start(backend, os_input, opts);
+    assert_snapshot!(format_raw_stdout(&stdout));
+}
+
+#[test]
+fn packet_rates_json() {
+    let (_, _, backend) = test_backend_factory(190, 50);
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let os_input = os_input_output_stdout(chatty_connection(), 2, Some(stdout.clone()));
+    let mut opts = opts_structured(OutputFormat::Json);
+    opts.render_opts.packets = true;
+    start(backend, os_input, opts);
+    assert_snapshot!(format_raw_stdout(&stdout));
+}
+
+#[test]
+fn packet_rates_csv() {
+    let (_, _, backend) = test_backend_factory(190, 50);
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let os_input = os_input_output_stdout(chatty_connection(), 2, Some(stdout.clone()));
+    let mut opts = opts_structured(OutputFormat::Csv);
+    opts.render_opts.packets = true;
+    start(backend, os_input, opts);
+    assert_snapshot!(format_raw_stdout(&stdout));
+}
+
 #[test]
 fn multiple_packets_of_traffic_from_different_connections() {
     let network_frames = vec![NetworkFrames::new(vec![
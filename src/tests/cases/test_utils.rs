@@ -5,15 +5,25 @@ use std::{
     io::Write,
     iter,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
 use packet_builder::*;
-use pnet::{datalink::DataLinkReceiver, packet::Packet};
+use pnet::{
+    datalink::DataLinkReceiver,
+    packet::{
+        ethernet::{EtherTypes, MutableEthernetPacket},
+        ip::IpNextHeaderProtocol,
+        ipv4::MutableIpv4Packet,
+        MutablePacket, Packet,
+    },
+};
 use pnet_base::MacAddr;
 use rstest::fixture;
 
 use crate::{
+    cli::{OutputFormat, RenderOpts},
     network::dns::Client,
     tests::fakes::{
         create_fake_dns_client, get_interfaces_with_frames, get_open_sockets, NetworkFrames,
@@ -63,6 +73,47 @@ pub fn build_tcp_packet(
     pkt.packet().to_vec()
 }
 
+pub fn build_udp_packet(
+    source_ip: &str,
+    destination_ip: &str,
+    source_port: u16,
+    destination_port: u16,
+    payload: &'static [u8],
+) -> Vec<u8> {
+    let mut pkt_buf = [0u8; 1500];
+    let pkt = packet_builder!(
+         pkt_buf,
+         ether({set_destination => MacAddr(0,0,0,0,0,0), set_source => MacAddr(0,0,0,0,0,0)}) /
+         ipv4({set_source => ipv4addr!(source_ip), set_destination => ipv4addr!(destination_ip) }) /
+         udp({set_source => source_port, set_destination => destination_port }) /
+         payload(payload)
+    );
+    pkt.packet().to_vec()
+}
+
+/// An IPv4 packet of any protocol, whose payload is not parsed further.
+pub fn build_ipv4_packet(
+    source_ip: &str,
+    destination_ip: &str,
+    protocol: IpNextHeaderProtocol,
+    payload: &[u8],
+) -> Vec<u8> {
+    let ip_length = MutableIpv4Packet::minimum_packet_size() + payload.len();
+    let mut frame = vec![0u8; MutableEthernetPacket::minimum_packet_size() + ip_length];
+    let mut ethernet = MutableEthernetPacket::new(&mut frame).unwrap();
+    ethernet.set_ethertype(EtherTypes::Ipv4);
+    let mut ip = MutableIpv4Packet::new(ethernet.payload_mut()).unwrap();
+    ip.set_version(4);
+    ip.set_header_length(5);
+    ip.set_total_length(ip_length as u16);
+    ip.set_ttl(64);
+    ip.set_next_level_protocol(protocol);
+    ip.set_source(source_ip.parse().unwrap());
+    ip.set_destination(destination_ip.parse().unwrap());
+    ip.set_payload(payload);
+    frame
+}
+
 #[fixture]
 pub fn sample_frames_short() -> Vec<Box<dyn DataLinkReceiver>> {
     vec![NetworkFrames::new(vec![
@@ -271,9 +322,24 @@ pub fn os_input_output_factory(
         terminal_events: keyboard_events,
         dns_client,
         write_to_stdout,
+        csv_files: None,
+        metrics_listener: None,
+        capture_filter: None,
+        netns_scanner: None,
+        clock: ticking_clock(),
     }
 }
 
+/// A clock which advances by the default one second interval each time it is read, so that
+/// snapshot rates do not depend on how long the test machine takes to refresh.
+pub fn ticking_clock() -> Box<dyn FnMut() -> Instant + Send> {
+    let mut now = Instant::now();
+    Box::new(move || {
+        now += Duration::from_secs(1);
+        now
+    })
+}
+
 pub fn opts_raw() -> Opt {
     Opt {
         interface: Some(String::from("interface_name")),
@@ -281,6 +347,15 @@ pub fn opts_raw() -> Opt {
         ..Default::default()
     }
 }
+pub fn opts_structured(format: OutputFormat) -> Opt {
+    Opt {
+        render_opts: RenderOpts {
+            format,
+            ..Default::default()
+        },
+        ..opts_raw()
+    }
+}
 pub fn opts_ui() -> Opt {
     Opt {
         interface: Some(String::from("interface_name")),
@@ -15,6 +15,7 @@ use crate::{
             sample_frames_short, sample_frames_sustained_long,
             sample_frames_sustained_multiple_processes, sample_frames_sustained_one_process,
             sleep_and_quit_events, sleep_resize_and_quit_events, test_backend_factory,
+            ticking_clock,
         },
         fakes::{
             create_fake_dns_client, get_interfaces_with_frames, get_open_sockets, NetworkFrames,
@@ -665,6 +666,11 @@ fn traffic_with_host_names(network_frames: Vec<Box<dyn DataLinkReceiver>>) {
         terminal_events: sleep_and_quit_events(3),
         dns_client,
         write_to_stdout,
+        csv_files: None,
+        metrics_listener: None,
+        capture_filter: None,
+        netns_scanner: None,
+        clock: ticking_clock(),
     };
     let opts = opts_ui();
     start(backend, os_input, opts);
@@ -704,6 +710,11 @@ fn truncate_long_hostnames(network_frames: Vec<Box<dyn DataLinkReceiver>>) {
         terminal_events: sleep_and_quit_events(3),
         dns_client,
         write_to_stdout,
+        csv_files: None,
+        metrics_listener: None,
+        capture_filter: None,
+        netns_scanner: None,
+        clock: ticking_clock(),
     };
     let opts = opts_ui();
     start(backend, os_input, opts);
@@ -743,6 +754,11 @@ fn no_resolve_mode(network_frames: Vec<Box<dyn DataLinkReceiver>>) {
         terminal_events: sleep_and_quit_events(3),
         dns_client,
         write_to_stdout,
+        csv_files: None,
+        metrics_listener: None,
+        capture_filter: None,
+        netns_scanner: None,
+        clock: ticking_clock(),
     };
     let opts = opts_ui();
     start(backend, os_input, opts);
@@ -776,6 +792,11 @@ fn traffic_with_winch_event() {
         terminal_events: sleep_resize_and_quit_events(2),
         dns_client,
         write_to_stdout,
+        csv_files: None,
+        metrics_listener: None,
+        capture_filter: None,
+        netns_scanner: None,
+        clock: ticking_clock(),
     };
     let opts = opts_ui();
     start(backend, os_input, opts);
@@ -17,7 +17,7 @@ use crate::{
         dns::{self, Lookup},
         Connection, Protocol,
     },
-    os::ProcessInfo,
+    os::{InterfacesWithFrames, ProcessInfo},
     OpenSockets,
 };
 
@@ -164,8 +164,12 @@ pub fn get_interfaces() -> Vec<NetworkInterface> {
 
 pub fn get_interfaces_with_frames(
     frames: impl IntoIterator<Item = Box<dyn DataLinkReceiver>>,
-) -> Vec<(NetworkInterface, Box<dyn DataLinkReceiver>)> {
-    get_interfaces().into_iter().zip_eq(frames).collect()
+) -> InterfacesWithFrames {
+    get_interfaces()
+        .into_iter()
+        .zip_eq(frames)
+        .map(|(interface, frames)| (interface, frames, None, None))
+        .collect()
 }
 
 pub fn create_fake_dns_client(ips_to_hosts: HashMap<IpAddr, String>) -> Option<dns::Client> {